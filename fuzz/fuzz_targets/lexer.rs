@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rustpl0::lexer::lex_bytes;
+
+// Any input must either lex or produce a LexError; it must never panic.
+fuzz_target!(|data: &[u8]| {
+  let _ = lex_bytes("fuzz.pl0", data);
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rustpl0::machine::machine::run_program;
+
+// Load and run arbitrary program text. The step limit keeps looping programs
+// from stalling the fuzzer; errors are expected, panics are not.
+fuzz_target!(|data: &[u8]| {
+  if let Ok(program) = std::str::from_utf8(data) {
+    let _ = run_program(program, false, true, Some(10_000), &mut &b"1\n"[..], &mut std::io::sink());
+  }
+});
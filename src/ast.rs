@@ -0,0 +1,125 @@
+use crate::machine::machine_types::Word;
+
+/// Where a construct starts in the source file.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+  pub line: i32,
+  pub column: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+  pub name: String,
+  pub span: Span,
+}
+
+/// A main program, or a module when `module` is set. A module's block has
+/// no variables and its body is `skip`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+  pub module: Option<Ident>,
+  pub imports: Vec<Ident>,
+  pub block: Block,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+  pub consts: Vec<ConstDef>,
+  pub vars: Vec<VarDecl>,
+  pub procs: Vec<ProcDecl>,
+  pub body: Stmt,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstDef {
+  pub name: Ident,
+  pub value: Word,
+}
+
+/// A variable, or an array of `length` variables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarDecl {
+  pub name: Ident,
+  pub length: Option<Word>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcKind {
+  Procedure,
+  Function,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcDecl {
+  pub kind: ProcKind,
+  pub name: Ident,
+  pub params: Vec<Ident>,
+  pub block: Block,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+  pub kind: StmtKind,
+  pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+  Assign { target: Ident, index: Option<Expr>, value: Expr },
+  Call { name: Ident, args: Vec<Expr> },
+  Begin(Vec<Stmt>),
+  If { cond: Condition, then_stmt: Box<Stmt>, else_stmt: Option<Box<Stmt>> },
+  While { cond: Condition, body: Box<Stmt> },
+  Repeat { body: Vec<Stmt>, cond: Condition },
+  /// `for var := from to|downto to do body`; `to` is evaluated once.
+  For { var: Ident, from: Expr, to: Expr, downto: bool, body: Box<Stmt> },
+  Break,
+  Continue,
+  Read { target: Ident, index: Option<Expr> },
+  Write(WriteArg),
+  /// Writes each argument in turn, numbers without the `OUTPUT:` prefix,
+  /// then a newline.
+  Writeln(Vec<WriteArg>),
+  Skip,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WriteArg {
+  Expr(Expr),
+  Str(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+  Odd(Expr),
+  Rel { left: Expr, op: RelOp, right: Expr },
+  Not(Box<Condition>),
+  And(Box<Condition>, Box<Condition>),
+  Or(Box<Condition>, Box<Condition>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelOp {
+  Eq, Neq, Lt, Leq, Gt, Geq,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+  pub kind: ExprKind,
+  pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+  Number(Word),
+  Name(Ident),
+  Call { name: Ident, args: Vec<Expr> },
+  Index { name: Ident, index: Box<Expr> },
+  Neg(Box<Expr>),
+  Binary { left: Box<Expr>, op: BinOp, right: Box<Expr> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+  Add, Sub, Mul, Div, Mod,
+}
@@ -0,0 +1,539 @@
+use crate::ast::*;
+use crate::json::Json;
+use crate::machine::machine_types::Word;
+
+/// A number, name or other value held by a node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value<'a> {
+  Int(Word),
+  Name(&'a str),
+  /// A string literal, shown quoted.
+  Str(&'a str),
+  /// An operator or keyword, such as `+` or `downto`.
+  Symbol(&'a str),
+}
+
+/// Receives the nodes of a syntax tree in source order. `start` opens a
+/// node, which holds the attributes, fields and lists that follow until its
+/// `end`.
+pub trait Visitor {
+  fn start(&mut self, kind: &str, span: Option<Span>);
+  fn attr(&mut self, name: &str, value: Value);
+  /// A node that is nothing but a value, such as a number in an expression.
+  fn leaf(&mut self, kind: &str, span: Span, value: Value);
+  /// Names the node that follows.
+  fn field(&mut self, name: &str);
+  /// Opens a field holding `len` nodes, which follow until `end_list`.
+  fn start_list(&mut self, name: &str, len: usize);
+  fn end_list(&mut self);
+  fn end(&mut self);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AstFormat {
+  /// One node per line, indented under its parent.
+  Text,
+  /// Nested objects with a `kind` and, where known, a `span`. `Program`,
+  /// `Block` and the condition nodes `Odd`, `Rel`, `Not`, `And` and `Or`
+  /// have none, since the parser does not record where they start.
+  Json,
+  /// Compact S-expressions without spans.
+  Sexp,
+}
+
+pub fn dump(program: &Program, format: AstFormat) -> String {
+  dump_with(format, |visitor| walk_program(program, visitor))
+}
+
+/// Dumps whatever `walk` visits. Several top-level nodes become a JSON array.
+pub fn dump_with(format: AstFormat, walk: impl FnOnce(&mut dyn Visitor)) -> String {
+  match format {
+    AstFormat::Text => {
+      let mut printer = TextPrinter::default();
+      walk(&mut printer);
+      printer.out
+    },
+    AstFormat::Json => {
+      let mut printer = JsonPrinter::default();
+      walk(&mut printer);
+      let json = match printer.roots.len() {
+        1 => printer.roots.remove(0),
+        _ => Json::Array(printer.roots),
+      };
+      format!("{}\n", json)
+    },
+    AstFormat::Sexp => {
+      let mut printer = SexpPrinter::default();
+      walk(&mut printer);
+      printer.out + "\n"
+    },
+  }
+}
+
+pub fn walk_program(program: &Program, v: &mut dyn Visitor) {
+  v.start("Program", None);
+  if let Some(module) = &program.module {
+    v.attr("module", Value::Name(&module.name));
+  }
+  v.start_list("imports", program.imports.len());
+  for import in &program.imports {
+    v.leaf("Import", import.span, Value::Name(&import.name));
+  }
+  v.end_list();
+  v.field("block");
+  walk_block(&program.block, v);
+  v.end();
+}
+
+pub fn walk_block(block: &Block, v: &mut dyn Visitor) {
+  v.start("Block", None);
+  v.start_list("consts", block.consts.len());
+  for c in &block.consts {
+    walk_const(c, v);
+  }
+  v.end_list();
+  v.start_list("vars", block.vars.len());
+  for var in &block.vars {
+    walk_var(var, v);
+  }
+  v.end_list();
+  v.start_list("procs", block.procs.len());
+  for proc in &block.procs {
+    walk_proc(proc, v);
+  }
+  v.end_list();
+  v.field("body");
+  walk_stmt(&block.body, v);
+  v.end();
+}
+
+pub fn walk_const(c: &ConstDef, v: &mut dyn Visitor) {
+  v.start("Const", Some(c.name.span));
+  v.attr("name", Value::Name(&c.name.name));
+  v.attr("value", Value::Int(c.value));
+  v.end();
+}
+
+pub fn walk_var(var: &VarDecl, v: &mut dyn Visitor) {
+  v.start("Var", Some(var.name.span));
+  v.attr("name", Value::Name(&var.name.name));
+  if let Some(length) = var.length {
+    v.attr("length", Value::Int(length));
+  }
+  v.end();
+}
+
+pub fn walk_proc(proc: &ProcDecl, v: &mut dyn Visitor) {
+  let kind = match proc.kind {
+    ProcKind::Procedure => "Procedure",
+    ProcKind::Function => "Function",
+  };
+  v.start(kind, Some(proc.name.span));
+  v.attr("name", Value::Name(&proc.name.name));
+  v.start_list("params", proc.params.len());
+  for param in &proc.params {
+    v.start("Param", Some(param.span));
+    v.attr("name", Value::Name(&param.name));
+    v.end();
+  }
+  v.end_list();
+  v.field("block");
+  walk_block(&proc.block, v);
+  v.end();
+}
+
+pub fn walk_stmt(stmt: &Stmt, v: &mut dyn Visitor) {
+  let span = Some(stmt.span);
+  match &stmt.kind {
+    StmtKind::Assign { target, index, value } => {
+      v.start("Assign", span);
+      v.attr("target", Value::Name(&target.name));
+      if let Some(index) = index {
+        v.field("index");
+        walk_expr(index, v);
+      }
+      v.field("value");
+      walk_expr(value, v);
+    },
+    StmtKind::Call { name, args } => {
+      v.start("Call", span);
+      v.attr("name", Value::Name(&name.name));
+      walk_exprs("args", args, v);
+    },
+    StmtKind::Begin(stmts) => {
+      v.start("Begin", span);
+      walk_stmts("statements", stmts, v);
+    },
+    StmtKind::If { cond, then_stmt, else_stmt } => {
+      v.start("If", span);
+      v.field("cond");
+      walk_condition(cond, v);
+      v.field("then");
+      walk_stmt(then_stmt, v);
+      if let Some(else_stmt) = else_stmt {
+        v.field("else");
+        walk_stmt(else_stmt, v);
+      }
+    },
+    StmtKind::While { cond, body } => {
+      v.start("While", span);
+      v.field("cond");
+      walk_condition(cond, v);
+      v.field("body");
+      walk_stmt(body, v);
+    },
+    StmtKind::Repeat { body, cond } => {
+      v.start("Repeat", span);
+      walk_stmts("body", body, v);
+      v.field("cond");
+      walk_condition(cond, v);
+    },
+    StmtKind::For { var, from, to, downto, body } => {
+      v.start("For", span);
+      v.attr("var", Value::Name(&var.name));
+      v.attr("direction", Value::Symbol(if *downto { "downto" } else { "to" }));
+      v.field("from");
+      walk_expr(from, v);
+      v.field("to");
+      walk_expr(to, v);
+      v.field("body");
+      walk_stmt(body, v);
+    },
+    StmtKind::Break => v.start("Break", span),
+    StmtKind::Continue => v.start("Continue", span),
+    StmtKind::Read { target, index } => {
+      v.start("Read", span);
+      v.attr("target", Value::Name(&target.name));
+      if let Some(index) = index {
+        v.field("index");
+        walk_expr(index, v);
+      }
+    },
+    StmtKind::Write(arg) => {
+      v.start("Write", span);
+      v.field("arg");
+      walk_write_arg(arg, stmt.span, v);
+    },
+    StmtKind::Writeln(args) => {
+      v.start("Writeln", span);
+      v.start_list("args", args.len());
+      for arg in args {
+        walk_write_arg(arg, stmt.span, v);
+      }
+      v.end_list();
+    },
+    StmtKind::Skip => v.start("Skip", span),
+  }
+  v.end();
+}
+
+fn walk_stmts(name: &str, stmts: &[Stmt], v: &mut dyn Visitor) {
+  v.start_list(name, stmts.len());
+  for stmt in stmts {
+    walk_stmt(stmt, v);
+  }
+  v.end_list();
+}
+
+/// Strings keep no span of their own, so they are given their statement's.
+fn walk_write_arg(arg: &WriteArg, span: Span, v: &mut dyn Visitor) {
+  match arg {
+    WriteArg::Expr(expr) => walk_expr(expr, v),
+    WriteArg::Str(text) => v.leaf("String", span, Value::Str(text)),
+  }
+}
+
+pub fn walk_condition(cond: &Condition, v: &mut dyn Visitor) {
+  match cond {
+    Condition::Odd(expr) => {
+      v.start("Odd", None);
+      v.field("operand");
+      walk_expr(expr, v);
+    },
+    Condition::Rel { left, op, right } => {
+      let symbol = match op {
+        RelOp::Eq => "=",
+        RelOp::Neq => "<>",
+        RelOp::Lt => "<",
+        RelOp::Leq => "<=",
+        RelOp::Gt => ">",
+        RelOp::Geq => ">=",
+      };
+      v.start("Rel", None);
+      v.attr("op", Value::Symbol(symbol));
+      v.field("left");
+      walk_expr(left, v);
+      v.field("right");
+      walk_expr(right, v);
+    },
+    Condition::Not(inner) => {
+      v.start("Not", None);
+      v.field("operand");
+      walk_condition(inner, v);
+    },
+    Condition::And(left, right) | Condition::Or(left, right) => {
+      v.start(if matches!(cond, Condition::And(..)) { "And" } else { "Or" }, None);
+      v.field("left");
+      walk_condition(left, v);
+      v.field("right");
+      walk_condition(right, v);
+    },
+  }
+  v.end();
+}
+
+pub fn walk_expr(expr: &Expr, v: &mut dyn Visitor) {
+  let span = Some(expr.span);
+  match &expr.kind {
+    ExprKind::Number(value) => return v.leaf("Number", expr.span, Value::Int(*value)),
+    ExprKind::Name(name) => return v.leaf("Name", expr.span, Value::Name(&name.name)),
+    ExprKind::Call { name, args } => {
+      v.start("Call", span);
+      v.attr("name", Value::Name(&name.name));
+      walk_exprs("args", args, v);
+    },
+    ExprKind::Index { name, index } => {
+      v.start("Index", span);
+      v.attr("name", Value::Name(&name.name));
+      v.field("index");
+      walk_expr(index, v);
+    },
+    ExprKind::Neg(operand) => {
+      v.start("Neg", span);
+      v.field("operand");
+      walk_expr(operand, v);
+    },
+    ExprKind::Binary { left, op, right } => {
+      let symbol = match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        BinOp::Mod => "%",
+      };
+      v.start("Binary", span);
+      v.attr("op", Value::Symbol(symbol));
+      v.field("left");
+      walk_expr(left, v);
+      v.field("right");
+      walk_expr(right, v);
+    },
+  }
+  v.end();
+}
+
+fn walk_exprs(name: &str, exprs: &[Expr], v: &mut dyn Visitor) {
+  v.start_list(name, exprs.len());
+  for expr in exprs {
+    walk_expr(expr, v);
+  }
+  v.end_list();
+}
+
+fn text(value: Value) -> String {
+  match value {
+    Value::Int(n) => n.to_string(),
+    Value::Name(s) | Value::Symbol(s) => s.to_string(),
+    Value::Str(s) => Json::from(s).to_string(),
+  }
+}
+
+/// `Kind attr=value [line:column]`, with fields labelled and lists given a
+/// heading line. Empty lists are left out.
+#[derive(Default)]
+struct TextPrinter {
+  out: String,
+  depth: usize,
+  label: Option<String>,
+  /// The line of the node just started, still taking attributes.
+  line: Option<(String, Option<Span>)>,
+  /// Whether each open list was given a heading.
+  lists: Vec<bool>,
+}
+
+impl TextPrinter {
+  fn flush(&mut self) {
+    if let Some((line, span)) = self.line.take() {
+      self.out += &line;
+      if let Some(span) = span {
+        self.out += &format!(" [{}:{}]", span.line, span.column);
+      }
+      self.out.push('\n');
+    }
+  }
+
+  fn open_line(&mut self, kind: &str, span: Option<Span>) {
+    self.flush();
+    let mut line = "  ".repeat(self.depth);
+    if let Some(label) = self.label.take() {
+      line += &format!("{}: ", label);
+    }
+    line += kind;
+    self.line = Some((line, span));
+  }
+}
+
+impl Visitor for TextPrinter {
+  fn start(&mut self, kind: &str, span: Option<Span>) {
+    self.open_line(kind, span);
+    self.depth += 1;
+  }
+
+  fn attr(&mut self, name: &str, value: Value) {
+    if let Some((line, _)) = &mut self.line {
+      *line += &format!(" {}={}", name, text(value));
+    }
+  }
+
+  fn leaf(&mut self, kind: &str, span: Span, value: Value) {
+    self.open_line(kind, Some(span));
+    if let Some((line, _)) = &mut self.line {
+      *line += &format!(" {}", text(value));
+    }
+    self.flush();
+  }
+
+  fn field(&mut self, name: &str) {
+    self.label = Some(name.to_string());
+  }
+
+  fn start_list(&mut self, name: &str, len: usize) {
+    self.flush();
+    if len > 0 {
+      self.out += &format!("{}{}:\n", "  ".repeat(self.depth), name);
+      self.depth += 1;
+    }
+    self.lists.push(len > 0);
+  }
+
+  fn end_list(&mut self) {
+    self.flush();
+    if self.lists.pop() == Some(true) {
+      self.depth -= 1;
+    }
+  }
+
+  fn end(&mut self) {
+    self.flush();
+    self.depth -= 1;
+  }
+}
+
+enum Frame {
+  Node { label: Option<String>, members: Vec<(String, Json)> },
+  List { name: String, items: Vec<Json> },
+}
+
+#[derive(Default)]
+struct JsonPrinter {
+  frames: Vec<Frame>,
+  label: Option<String>,
+  roots: Vec<Json>,
+}
+
+impl JsonPrinter {
+  fn add(&mut self, label: Option<String>, value: Json) {
+    match self.frames.last_mut() {
+      Some(Frame::Node { members, .. }) => members.push((label.unwrap_or_default(), value)),
+      Some(Frame::List { items, .. }) => items.push(value),
+      None => self.roots.push(value),
+    }
+  }
+
+  fn node(kind: &str, span: Option<Span>) -> Vec<(String, Json)> {
+    let mut members = vec![("kind".to_string(), Json::from(kind))];
+    if let Some(span) = span {
+      let span = Json::object([("line", Json::from(span.line as i64)), ("column", Json::from(span.column as i64))]);
+      members.push(("span".to_string(), span));
+    }
+    members
+  }
+}
+
+fn json(value: Value) -> Json {
+  match value {
+    Value::Int(n) => Json::from(n as i64),
+    Value::Name(s) | Value::Str(s) | Value::Symbol(s) => Json::from(s),
+  }
+}
+
+impl Visitor for JsonPrinter {
+  fn start(&mut self, kind: &str, span: Option<Span>) {
+    let label = self.label.take();
+    self.frames.push(Frame::Node { label, members: JsonPrinter::node(kind, span) });
+  }
+
+  fn attr(&mut self, name: &str, value: Value) {
+    if let Some(Frame::Node { members, .. }) = self.frames.last_mut() {
+      members.push((name.to_string(), json(value)));
+    }
+  }
+
+  fn leaf(&mut self, kind: &str, span: Span, value: Value) {
+    let mut members = JsonPrinter::node(kind, Some(span));
+    members.push(("value".to_string(), json(value)));
+    let label = self.label.take();
+    self.add(label, Json::Object(members));
+  }
+
+  fn field(&mut self, name: &str) {
+    self.label = Some(name.to_string());
+  }
+
+  fn start_list(&mut self, name: &str, _len: usize) {
+    self.frames.push(Frame::List { name: name.to_string(), items: Vec::new() });
+  }
+
+  fn end_list(&mut self) {
+    if let Some(Frame::List { name, items }) = self.frames.pop() {
+      self.add(Some(name), Json::Array(items));
+    }
+  }
+
+  fn end(&mut self) {
+    if let Some(Frame::Node { label, members }) = self.frames.pop() {
+      self.add(label, Json::Object(members));
+    }
+  }
+}
+
+/// `(kind attr... child...)` on one line, with leaves written as bare values
+/// and the nodes of a list written in place.
+#[derive(Default)]
+struct SexpPrinter {
+  out: String,
+}
+
+impl SexpPrinter {
+  fn separate(&mut self) {
+    if !self.out.is_empty() && !self.out.ends_with('(') {
+      self.out.push(' ');
+    }
+  }
+}
+
+impl Visitor for SexpPrinter {
+  fn start(&mut self, kind: &str, _span: Option<Span>) {
+    self.separate();
+    self.out += &format!("({}", kind.to_lowercase());
+  }
+
+  fn attr(&mut self, _name: &str, value: Value) {
+    self.separate();
+    self.out += &text(value);
+  }
+
+  fn leaf(&mut self, _kind: &str, _span: Span, value: Value) {
+    self.attr("", value);
+  }
+
+  fn field(&mut self, _name: &str) {}
+
+  fn start_list(&mut self, _name: &str, _len: usize) {}
+
+  fn end_list(&mut self) {}
+
+  fn end(&mut self) {
+    self.out.push(')');
+  }
+}
@@ -0,0 +1,34 @@
+use std::{env, io::{stdin, stdout}, path::PathBuf, process::exit};
+use rustpl0::compiler::CompileOptions;
+use rustpl0::dialect::Dialect;
+use rustpl0::lsp::server::run;
+
+const USAGE: &str = "Usage: `rustpl0-lsp [--dialect=standard|extended] [-i | --ignore-case] [--module-path=<dir>]...`";
+
+/// Speaks the Language Server Protocol over stdin and stdout. The flags set
+/// defaults that a client's `initializationOptions` can override.
+fn main() {
+    let mut options = CompileOptions::default();
+
+    for arg in env::args().skip(1) {
+      match arg.as_str() {
+        "-i" | "--ignore-case" => {
+          options.lexer.ignore_keyword_case = true;
+        },
+        _ if arg.starts_with("--module-path=") => {
+          options.module_path.push(PathBuf::from(&arg["--module-path=".len()..]));
+        },
+        _ => {
+          match arg.strip_prefix("--dialect=").and_then(Dialect::from_name) {
+            Some(d) => options.lexer.dialect = d,
+            None => {
+              eprintln!("{}", USAGE);
+              exit(2);
+            }
+          }
+        }
+      }
+    }
+
+    exit(run(options, &mut stdin().lock(), &mut stdout()));
+}
@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use crate::ast::ProcKind;
+use crate::ir::*;
+use crate::lowering::AR_SIZE;
+use crate::machine::instruction::*;
+use crate::machine::machine_types::{Address, Instruction};
+use crate::object::{Export, ExportValue, Object, Relocation};
+
+// Instructions are generated one basic block at a time. Within a block, a
+// temporary used once, by a later instruction of the same block, is not
+// stored: its instruction is kept as an expression tree and generated where
+// it is used, so the value is computed right onto the VM stack. Trees are
+// generated in the order their instructions appear, so side effects and
+// runtime errors keep their order too. Every other temporary gets a hidden
+// local after the declared variables, which is also where a value used
+// across blocks, such as a for loop's limit, is kept.
+//
+// Arguments are pushed by the caller before CAL, and a function's caller
+// pushes a result slot before the arguments; the caller pops the arguments
+// after the call, leaving the result on top of the stack.
+//
+// An array's elements follow each other among the locals. Every indexed access
+// is preceded by a CHK against the array's entry in the executable's table.
+
+enum Tree<'a> {
+  Const(i32),
+  /// A temporary kept in a hidden local.
+  Local(Address),
+  Inst(&'a Inst, Vec<Tree<'a>>),
+}
+
+impl Tree<'_> {
+  /// Whether the tree only reads variables and computes, so that it can run
+  /// earlier or later without changing anything, even which error stops the
+  /// program.
+  fn pure(&self) -> bool {
+    match self {
+      Tree::Const(_) | Tree::Local(_) => true,
+      Tree::Inst(inst, operands) => {
+        let computes = match inst {
+          Inst::Binary { op: BinOp::Div | BinOp::Mod, .. } => matches!(operands[1], Tree::Const(value) if value != 0),
+          Inst::Binary { .. } | Inst::Unary { .. } | Inst::Copy { .. } | Inst::Load { .. } => true,
+          _ => false,
+        };
+        computes && operands.iter().all(Tree::pure)
+      },
+    }
+  }
+}
+
+/// The VM code of one function, with addresses relative to its first
+/// instruction.
+#[derive(Default)]
+struct FunctionCode {
+  code: Vec<Instruction>,
+  /// Jumps to patch once every block's address is known.
+  jumps: Vec<(usize, BlockId)>,
+  /// CALs to local functions, to patch once every function's address is known.
+  calls: Vec<(usize, usize)>,
+  relocations: Vec<Relocation>,
+}
+
+struct FunctionGenerator<'a> {
+  function: &'a Function,
+  out: FunctionCode,
+  /// How many times each temporary is used, and the block of its last use.
+  uses: HashMap<Temp, (usize, BlockId)>,
+  /// The hidden locals of temporaries that are stored, by temporary.
+  locals: HashMap<Temp, Address>,
+  /// Instructions of the current block whose value is still to be generated,
+  /// in order.
+  pending: Vec<(Temp, Tree<'a>)>,
+}
+
+impl<'a> FunctionGenerator<'a> {
+  fn emit(&mut self, op: i32, m: i32) -> usize {
+    self.out.code.push(Instruction { op, m });
+    self.out.code.len() - 1
+  }
+
+  /// Whether `temp` can be generated where it is used rather than stored.
+  fn inlined(&self, temp: Temp, block: BlockId) -> bool {
+    self.uses.get(&temp) == Some(&(1, block))
+  }
+
+  fn local(&mut self, temp: Temp) -> Address {
+    let next = AR_SIZE + self.function.locals + self.locals.len() as Address;
+    *self.locals.entry(temp).or_insert(next)
+  }
+
+  /// The trees for an instruction's operands. Pending trees are taken only
+  /// when they are the last ones pending, in operand order, or in any order
+  /// if none of them can have an effect; otherwise every pending tree is
+  /// stored first, so that nothing runs out of order. Trees left pending must
+  /// be flushed before the instruction's own code.
+  fn operands(&mut self, operands: &[Operand]) -> Vec<Tree<'a>> {
+    let wanted: Vec<Temp> = operands.iter()
+      .filter_map(|o| match o {
+        Operand::Temp(t) if self.pending.iter().any(|(p, _)| p == t) => Some(*t),
+        _ => None,
+      })
+      .collect();
+    let tail = self.pending.len().saturating_sub(wanted.len());
+    let mut taken: HashMap<Temp, Tree<'a>> = HashMap::new();
+    let last: Vec<Temp> = self.pending[tail..].iter().map(|(t, _)| *t).collect();
+    let reorderable = wanted.iter().all(|t| last.contains(t)) && self.pending[tail..].iter().all(|(_, tree)| tree.pure());
+    if last == wanted || reorderable {
+      taken.extend(self.pending.drain(tail..));
+    } else {
+      self.flush();
+    }
+    operands.iter().map(|o| match o {
+      Operand::Const(value) => Tree::Const(*value),
+      Operand::Temp(t) => taken.remove(t).unwrap_or_else(|| Tree::Local(self.local(*t))),
+    }).collect()
+  }
+
+  /// Stores every pending tree in its hidden local.
+  fn flush(&mut self) {
+    for (temp, tree) in std::mem::take(&mut self.pending) {
+      self.store(temp, &tree);
+    }
+  }
+
+  fn store(&mut self, temp: Temp, tree: &Tree<'a>) {
+    let local = self.local(temp);
+    self.emit(PBP, 0);
+    self.gen_tree(tree);
+    self.emit(STO, local);
+  }
+
+  fn gen_block(&mut self, id: BlockId, block: &'a Block) {
+    for inst in &block.insts {
+      let operands = self.operands(&inst.operands());
+      let tree = Tree::Inst(inst, operands);
+      match inst.dst() {
+        Some(dst) if self.inlined(dst, id) => self.pending.push((dst, tree)),
+        Some(dst) if self.uses.contains_key(&dst) => {
+          self.flush();
+          self.store(dst, &tree);
+        },
+        Some(_) => {
+          // An unused value, such as a function's result, is still computed
+          // for its side effects.
+          self.flush();
+          self.gen_tree(&tree);
+          self.emit(POP, 0);
+        },
+        None => {
+          self.flush();
+          self.gen_tree(&tree);
+        },
+      }
+    }
+
+    let next = id + 1;
+    match &block.term {
+      Terminator::Jump(target) if *target == next => {
+        self.flush();
+      },
+      Terminator::Jump(target) => {
+        self.flush();
+        let at = self.emit(JMP, 0);
+        self.out.jumps.push((at, *target));
+      },
+      Terminator::Branch { cond, then, otherwise } => {
+        let cond = self.operands(&[*cond]).remove(0);
+        self.flush();
+        self.gen_tree(&cond);
+        if *otherwise == next {
+          let at = self.emit(JPC, 0);
+          self.out.jumps.push((at, *then));
+        } else if *then == next {
+          self.emit(JPC, 2);
+          let at = self.emit(JMP, 0);
+          self.out.jumps.push((at, *otherwise));
+        } else {
+          let at = self.emit(JPC, 0);
+          self.out.jumps.push((at, *then));
+          let at = self.emit(JMP, 0);
+          self.out.jumps.push((at, *otherwise));
+        }
+      },
+      Terminator::Return => {
+        self.flush();
+        self.emit(RTN, 0);
+      },
+      Terminator::Halt => {
+        self.flush();
+        self.emit(HLT, 0);
+      },
+    }
+  }
+
+  /// Pushes the base of the activation record `depth` static links out.
+  fn gen_base(&mut self, depth: usize) {
+    self.emit(PBP, 0);
+    for _ in 0..depth {
+      self.emit(LOD, 0);
+    }
+  }
+
+  /// Pushes the address of element `index` of an array, relative to the
+  /// array's first element, after checking it against the array's bounds.
+  fn gen_element(&mut self, array: &ArrayRef, index: &Tree<'a>) {
+    self.gen_base(array.base.depth);
+    self.gen_tree(index);
+    let at = self.emit(CHK, array.id as i32);
+    self.out.relocations.push(Relocation::Array { at });
+    self.emit(ADD, 0);
+  }
+
+  fn gen_tree(&mut self, tree: &Tree<'a>) {
+    let (inst, operands) = match tree {
+      Tree::Const(value) => {
+        self.emit(LIT, *value);
+        return;
+      },
+      Tree::Local(local) => {
+        self.emit(PBP, 0);
+        self.emit(LOD, *local);
+        return;
+      },
+      Tree::Inst(inst, operands) => (*inst, operands),
+    };
+    match inst {
+      Inst::Binary { op, .. } => {
+        self.gen_tree(&operands[0]);
+        self.gen_tree(&operands[1]);
+        let op = match op {
+          BinOp::Add => ADD,
+          BinOp::Sub => SUB,
+          BinOp::Mul => MUL,
+          BinOp::Div => DIV,
+          BinOp::Mod => MOD,
+          BinOp::Eq => EQL,
+          BinOp::Neq => NEQ,
+          BinOp::Lt => LSS,
+          BinOp::Leq => LEQ,
+          BinOp::Gt => GTR,
+          BinOp::Geq => GEQ,
+        };
+        self.emit(op, 0);
+      },
+      Inst::Unary { op: UnOp::Neg, .. } => {
+        self.gen_tree(&operands[0]);
+        self.emit(NEG, 0);
+      },
+      Inst::Unary { op: UnOp::Odd, .. } => {
+        self.gen_tree(&operands[0]);
+        self.emit(LIT, 2);
+        self.emit(MOD, 0);
+      },
+      Inst::Copy { .. } => self.gen_tree(&operands[0]),
+      Inst::Load { var, .. } => {
+        self.gen_base(var.depth);
+        self.emit(LOD, var.offset);
+      },
+      Inst::Store { var, .. } => {
+        self.gen_base(var.depth);
+        self.gen_tree(&operands[0]);
+        self.emit(STO, var.offset);
+      },
+      Inst::LoadElem { array, .. } => {
+        self.gen_element(array, &operands[0]);
+        self.emit(LIT, array.base.offset);
+        self.emit(ADD, 0);
+        self.emit(PSI, 0);
+      },
+      Inst::StoreElem { array, .. } => {
+        self.gen_element(array, &operands[0]);
+        self.gen_tree(&operands[1]);
+        self.emit(STO, array.base.offset);
+      },
+      Inst::Call { dst, callee, depth, .. } => {
+        if dst.is_some() {
+          self.emit(LIT, 0);
+        }
+        for arg in operands {
+          self.gen_tree(arg);
+        }
+        self.gen_base(*depth);
+        match callee {
+          Callee::Import(index) => {
+            let at = self.emit(CAL, *index as Address);
+            self.out.relocations.push(Relocation::Import { at });
+          },
+          Callee::Local(index) => {
+            let at = self.emit(CAL, 0);
+            self.out.calls.push((at, *index));
+          },
+        }
+        for _ in operands {
+          self.emit(POP, 0);
+        }
+      },
+      Inst::Read { .. } => {
+        self.emit(CHI, 0);
+      },
+      Inst::Write { mode, .. } => {
+        self.gen_tree(&operands[0]);
+        let mode = match mode {
+          WriteMode::Line => CHO_LINE,
+          WriteMode::Char => CHO_CHAR,
+          WriteMode::Number => CHO_NUMBER,
+        };
+        self.emit(CHO, mode);
+      },
+    }
+  }
+}
+
+/// Counts the uses of each temporary, with the block of the last one.
+fn count_uses(function: &Function) -> HashMap<Temp, (usize, BlockId)> {
+  let mut uses: HashMap<Temp, (usize, BlockId)> = HashMap::new();
+  for (id, block) in function.blocks.iter().enumerate() {
+    let mut operands: Vec<Operand> = block.insts.iter().flat_map(|inst| inst.operands()).collect();
+    if let Terminator::Branch { cond, .. } = &block.term {
+      operands.push(*cond);
+    }
+    for operand in operands {
+      if let Operand::Temp(t) = operand {
+        let entry = uses.entry(t).or_insert((0, id));
+        *entry = (entry.0 + 1, id);
+      }
+    }
+  }
+  uses
+}
+
+fn gen_function(function: &Function) -> FunctionCode {
+  let mut generator = FunctionGenerator {
+    function,
+    out: FunctionCode::default(),
+    uses: count_uses(function),
+    locals: HashMap::new(),
+    pending: Vec::new(),
+  };
+  let mut starts: Vec<usize> = Vec::new();
+  for (id, block) in function.blocks.iter().enumerate() {
+    starts.push(generator.out.code.len());
+    generator.gen_block(id, block);
+  }
+  let mut out = generator.out;
+  for (at, target) in std::mem::take(&mut out.jumps) {
+    out.code[at].m = starts[target] as i32 - at as i32;
+  }
+
+  // The main block reserves its activation record along with its locals.
+  let reserved = if function.kind == FunctionKind::Main { AR_SIZE } else { 0 };
+  let locals = reserved + function.locals + generator.locals.len() as Address;
+  if locals > 0 {
+    out.code.insert(0, Instruction { op: INC, m: locals });
+    for call in &mut out.calls {
+      call.0 += 1;
+    }
+    for relocation in &mut out.relocations {
+      let (Relocation::Code { at } | Relocation::Array { at } | Relocation::Import { at }) = relocation;
+      *at += 1;
+    }
+  }
+  out
+}
+
+/// Generates an object from a lowered program or module. A main program's
+/// block comes first, so that it starts at address 0.
+pub fn generate(unit: &Unit) -> Object {
+  let mut code: Vec<Instruction> = Vec::new();
+  let mut relocations: Vec<Relocation> = Vec::new();
+  let mut calls: Vec<(usize, usize)> = Vec::new();
+  let mut addresses: Vec<Address> = Vec::new();
+  for function in &unit.functions {
+    let start = code.len();
+    addresses.push(start as Address);
+    let out = gen_function(function);
+    code.extend(out.code);
+    calls.extend(out.calls.into_iter().map(|(at, index)| (start + at, index)));
+    relocations.extend(out.relocations.into_iter().map(|relocation| match relocation {
+      Relocation::Code { at } => Relocation::Code { at: start + at },
+      Relocation::Array { at } => Relocation::Array { at: start + at },
+      Relocation::Import { at } => Relocation::Import { at: start + at },
+    }));
+  }
+  for (at, index) in calls {
+    code[at].m = addresses[index];
+    relocations.push(Relocation::Code { at });
+  }
+  relocations.sort_by_key(|relocation| {
+    let (Relocation::Code { at } | Relocation::Array { at } | Relocation::Import { at }) = *relocation;
+    at
+  });
+
+  let exports = unit.exports.iter().map(|(name, export)| {
+    let value = match export {
+      ExportRef::Const(value) => ExportValue::Const(*value),
+      ExportRef::Function(index) => {
+        let function = &unit.functions[*index];
+        let kind = match function.kind {
+          FunctionKind::Proc(kind) => kind,
+          FunctionKind::Main => ProcKind::Procedure,
+        };
+        ExportValue::Proc { kind, arity: function.params.len(), address: addresses[*index] }
+      },
+    };
+    Export { name: name.clone(), value }
+  }).collect();
+
+  Object {
+    module: unit.module.clone(),
+    code,
+    arrays: unit.arrays.clone(),
+    exports,
+    imports: unit.imports.clone(),
+    relocations,
+  }
+}
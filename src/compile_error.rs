@@ -0,0 +1,62 @@
+use std::fmt;
+use crate::ast::Span;
+use crate::lexer::LexError;
+use crate::linter::Lint;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Severity {
+  #[default]
+  Error,
+  Warning,
+}
+
+/// An error found while compiling a PL/0 program, at a position in its
+/// source. The linter reports its warnings the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+  pub filename: String,
+  pub line: i32,
+  pub column: i32,
+  pub message: String,
+  pub severity: Severity,
+  /// The lint that reported this, if it is not a hard error.
+  pub lint: Option<Lint>,
+}
+
+impl CompileError {
+  pub fn new(filename: &str, span: Span, message: String) -> Self {
+    CompileError { filename: filename.to_string(), line: span.line, column: span.column, message, severity: Severity::Error, lint: None }
+  }
+
+  /// Formats the error like a lexer error: the message, then the offending
+  /// source line with a caret under the error column.
+  pub fn render(&self, source: &str) -> String {
+    let mut report = self.to_string();
+    if let Some(line) = source.lines().nth((self.line - 1).max(0) as usize) {
+      let trimmed = line.trim_start();
+      let indent = (line.len() - trimmed.len()) as i32;
+      report += &format!("\t{}\n\t{}^\n", trimmed, " ".repeat((self.column - 1 - indent).max(0) as usize));
+    }
+    report
+  }
+}
+
+impl fmt::Display for CompileError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "{}: At line: {}, column: {}", self.filename, self.line, self.column)?;
+    let severity = match self.severity {
+      Severity::Error => "Error",
+      Severity::Warning => "Warning",
+    };
+    match self.lint {
+      Some(lint) => writeln!(f, "{}: {} [{}]", severity, self.message, lint.id()),
+      None => writeln!(f, "{}: {}", severity, self.message),
+    }
+  }
+}
+
+impl From<LexError> for CompileError {
+  fn from(err: LexError) -> Self {
+    CompileError { filename: err.filename, line: err.line, column: err.column, message: err.message, severity: Severity::Error, lint: None }
+  }
+}
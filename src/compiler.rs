@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::ast::{Ident, Program, Span};
+use crate::codegen;
+use crate::compile_error::CompileError;
+use crate::dialect::Dialect;
+use crate::ir::Unit;
+use crate::lexer::{lex_with_options, LexerOptions};
+use crate::linker;
+use crate::lowering;
+use crate::machine::machine_types::Executable;
+use crate::object::Object;
+use crate::opt::{optimize, OptLevel};
+use crate::parser;
+use crate::resolver::{self, SymbolIndex};
+use crate::token::Token;
+
+/// Settings for compiling a program and the modules it imports.
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+  /// Used to lex every file; its dialect is also the one parsed.
+  pub lexer: LexerOptions,
+  /// Directories searched, in order, for `<module>.pl0` after the directory
+  /// of the importing file.
+  pub module_path: Vec<PathBuf>,
+  /// Applied to every file's IR before code is generated.
+  pub opt_level: OptLevel,
+  /// Lists each optimization pass run on standard error.
+  pub print_passes: bool,
+}
+
+/// Parses, checks and generates code for an already lexed program.
+pub fn compile_tokens(tokens: &[Token], dialect: Dialect) -> Result<Executable, CompileError> {
+  let options = CompileOptions { lexer: LexerOptions { dialect, ..LexerOptions::default() }, ..CompileOptions::default() };
+  compile_program(tokens, &options)
+}
+
+/// Compiles PL/0 source text into VM instructions.
+pub fn compile_source(filename: &str, source: &str, dialect: Dialect) -> Result<Executable, CompileError> {
+  let options = LexerOptions { dialect, ..LexerOptions::default() };
+  let tokens = lex_with_options(filename, source.as_bytes(), options)?;
+  compile_tokens(&tokens, dialect)
+}
+
+/// Compiles an already lexed program or module on its own. Imported modules
+/// are compiled only to learn what they export; link their own objects in
+/// afterwards.
+pub fn compile_object(tokens: &[Token], options: &CompileOptions) -> Result<Object, CompileError> {
+  let filename = tokens.first().map(|t| t.filename.clone()).unwrap_or_default();
+  let program = parser::parse(tokens, options.lexer.dialect)?;
+  let mut loader = ModuleLoader::new(options);
+  if let Some(module) = &program.module {
+    loader.importing.push(module.name.clone());
+  }
+  loader.load_imports(&program, &filename)?;
+  loader.compile(&program, &filename)
+}
+
+/// Lowers an already lexed program or module to the intermediate
+/// representation code is generated from, compiling its imports on the way.
+pub fn compile_ir(tokens: &[Token], options: &CompileOptions) -> Result<Unit, CompileError> {
+  let filename = tokens.first().map(|t| t.filename.clone()).unwrap_or_default();
+  let program = parser::parse(tokens, options.lexer.dialect)?;
+  let mut loader = ModuleLoader::new(options);
+  if let Some(module) = &program.module {
+    loader.importing.push(module.name.clone());
+  }
+  loader.load_imports(&program, &filename)?;
+  loader.lower(&program, &filename)
+}
+
+/// Compiles an already lexed main program together with every module it
+/// imports, directly or not, and links them into one executable.
+pub fn compile_program(tokens: &[Token], options: &CompileOptions) -> Result<Executable, CompileError> {
+  let filename = tokens.first().map(|t| t.filename.clone()).unwrap_or_default();
+  let program = parser::parse(tokens, options.lexer.dialect)?;
+  if let Some(module) = &program.module {
+    return Err(CompileError::new(&filename, module.span, format!("Module '{}' has no main program to run.", module.name)));
+  }
+
+  let mut loader = ModuleLoader::new(options);
+  loader.load_imports(&program, &filename)?;
+
+  let main = loader.compile(&program, &filename)?;
+  let mut objects: Vec<Object> = vec![main];
+  for name in &loader.order {
+    objects.push(loader.objects.remove(name).unwrap());
+  }
+  linker::link(&objects).map_err(|msg| CompileError::new(&filename, Span::default(), msg))
+}
+
+/// What checking one file found, for editor tooling.
+#[derive(Debug, Clone, Default)]
+pub struct Analysis {
+  /// The parsed file, unless it could not be lexed or parsed.
+  pub program: Option<Program>,
+  /// The file's declarations and name uses, up to the first semantic error.
+  pub index: SymbolIndex,
+  /// The first error found, which is always in this file.
+  pub error: Option<CompileError>,
+}
+
+/// Lexes, parses and resolves `source` as if read from `filename`, loading
+/// the modules it imports but generating no code. An error inside an
+/// imported module is reported at the import that led to it.
+pub fn analyze(filename: &str, source: &[u8], options: &CompileOptions) -> Analysis {
+  let parsed = lex_with_options(filename, source, options.lexer.clone())
+    .map_err(CompileError::from)
+    .and_then(|tokens| parser::parse(&tokens, options.lexer.dialect));
+  let program = match parsed {
+    Ok(program) => program,
+    Err(error) => return Analysis { error: Some(error), ..Analysis::default() },
+  };
+
+  let mut loader = ModuleLoader::new(options);
+  if let Some(module) = &program.module {
+    loader.importing.push(module.name.clone());
+  }
+  if let Err(error) = loader.load_imports(&program, filename) {
+    let error = match program.imports.iter().find(|i| !loader.objects.contains_key(&i.name)) {
+      // Imports load in order, so the first one missing is the one that failed.
+      Some(import) if error.filename != filename => {
+        let message = format!("Module '{}' does not compile: {}:{}:{}: {}", import.name, error.filename, error.line, error.column, error.message);
+        CompileError::new(filename, import.span, message)
+      },
+      _ => error,
+    };
+    return Analysis { program: Some(program), error: Some(error), ..Analysis::default() };
+  }
+
+  let imports: Vec<&Object> = program.imports.iter().map(|i| &loader.objects[&i.name]).collect();
+  let (index, result) = resolver::index(&program, filename, &imports);
+  Analysis { program: Some(program), index, error: result.err() }
+}
+
+struct ModuleLoader<'a> {
+  options: &'a CompileOptions,
+  objects: HashMap<String, Object>,
+  /// Compiled modules, each after the modules it imports.
+  order: Vec<String>,
+  /// Modules whose imports are being loaded, outermost first.
+  importing: Vec<String>,
+}
+
+impl<'a> ModuleLoader<'a> {
+  fn new(options: &'a CompileOptions) -> Self {
+    ModuleLoader { options, objects: HashMap::new(), order: Vec::new(), importing: Vec::new() }
+  }
+
+  /// Compiles every module `program` imports that is not compiled yet.
+  fn load_imports(&mut self, program: &Program, filename: &str) -> Result<(), CompileError> {
+    for import in &program.imports {
+      if self.objects.contains_key(&import.name) {
+        continue;
+      }
+      if let Some(start) = self.importing.iter().position(|m| *m == import.name) {
+        let cycle: Vec<&str> = self.importing[start..].iter().map(|m| m.as_str()).chain([import.name.as_str()]).collect();
+        return Err(CompileError::new(filename, import.span, format!("Import cycle: {}.", cycle.join(" -> "))));
+      }
+
+      let path = self.find_module(import, filename)?;
+      let path_name = path.to_string_lossy().to_string();
+      let source = fs::read(&path)
+        .map_err(|err| CompileError::new(filename, import.span, format!("Could not read module file '{}': {}.", path_name, err)))?;
+      let tokens = lex_with_options(&path_name, &source, self.options.lexer.clone())?;
+      let module = parser::parse(&tokens, self.options.lexer.dialect)?;
+      match &module.module {
+        Some(name) if name.name == import.name => {},
+        Some(name) => {
+          return Err(CompileError::new(&path_name, name.span, format!("This file declares module '{}', but was imported as '{}'.", name.name, import.name)));
+        },
+        None => {
+          return Err(CompileError::new(filename, import.span, format!("'{}' is a program, not a module.", path_name)));
+        },
+      }
+
+      self.importing.push(import.name.clone());
+      self.load_imports(&module, &path_name)?;
+      self.importing.pop();
+
+      let object = self.compile(&module, &path_name)?;
+      self.objects.insert(import.name.clone(), object);
+      self.order.push(import.name.clone());
+    }
+    Ok(())
+  }
+
+  fn lower(&self, program: &Program, filename: &str) -> Result<Unit, CompileError> {
+    let imports: Vec<&Object> = program.imports.iter().map(|i| &self.objects[&i.name]).collect();
+    resolver::resolve(program, filename, &imports)?;
+    let mut unit = lowering::lower(program, &imports);
+    let runs = optimize(&mut unit, self.options.opt_level);
+    if self.options.print_passes {
+      for run in runs {
+        eprintln!("{}: {}", filename, run);
+      }
+    }
+    Ok(unit)
+  }
+
+  fn compile(&self, program: &Program, filename: &str) -> Result<Object, CompileError> {
+    Ok(codegen::generate(&self.lower(program, filename)?))
+  }
+
+  /// Looks for `<module>.pl0` next to the importing file, then along the
+  /// module path.
+  fn find_module(&self, import: &Ident, filename: &str) -> Result<PathBuf, CompileError> {
+    let file = format!("{}.pl0", import.name);
+    let importer_dir = Path::new(filename).parent().map(Path::to_path_buf).unwrap_or_default();
+    std::iter::once(importer_dir)
+      .chain(self.options.module_path.iter().cloned())
+      .map(|dir| dir.join(&file))
+      .find(|path| path.is_file())
+      .ok_or_else(|| CompileError::new(filename, import.span, format!("Could not find module '{}' ('{}').", import.name, file)))
+  }
+}
@@ -0,0 +1,20 @@
+/// Which version of the PL/0 language the compiler accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+  /// The language described by the grammar in the README.
+  #[default]
+  Standard,
+  /// Standard PL/0 plus procedure parameters, functions, arrays, extra loop
+  /// constructs, boolean operators and string output.
+  Extended,
+}
+
+impl Dialect {
+  pub fn from_name(name: &str) -> Option<Dialect> {
+    match name {
+      "standard" => Some(Dialect::Standard),
+      "extended" => Some(Dialect::Extended),
+      _ => None,
+    }
+  }
+}
@@ -0,0 +1,344 @@
+use crate::ast::Span;
+use crate::compile_error::CompileError;
+use crate::lexer::{lex_with_options, LexerOptions};
+use crate::parser;
+use crate::reserved_types::KEYWORDS;
+use crate::syntax::{self, SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken};
+use crate::token::{TokenType, Trivia, TriviaKind};
+
+/// How keywords are written in formatted code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeywordCase {
+  #[default]
+  Lower,
+  /// `BEGIN ... END`; lexing the result again needs `ignore_keyword_case`.
+  Upper,
+}
+
+impl KeywordCase {
+  pub fn from_name(name: &str) -> Option<KeywordCase> {
+    match name {
+      "lower" => Some(KeywordCase::Lower),
+      "upper" => Some(KeywordCase::Upper),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatOptions {
+  /// Spaces per nesting level.
+  pub indent_width: usize,
+  pub keyword_case: KeywordCase,
+}
+
+impl Default for FormatOptions {
+  fn default() -> Self {
+    FormatOptions { indent_width: 2, keyword_case: KeywordCase::Lower }
+  }
+}
+
+/// Formats a PL/0 source file. The file must parse; comments are kept next
+/// to the code they were written beside, and at most one blank line is kept
+/// wherever the source had some. Formatting formatted code changes nothing.
+///
+/// `KeywordCase::Upper` needs `lexer.ignore_keyword_case`, since otherwise
+/// the output would not compile with the options the source was read with.
+pub fn format_source(filename: &str, source: &[u8], lexer: &LexerOptions, options: &FormatOptions) -> Result<String, CompileError> {
+  if options.keyword_case == KeywordCase::Upper && !lexer.ignore_keyword_case {
+    return Err(CompileError::new(filename, Span { line: 1, column: 1 }, "Upper case keywords only compile when keyword case is ignored!".to_string()));
+  }
+  let lexer = LexerOptions { keep_trivia: true, ..lexer.clone() };
+  let tokens = lex_with_options(filename, source, lexer.clone())?;
+  parser::parse(&tokens, lexer.dialect)?;
+
+  let mut printer = Printer {
+    options, out: String::new(), line: String::new(), indent: 0, line_indent: 0,
+    prev: None, last_token: None, after_sign: false, break_next: false, last_line: None,
+  };
+  printer.program(&syntax::parse(&tokens));
+  // The lexer reads each byte as a character; turn them back into bytes.
+  let bytes: Vec<u8> = printer.out.chars().map(|c| c as u8).collect();
+  Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Lays out the syntax tree line by line. Structure decides where lines
+/// break and how far they are indented; tokens within a line are spaced by
+/// `needs_space`.
+struct Printer<'a> {
+  options: &'a FormatOptions,
+  out: String,
+  /// The line being built, without its indentation.
+  line: String,
+  /// The indentation for lines started from now on.
+  indent: usize,
+  /// The indentation of `line`.
+  line_indent: usize,
+  /// The last token on `line`.
+  prev: Option<TokenType>,
+  /// The last token printed on any line.
+  last_token: Option<TokenType>,
+  /// Whether the last token is a sign rather than a binary operator.
+  after_sign: bool,
+  /// Set after a line comment, which nothing may follow on its line.
+  break_next: bool,
+  /// The source line that the last printed token or comment ended on.
+  last_line: Option<i32>,
+}
+
+impl Printer<'_> {
+  fn end_line(&mut self) {
+    if !self.line.is_empty() {
+      self.out += &format!("{}{}\n", " ".repeat(self.line_indent), self.line);
+      self.line.clear();
+    }
+    self.prev = None;
+    self.break_next = false;
+  }
+
+  /// Starts a new line for something from `source_line`, keeping one blank
+  /// line if the source had any before it.
+  fn start_line(&mut self, source_line: i32, indent: usize) {
+    self.end_line();
+    if self.last_line.is_some_and(|last| source_line > last + 1) && !self.out.is_empty() {
+      self.out.push('\n');
+    }
+    self.line_indent = indent;
+  }
+
+  /// Adds `text` to the current line, after a space if `space`.
+  fn append(&mut self, text: &str, source_line: i32, space: bool) {
+    if self.break_next {
+      // Continue the construct on the next line, one level deeper.
+      self.start_line(source_line, self.indent + self.options.indent_width);
+    } else if self.line.is_empty() {
+      self.start_line(source_line, self.indent);
+    } else if space {
+      self.line.push(' ');
+    }
+    self.line += text;
+  }
+
+  fn comment(&mut self, comment: &Trivia) {
+    self.append(&comment.text, comment.line, true);
+    self.prev = None;
+    self.break_next = comment.is_line_comment();
+    self.last_line = Some(comment.end_line());
+  }
+
+  fn token(&mut self, t: &SyntaxToken) {
+    self.leading(t);
+    self.token_only(t);
+  }
+
+  /// The comments before `t`, each on lines of its own if it had them.
+  fn leading(&mut self, t: &SyntaxToken) {
+    let comments: Vec<&Trivia> = comments(&t.leading).collect();
+    for comment in &comments {
+      if self.last_line.is_some_and(|last| comment.line > last) {
+        self.end_line();
+      }
+      self.comment(comment);
+    }
+    if !comments.is_empty() && self.last_line.is_some_and(|last| t.line > last) {
+      self.end_line();
+    }
+  }
+
+  /// `t` and the comments after it, but not those before it.
+  fn token_only(&mut self, t: &SyntaxToken) {
+    if t.typ != TokenType::Eofsym {
+      let space = self.prev.is_none_or(|prev| needs_space(prev, t.typ, self.after_sign));
+      let sign = matches!(t.typ, TokenType::Plussym | TokenType::Minussym) && !self.last_token.is_some_and(ends_operand);
+      self.append(&self.cased(t), t.line, space);
+      self.prev = Some(t.typ);
+      self.last_token = Some(t.typ);
+      self.after_sign = sign;
+      self.last_line = Some(t.line);
+    }
+    for comment in comments(&t.trailing) {
+      self.comment(comment);
+    }
+  }
+
+  fn cased(&self, t: &SyntaxToken) -> String {
+    if !KEYWORDS.iter().any(|&(_, typ, _)| typ == t.typ) {
+      return t.text.clone();
+    }
+    match self.options.keyword_case {
+      KeywordCase::Lower => t.text.to_ascii_lowercase(),
+      KeywordCase::Upper => t.text.to_ascii_uppercase(),
+    }
+  }
+
+  /// Prints every token under `node` on the current line.
+  fn flat(&mut self, node: &SyntaxNode) {
+    for t in node.tokens() {
+      self.token(t);
+    }
+  }
+
+  fn program(&mut self, program: &SyntaxNode) {
+    for child in &program.children {
+      match child {
+        SyntaxElement::Node(node) if node.kind == SyntaxKind::Block => self.block(node),
+        SyntaxElement::Node(node) => {
+          self.flat(node);
+          self.end_line();
+        },
+        SyntaxElement::Token(t) => self.token(t),
+      }
+    }
+    self.end_line();
+  }
+
+  fn block(&mut self, block: &SyntaxNode) {
+    for node in block.nodes() {
+      match node.kind {
+        SyntaxKind::ConstDecl => self.const_decl(node),
+        SyntaxKind::VarDecl => {
+          self.flat(node);
+          self.end_line();
+        },
+        SyntaxKind::ProcDecl => self.proc_decl(node),
+        _ => {
+          self.end_line();
+          self.stmt(node);
+        },
+      }
+    }
+  }
+
+  /// One definition per line, with their `=` signs lined up.
+  fn const_decl(&mut self, decl: &SyntaxNode) {
+    let width = decl.nodes().filter_map(|def| def.tokens().first().map(|name| name.text.len())).max().unwrap_or(0);
+    let outer = self.indent;
+    for (n, def) in decl.children.iter().enumerate() {
+      match def {
+        SyntaxElement::Token(t) => self.token(t),
+        SyntaxElement::Node(def) => {
+          // Later definitions line up under the first.
+          if n > 1 {
+            self.end_line();
+            self.indent = outer + "const ".len();
+          }
+          for (i, t) in def.tokens().into_iter().enumerate() {
+            self.token(t);
+            if i == 0 {
+              self.line += &" ".repeat(width - t.text.len());
+            }
+          }
+        },
+      }
+    }
+    self.indent = outer;
+    self.end_line();
+  }
+
+  fn proc_decl(&mut self, decl: &SyntaxNode) {
+    for child in &decl.children {
+      match child {
+        SyntaxElement::Token(t) => self.token(t),
+        SyntaxElement::Node(block) => {
+          self.end_line();
+          self.indent += self.options.indent_width;
+          self.block(block);
+          self.indent -= self.options.indent_width;
+        },
+      }
+    }
+    self.end_line();
+  }
+
+  fn stmt(&mut self, stmt: &SyntaxNode) {
+    match stmt.kind {
+      SyntaxKind::BeginStmt | SyntaxKind::RepeatStmt => self.stmt_list(stmt),
+      SyntaxKind::IfStmt | SyntaxKind::WhileStmt | SyntaxKind::ForStmt => {
+        let mut bodies = stmt.children.iter().filter(|child| matches!(child, SyntaxElement::Node(n) if n.kind != SyntaxKind::Condition));
+        let body = bodies.next();
+        for child in &stmt.children {
+          match child {
+            SyntaxElement::Token(t) if t.typ == TokenType::Elsesym => {
+              if !matches!(body, Some(SyntaxElement::Node(n)) if n.kind == SyntaxKind::BeginStmt) {
+                self.end_line();
+              }
+              self.token(t);
+            },
+            SyntaxElement::Token(t) => self.token(t),
+            SyntaxElement::Node(node) if node.kind == SyntaxKind::Condition => self.flat(node),
+            SyntaxElement::Node(node) => {
+              let after_else = !std::ptr::eq(child, body.unwrap());
+              self.body(node, after_else);
+            },
+          }
+        }
+      },
+      _ => self.flat(stmt),
+    }
+  }
+
+  /// `begin ... end` and `repeat ... until`: the statements go one per line,
+  /// one level deeper than the keywords around them.
+  fn stmt_list(&mut self, stmt: &SyntaxNode) {
+    let mut opened = false;
+    for child in &stmt.children {
+      match child {
+        SyntaxElement::Token(t) if matches!(t.typ, TokenType::Endsym | TokenType::Untilsym) => {
+          // Comments before the closing keyword belong with the statements.
+          self.leading(t);
+          self.indent -= self.options.indent_width;
+          self.end_line();
+          self.token_only(t);
+        },
+        SyntaxElement::Token(t) => {
+          self.token(t);
+          if !opened {
+            opened = true;
+            self.indent += self.options.indent_width;
+          }
+        },
+        SyntaxElement::Node(node) if node.kind == SyntaxKind::Condition => self.flat(node),
+        SyntaxElement::Node(node) => {
+          self.end_line();
+          self.stmt(node);
+        },
+      }
+    }
+  }
+
+  /// The body of `then`, `else` or `do`: a `begin` block stays on the same
+  /// line, as does the `if` of an `else if`; anything else goes on the next
+  /// line, one level deeper.
+  fn body(&mut self, body: &SyntaxNode, after_else: bool) {
+    if body.kind == SyntaxKind::BeginStmt || (after_else && body.kind == SyntaxKind::IfStmt) {
+      self.stmt(body);
+      return;
+    }
+    self.end_line();
+    self.indent += self.options.indent_width;
+    self.stmt(body);
+    self.indent -= self.options.indent_width;
+  }
+}
+
+fn comments(trivia: &[Trivia]) -> impl Iterator<Item = &Trivia> {
+  trivia.iter().filter(|t| t.kind == TriviaKind::Comment)
+}
+
+fn ends_operand(typ: TokenType) -> bool {
+  matches!(typ, TokenType::Identsym | TokenType::Numbersym | TokenType::Rparensym | TokenType::Rbracketsym)
+}
+
+/// Whether a space goes between two tokens on a line.
+fn needs_space(prev: TokenType, next: TokenType, after_sign: bool) -> bool {
+  use TokenType::*;
+  if after_sign || matches!(prev, Lparensym | Lbracketsym) {
+    return false;
+  }
+  match next {
+    Rparensym | Rbracketsym | Commasym | Semisym | Periodsym => false,
+    // A call or an index: `f(x)`, `a[i]`.
+    Lparensym | Lbracketsym => prev != Identsym,
+    _ => true,
+  }
+}
@@ -0,0 +1,293 @@
+use std::fmt;
+use crate::ast::ProcKind;
+use crate::machine::machine_types::{Address, ArrayInfo, Word};
+use crate::object::Import;
+
+// Three-address code between the syntax tree and VM instructions. Each
+// procedure is a list of basic blocks; instructions compute temporaries from
+// operands that are temporaries or numbers, and only loads and stores touch
+// variables. Every temporary is assigned by exactly one instruction.
+
+pub type Temp = u32;
+pub type BlockId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operand {
+  Temp(Temp),
+  Const(Word),
+}
+
+/// A word in an activation record: `depth` static links out from the
+/// current procedure, at `offset` from that record's base. Parameters and a
+/// function's result have negative offsets.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Var {
+  pub name: String,
+  pub depth: usize,
+  pub offset: Address,
+}
+
+/// An array's first element, and its entry in the array table that bounds
+/// checks refer to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ArrayRef {
+  pub base: Var,
+  pub id: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+  Add, Sub, Mul, Div, Mod,
+  Eq, Neq, Lt, Leq, Gt, Geq,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnOp {
+  Neg,
+  /// Nonzero when the operand is odd.
+  Odd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Callee {
+  /// An index into the unit's functions.
+  Local(usize),
+  /// An index into the unit's imports.
+  Import(usize),
+}
+
+/// How `write` prints a word; see the CHO output modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WriteMode {
+  Line,
+  Char,
+  Number,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+  Binary { dst: Temp, op: BinOp, left: Operand, right: Operand },
+  Unary { dst: Temp, op: UnOp, src: Operand },
+  Copy { dst: Temp, src: Operand },
+  Load { dst: Temp, var: Var },
+  Store { var: Var, src: Operand },
+  /// Stops the program if `index` is outside the array.
+  LoadElem { dst: Temp, array: ArrayRef, index: Operand },
+  StoreElem { array: ArrayRef, index: Operand, src: Operand },
+  /// `depth` static links out is the block declaring the callee. A function
+  /// always has a `dst`, even when its result is not used.
+  Call { dst: Option<Temp>, callee: Callee, depth: usize, args: Vec<Operand> },
+  Read { dst: Temp },
+  Write { src: Operand, mode: WriteMode },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+  Jump(BlockId),
+  /// Goes to `then` when `cond` is nonzero.
+  Branch { cond: Operand, then: BlockId, otherwise: BlockId },
+  Return,
+  Halt,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+  pub insts: Vec<Inst>,
+  pub term: Terminator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FunctionKind {
+  /// The main program's block, which ends the program.
+  Main,
+  Proc(ProcKind),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+  pub name: String,
+  pub kind: FunctionKind,
+  pub params: Vec<String>,
+  /// Words of variables, which start right after the activation record.
+  /// Inlining adds the variables of inlined procedures after the declared
+  /// ones.
+  pub locals: Address,
+  /// Words of the variables the procedure declares itself, which nested
+  /// procedures may use. Optimizations never move these.
+  pub declared: Address,
+  /// The entry is block 0.
+  pub blocks: Vec<Block>,
+  /// Temporaries are numbered from 0 up to this.
+  pub temps: Temp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportRef {
+  Const(Word),
+  Function(usize),
+}
+
+/// One program or module. A main program's block is function 0.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Unit {
+  pub module: Option<String>,
+  pub functions: Vec<Function>,
+  pub arrays: Vec<ArrayInfo>,
+  pub imports: Vec<Import>,
+  pub exports: Vec<(String, ExportRef)>,
+}
+
+impl Inst {
+  /// The temporary this instruction assigns, if any.
+  pub fn dst(&self) -> Option<Temp> {
+    match self {
+      Inst::Binary { dst, .. } | Inst::Unary { dst, .. } | Inst::Copy { dst, .. } | Inst::Load { dst, .. }
+        | Inst::LoadElem { dst, .. } | Inst::Read { dst } => Some(*dst),
+      Inst::Call { dst, .. } => *dst,
+      Inst::Store { .. } | Inst::StoreElem { .. } | Inst::Write { .. } => None,
+    }
+  }
+
+  /// The operands, in the order they are evaluated.
+  pub fn operands(&self) -> Vec<Operand> {
+    match self {
+      Inst::Binary { left, right, .. } => vec![*left, *right],
+      Inst::Unary { src, .. } | Inst::Copy { src, .. } | Inst::Store { src, .. } | Inst::Write { src, .. } => vec![*src],
+      Inst::LoadElem { index, .. } => vec![*index],
+      Inst::StoreElem { index, src, .. } => vec![*index, *src],
+      Inst::Call { args, .. } => args.clone(),
+      Inst::Load { .. } | Inst::Read { .. } => Vec::new(),
+    }
+  }
+
+  pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+    match self {
+      Inst::Binary { left, right, .. } => vec![left, right],
+      Inst::Unary { src, .. } | Inst::Copy { src, .. } | Inst::Store { src, .. } | Inst::Write { src, .. } => vec![src],
+      Inst::LoadElem { index, .. } => vec![index],
+      Inst::StoreElem { index, src, .. } => vec![index, src],
+      Inst::Call { args, .. } => args.iter_mut().collect(),
+      Inst::Load { .. } | Inst::Read { .. } => Vec::new(),
+    }
+  }
+}
+
+impl Terminator {
+  pub fn successors(&self) -> Vec<BlockId> {
+    match self {
+      Terminator::Jump(target) => vec![*target],
+      Terminator::Branch { then, otherwise, .. } => vec![*then, *otherwise],
+      Terminator::Return | Terminator::Halt => Vec::new(),
+    }
+  }
+}
+
+impl BinOp {
+  pub fn symbol(self) -> &'static str {
+    match self {
+      BinOp::Add => "+",
+      BinOp::Sub => "-",
+      BinOp::Mul => "*",
+      BinOp::Div => "/",
+      BinOp::Mod => "%",
+      BinOp::Eq => "==",
+      BinOp::Neq => "<>",
+      BinOp::Lt => "<",
+      BinOp::Leq => "<=",
+      BinOp::Gt => ">",
+      BinOp::Geq => ">=",
+    }
+  }
+}
+
+impl fmt::Display for Operand {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Operand::Temp(t) => write!(f, "t{}", t),
+      Operand::Const(value) => write!(f, "{}", value),
+    }
+  }
+}
+
+impl fmt::Display for Var {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.depth {
+      0 => write!(f, "{}", self.name),
+      depth => write!(f, "{}^{}", self.name, depth),
+    }
+  }
+}
+
+impl fmt::Display for Inst {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Inst::Binary { dst, op, left, right } => write!(f, "t{} = {} {} {}", dst, left, op.symbol(), right),
+      Inst::Unary { dst, op: UnOp::Neg, src } => write!(f, "t{} = -{}", dst, src),
+      Inst::Unary { dst, op: UnOp::Odd, src } => write!(f, "t{} = odd {}", dst, src),
+      Inst::Copy { dst, src } => write!(f, "t{} = {}", dst, src),
+      Inst::Load { dst, var } => write!(f, "t{} = {}", dst, var),
+      Inst::Store { var, src } => write!(f, "{} := {}", var, src),
+      Inst::LoadElem { dst, array, index } => write!(f, "t{} = {}[{}]", dst, array.base, index),
+      Inst::StoreElem { array, index, src } => write!(f, "{}[{}] := {}", array.base, index, src),
+      Inst::Call { dst, callee, args, .. } => {
+        if let Some(dst) = dst {
+          write!(f, "t{} = ", dst)?;
+        }
+        let callee = match callee {
+          Callee::Local(index) => format!("f{}", index),
+          Callee::Import(index) => format!("import{}", index),
+        };
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        write!(f, "call {}({})", callee, args.join(", "))
+      },
+      Inst::Read { dst } => write!(f, "t{} = read", dst),
+      Inst::Write { src, mode: WriteMode::Line } => write!(f, "write {}", src),
+      Inst::Write { src, mode: WriteMode::Number } => write!(f, "write.number {}", src),
+      Inst::Write { src, mode: WriteMode::Char } => write!(f, "write.char {}", src),
+    }
+  }
+}
+
+impl fmt::Display for Terminator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Terminator::Jump(target) => write!(f, "jump b{}", target),
+      Terminator::Branch { cond, then, otherwise } => write!(f, "branch {} b{} b{}", cond, then, otherwise),
+      Terminator::Return => write!(f, "return"),
+      Terminator::Halt => write!(f, "halt"),
+    }
+  }
+}
+
+impl fmt::Display for Unit {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Some(module) = &self.module {
+      writeln!(f, "module {}", module)?;
+    }
+    for (i, import) in self.imports.iter().enumerate() {
+      writeln!(f, "import{} = {}.{}", i, import.module, import.name)?;
+    }
+    for (name, export) in &self.exports {
+      match export {
+        ExportRef::Const(value) => writeln!(f, "export {} = {}", name, value)?,
+        ExportRef::Function(index) => writeln!(f, "export {} = f{}", name, index)?,
+      }
+    }
+    for (i, function) in self.functions.iter().enumerate() {
+      let kind = match function.kind {
+        FunctionKind::Main => "main",
+        FunctionKind::Proc(ProcKind::Procedure) => "procedure",
+        FunctionKind::Proc(ProcKind::Function) => "function",
+      };
+      writeln!(f, "\nf{}: {} {}({}) locals {}", i, kind, function.name, function.params.join(", "), function.locals)?;
+      for (id, block) in function.blocks.iter().enumerate() {
+        writeln!(f, "b{}:", id)?;
+        for inst in &block.insts {
+          writeln!(f, "  {}", inst)?;
+        }
+        writeln!(f, "  {}", block.term)?;
+      }
+    }
+    Ok(())
+  }
+}
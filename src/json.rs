@@ -0,0 +1,285 @@
+use std::fmt;
+
+/// A JSON value, enough to read and write language server messages and
+/// syntax tree dumps.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Json>),
+  /// Members in the order they were written or read.
+  Object(Vec<(String, Json)>),
+}
+
+impl Json {
+  /// Builds an object from `(key, value)` pairs.
+  pub fn object<const N: usize>(members: [(&str, Json); N]) -> Json {
+    Json::Object(members.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+  }
+
+  /// The member `key` of an object, or `Null`.
+  pub fn get(&self, key: &str) -> &Json {
+    match self {
+      Json::Object(members) => members.iter().find(|(k, _)| k == key).map_or(&Json::Null, |(_, v)| v),
+      _ => &Json::Null,
+    }
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Json::String(s) => Some(s),
+      _ => None,
+    }
+  }
+
+  pub fn as_i64(&self) -> Option<i64> {
+    match self {
+      Json::Number(n) if n.fract() == 0.0 => Some(*n as i64),
+      _ => None,
+    }
+  }
+
+  pub fn as_bool(&self) -> Option<bool> {
+    match self {
+      Json::Bool(b) => Some(*b),
+      _ => None,
+    }
+  }
+
+  pub fn as_array(&self) -> &[Json] {
+    match self {
+      Json::Array(items) => items,
+      _ => &[],
+    }
+  }
+
+  /// Parses a complete JSON text.
+  pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser { chars: text.chars().collect(), pos: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+      return Err(parser.error("end of input"));
+    }
+    Ok(value)
+  }
+}
+
+impl From<&str> for Json {
+  fn from(s: &str) -> Json {
+    Json::String(s.to_string())
+  }
+}
+
+impl From<String> for Json {
+  fn from(s: String) -> Json {
+    Json::String(s)
+  }
+}
+
+impl From<i64> for Json {
+  fn from(n: i64) -> Json {
+    Json::Number(n as f64)
+  }
+}
+
+impl From<bool> for Json {
+  fn from(b: bool) -> Json {
+    Json::Bool(b)
+  }
+}
+
+impl From<Vec<Json>> for Json {
+  fn from(items: Vec<Json>) -> Json {
+    Json::Array(items)
+  }
+}
+
+impl fmt::Display for Json {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Json::Null => write!(f, "null"),
+      Json::Bool(b) => write!(f, "{}", b),
+      Json::Number(n) => write!(f, "{}", n),
+      Json::String(s) => write_string(f, s),
+      Json::Array(items) => {
+        write!(f, "[")?;
+        for (i, item) in items.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write!(f, "{}", item)?;
+        }
+        write!(f, "]")
+      },
+      Json::Object(members) => {
+        write!(f, "{{")?;
+        for (i, (key, value)) in members.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write_string(f, key)?;
+          write!(f, ":{}", value)?;
+        }
+        write!(f, "}}")
+      },
+    }
+  }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+  write!(f, "\"")?;
+  for c in s.chars() {
+    match c {
+      '"' => write!(f, "\\\"")?,
+      '\\' => write!(f, "\\\\")?,
+      '\n' => write!(f, "\\n")?,
+      '\r' => write!(f, "\\r")?,
+      '\t' => write!(f, "\\t")?,
+      c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+      c => write!(f, "{}", c)?,
+    }
+  }
+  write!(f, "\"")
+}
+
+struct Parser {
+  chars: Vec<char>,
+  pos: usize,
+}
+
+impl Parser {
+  fn error(&self, expected: &str) -> String {
+    format!("Error: Expected {} at offset {} of the JSON text!", expected, self.pos)
+  }
+
+  fn skip_whitespace(&mut self) {
+    while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+      self.pos += 1;
+    }
+  }
+
+  fn eat(&mut self, c: char) -> bool {
+    self.skip_whitespace();
+    if self.chars.get(self.pos) == Some(&c) {
+      self.pos += 1;
+      return true;
+    }
+    false
+  }
+
+  fn eat_word(&mut self, word: &str) -> bool {
+    let end = self.pos + word.len();
+    if end <= self.chars.len() && self.chars[self.pos..end].iter().copied().eq(word.chars()) {
+      self.pos = end;
+      return true;
+    }
+    false
+  }
+
+  fn value(&mut self) -> Result<Json, String> {
+    self.skip_whitespace();
+    match self.chars.get(self.pos).copied() {
+      Some('{') => {
+        self.pos += 1;
+        let mut members = Vec::new();
+        if self.eat('}') {
+          return Ok(Json::Object(members));
+        }
+        loop {
+          self.skip_whitespace();
+          let key = self.string()?;
+          if !self.eat(':') {
+            return Err(self.error("':'"));
+          }
+          members.push((key, self.value()?));
+          if self.eat('}') {
+            return Ok(Json::Object(members));
+          }
+          if !self.eat(',') {
+            return Err(self.error("',' or '}'"));
+          }
+        }
+      },
+      Some('[') => {
+        self.pos += 1;
+        let mut items = Vec::new();
+        if self.eat(']') {
+          return Ok(Json::Array(items));
+        }
+        loop {
+          items.push(self.value()?);
+          if self.eat(']') {
+            return Ok(Json::Array(items));
+          }
+          if !self.eat(',') {
+            return Err(self.error("',' or ']'"));
+          }
+        }
+      },
+      Some('"') => Ok(Json::String(self.string()?)),
+      Some('-' | '0'..='9') => self.number(),
+      _ if self.eat_word("true") => Ok(Json::Bool(true)),
+      _ if self.eat_word("false") => Ok(Json::Bool(false)),
+      _ if self.eat_word("null") => Ok(Json::Null),
+      _ => Err(self.error("a JSON value")),
+    }
+  }
+
+  fn number(&mut self) -> Result<Json, String> {
+    let start = self.pos;
+    while self.chars.get(self.pos).is_some_and(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+      self.pos += 1;
+    }
+    let text: String = self.chars[start..self.pos].iter().collect();
+    text.parse::<f64>().map(Json::Number).map_err(|_| self.error("a number"))
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    if self.chars.get(self.pos) != Some(&'"') {
+      return Err(self.error("a string"));
+    }
+    self.pos += 1;
+    let mut s = String::new();
+    loop {
+      let c = *self.chars.get(self.pos).ok_or_else(|| self.error("'\"'"))?;
+      self.pos += 1;
+      match c {
+        '"' => return Ok(s),
+        '\\' => {
+          let escape = *self.chars.get(self.pos).ok_or_else(|| self.error("an escape"))?;
+          self.pos += 1;
+          match escape {
+            'n' => s.push('\n'),
+            'r' => s.push('\r'),
+            't' => s.push('\t'),
+            'b' => s.push('\u{8}'),
+            'f' => s.push('\u{c}'),
+            'u' => s.push(self.unicode_escape()?),
+            c => s.push(c),
+          }
+        },
+        c => s.push(c),
+      }
+    }
+  }
+
+  /// Reads the hex digits of a `\u` escape, joining surrogate pairs.
+  fn unicode_escape(&mut self) -> Result<char, String> {
+    let high = self.hex4()?;
+    if (0xD800..0xDC00).contains(&high) && self.eat_word("\\u") {
+      let low = self.hex4()?;
+      return Ok(char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)).unwrap_or('\u{FFFD}'));
+    }
+    Ok(char::from_u32(high).unwrap_or('\u{FFFD}'))
+  }
+
+  fn hex4(&mut self) -> Result<u32, String> {
+    let end = self.pos + 4;
+    let digits: String = self.chars.get(self.pos..end).ok_or_else(|| self.error("four hex digits"))?.iter().collect();
+    self.pos = end;
+    u32::from_str_radix(&digits, 16).map_err(|_| self.error("four hex digits"))
+  }
+}
@@ -1,31 +1,129 @@
-use std::{fs::File, io::{prelude::*, stderr, Write, SeekFrom, BufReader}, process::exit};
+use std::{fmt, fs::File, io::{prelude::*, stderr, Cursor, Write, SeekFrom, BufReader}, process::exit};
 use crate::lexer_log::{self};
-use crate::token::{Token, TokenType};
+use crate::token::{Token, TokenType, Trivia, TriviaKind};
 use crate::reserved_types::get_reserved_word;
+use crate::machine::machine_types::Word;
+use crate::dialect::Dialect;
 
 const END_OF_FILE: char = '\u{0003}';
 const MAX_IDENTIFIER_LENGTH: usize = 255;
-const MAX_NUMBER_LENGTH: usize = 5;
-struct Lexer {
+
+/// Settings that change what the lexer accepts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexerOptions {
+  /// Longest identifier allowed, in characters.
+  pub max_identifier_length: usize,
+  /// Smallest value a number literal (including its sign) may have.
+  pub min_number: Word,
+  /// Largest value a number literal may have.
+  pub max_number: Word,
+  /// Decides which words are reserved.
+  pub dialect: Dialect,
+  /// Which kinds of comment are recognised.
+  pub comments: CommentSyntax,
+  /// Recognises keywords in any letter case (`BEGIN`, `End`), as older
+  /// PL/0 and Pascal dialects do. Identifiers stay case sensitive.
+  pub ignore_keyword_case: bool,
+  /// Records the whitespace and comments before each token in its `trivia`,
+  /// so that tools such as the formatter can reproduce the source exactly.
+  pub keep_trivia: bool,
+}
+
+impl Default for LexerOptions {
+  fn default() -> Self {
+    LexerOptions {
+      max_identifier_length: MAX_IDENTIFIER_LENGTH,
+      min_number: Word::MIN,
+      max_number: Word::MAX,
+      dialect: Dialect::Standard,
+      comments: CommentSyntax::default(),
+      ignore_keyword_case: false,
+      keep_trivia: false,
+    }
+  }
+}
+
+/// The comment styles the lexer skips; all of them by default. Line comments
+/// run to the end of the line (or file). Block comments nest, so a `{` or `(*`
+/// inside one must be closed before the comment ends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommentSyntax {
+  /// `# ...`
+  pub hash: bool,
+  /// `// ...`
+  pub double_slash: bool,
+  /// `{ ... }`
+  pub braces: bool,
+  /// `(* ... *)`
+  pub paren_star: bool,
+}
+
+impl Default for CommentSyntax {
+  fn default() -> Self {
+    CommentSyntax { hash: true, double_slash: true, braces: true, paren_star: true }
+  }
+}
+
+/// An open block comment: the text that closes it, and where it started.
+struct CommentOpener {
+  closer: &'static str,
+  line: i32,
+  column: i32,
+  offset: u64,
+}
+
+/// A lexical error, carrying the offending source line so it can be reported
+/// with carets under the `length` characters starting at the error column.
+#[derive(Debug, PartialEq)]
+pub struct LexError {
+  pub filename: String,
+  pub line: i32,
+  pub column: i32,
+  pub length: i32,
+  pub message: String,
+  pub source_line: String,
+  pub pointer_column: i32,
+}
+
+impl fmt::Display for LexError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "{}: At line: {}, column: {}", self.filename, self.line, self.column)?;
+    writeln!(f, "Error: {}", self.message)?;
+    writeln!(f, "\t{}", self.source_line)?;
+    writeln!(f, "\t{}{}", " ".repeat(self.pointer_column.max(0) as usize), "^".repeat(self.length.max(1) as usize))
+  }
+}
+
+struct Lexer<R: Read + Seek> {
   input_file_name: String,
-  file_reader: BufReader<File>,
+  file_reader: R,
   done: bool,
   line: i32,
   column: i32,
   last_column: i32,
   debug: bool,
+  options: LexerOptions,
   token_stream: Vec<Token>,
 }
 
-impl Lexer {
-  fn initialize(filename: &String, reader: BufReader<File>, do_debug: bool) -> Self {
-    let new_lexer = Lexer { input_file_name: filename.to_string(), file_reader: reader, done: false, line: 1, column: 1, last_column: 0, debug: do_debug, token_stream: Vec::new() };
-    new_lexer
+impl<R: Read + Seek> Lexer<R> {
+  fn initialize(filename: &str, reader: R, do_debug: bool, options: LexerOptions) -> Self {
+    Lexer { input_file_name: filename.to_string(), file_reader: reader, done: false, line: 1, column: 1, last_column: 0, debug: do_debug, options, token_stream: Vec::new() }
   }
 
-  fn error(&mut self, msg: &str, c: char) {
+  fn error(&mut self, msg: &str, c: char) -> LexError {
     self.ungetchar(c);
+    self.report(msg, self.column, 1)
+  }
+
+  /// Reports an error covering everything from `start_column` up to (but not
+  /// including) the character `c` that was just read.
+  fn error_span(&mut self, msg: &str, c: char, start_column: i32) -> LexError {
+    self.ungetchar(c);
+    self.report(msg, start_column, self.column - start_column)
+  }
 
+  fn report(&mut self, msg: &str, err_column: i32, length: i32) -> LexError {
     let clean_msg: String = msg.replace("\r", " ")
                           .replace("\n", " ")
                           .replace(END_OF_FILE, " ");
@@ -33,9 +131,8 @@ impl Lexer {
     let line_number = self.line;
     let col_number = self.column;
     let mut err_line: String = String::new();
-    let mut err_pointer: String = String::new();
 
-    let _ = self.file_reader.seek(SeekFrom::Current(-(col_number) as i64));
+    let _ = self.file_reader.seek(SeekFrom::Current(-(col_number - 1) as i64));
 
     let mut c: char = self.getchar();
 
@@ -45,31 +142,27 @@ impl Lexer {
       space_count += 1;
     }
 
-    err_line.push('\t');
     while (c != '\n') && (c != '\r') && (c != END_OF_FILE) {
       err_line.push(c);
       c = self.getchar();
     }
-    err_line.push('\n');
-
-    err_pointer.push('\t');
-    for _ in 0..(col_number - space_count) {
-      err_pointer.push(' ');
-    }
-    err_pointer = err_pointer + "^\n";
 
-    let err_str: String = format!("{}: At line: {}, column: {}\nError: {}\n", self.input_file_name, line_number, col_number, clean_msg);
+    self.done = true;
 
-    let _ = stderr().flush();
-    let _ = stderr().write_all(err_str.as_bytes());
-    let _ = stderr().write_all(err_line.as_bytes());
-    let _ = stderr().write_all(err_pointer.as_bytes());
-    exit(101);
+    LexError {
+      filename: self.input_file_name.clone(),
+      line: line_number,
+      column: err_column,
+      length,
+      message: clean_msg,
+      source_line: err_line,
+      pointer_column: err_column - 1 - space_count,
+    }
   }
 
-  fn lexer_run(&mut self) -> Vec<Token> {
+  fn lexer_run(&mut self) -> Result<Vec<Token>, LexError> {
     while !self.done {
-      let t = self.lexer_next();
+      let t = self.lexer_next()?;
       self.token_stream.push(t);
     }
 
@@ -77,13 +170,13 @@ impl Lexer {
       lexer_log::create_log(self.input_file_name.clone(), &self.token_stream);
     }
 
-    self.token_stream.clone()
+    Ok(self.token_stream.clone())
   }
 
-  fn lexer_next(&mut self) -> Token {
+  fn lexer_next(&mut self) -> Result<Token, LexError> {
     let mut t: Token = Token::with_file(self.input_file_name.clone());
 
-    self.consume_ignored();
+    t.trivia = self.consume_ignored()?;
 
     t.line = self.line;
     t.column = self.column;
@@ -94,13 +187,13 @@ impl Lexer {
       t.typ = TokenType::Eofsym;
       t.text = END_OF_FILE.to_string();
       self.done = true;
-      return t;
+      return Ok(t);
     }
 
     if is_alpha(c) {
       return self.lexer_ident(c, t);
     } else if is_numeric(c) {
-      return self.lexer_num(c, t);
+      return self.lexer_num(None, c, t);
     }
 
     t.text = c.to_string();
@@ -112,17 +205,29 @@ impl Lexer {
       '=' => t.typ = TokenType::Eqsym,
       '(' => t.typ = TokenType::Lparensym,
       ')' => t.typ = TokenType::Rparensym,
+      '[' if self.options.dialect == Dialect::Extended => t.typ = TokenType::Lbracketsym,
+      ']' if self.options.dialect == Dialect::Extended => t.typ = TokenType::Rbracketsym,
+      '+' | '-' if self.sign_allowed() => {
+        let n: char = self.getchar();
+        if is_numeric(n) {
+          return self.lexer_num(Some(c), n, t);
+        }
+        self.ungetchar(n);
+        t.typ = if c == '+' { TokenType::Plussym } else { TokenType::Minussym };
+      },
       '+' => t.typ = TokenType::Plussym,
       '-' => t.typ = TokenType::Minussym,
       '*' => t.typ = TokenType::Multsym,
       '/' => t.typ = TokenType::Divsym,
+      '%' if self.options.dialect == Dialect::Extended => t.typ = TokenType::Modsym,
+      '"' if self.options.dialect == Dialect::Extended => return self.lexer_string(c, t),
       ':' => return self.lexer_assign(c, t),
       '<' => return self.get_less_than(c, t),
       '>' => return self.get_greater_than(c, t),
-      _ => self.error(format!("Illegal character: '{}', remove this character.", c).as_str(), c),
+      _ => return Err(self.error(format!("Illegal character: '{}', remove this character.", c).as_str(), c)),
     }
 
-    t 
+    Ok(t)
   }
 
   fn getchar(&mut self) -> char {
@@ -154,108 +259,264 @@ impl Lexer {
   }
 
   fn ungetchar(&mut self, c: char) {
-
-    self.column = self.last_column;
+    // Reading END_OF_FILE consumes nothing, so there is nothing to push back.
+    if c == END_OF_FILE {
+      return;
+    }
 
     if c == '\n' {
       self.line -= 1;
+      self.column = self.last_column;
+    } else {
+      self.column -= 1;
     }
 
-    if c != END_OF_FILE {
-      self.file_reader.seek(SeekFrom::Current(-1))
-      .expect("Error: Could not seek back one character!");
+    self.file_reader.seek(SeekFrom::Current(-1))
+    .expect("Error: Could not seek back one character!");
+  }
+
+  /// Reads the next character if it is `expected`.
+  fn accept_char(&mut self, expected: char) -> bool {
+    let c: char = self.getchar();
+    if c != expected {
+      self.ungetchar(c);
+      return false;
     }
+    true
   }
 
-  fn consume_ignored(&mut self) {
-    let mut c: char = self.getchar();
-    while is_space(c) || is_comment(c) {
-      if is_space(c) {
-        c = self.getchar();
-      } else if is_comment(c) {
-        self.consume_comment();
-        c = self.getchar();
+  /// If the input at `c` (already read) opens a block comment, consumes the
+  /// rest of the opener and returns what closes it.
+  fn block_comment_opener(&mut self, c: char) -> Option<&'static str> {
+    let comments = self.options.comments;
+    if comments.braces && c == '{' {
+      Some("}")
+    } else if comments.paren_star && c == '(' && self.accept_char('*') {
+      Some("*)")
+    } else {
+      None
+    }
+  }
+
+  /// Skips whitespace and comments, returning them as trivia if the
+  /// options keep it.
+  fn consume_ignored(&mut self) -> Result<Vec<Trivia>, LexError> {
+    let mut trivia: Vec<Trivia> = Vec::new();
+    loop {
+      let start_line = self.line;
+      let start_column = self.column;
+      let start_offset = self.offset();
+      let c: char = self.getchar();
+
+      let kind = if is_space(c) {
+        // A run of whitespace ends after a newline, so each piece of trivia
+        // is on one line.
+        let mut c = c;
+        while c != '\n' {
+          c = self.getchar();
+          if !is_space(c) {
+            self.ungetchar(c);
+            break;
+          }
+        }
+        TriviaKind::Whitespace
+      } else if (self.options.comments.hash && c == '#') || (self.options.comments.double_slash && c == '/' && self.accept_char('/')) {
+        self.consume_line_comment();
+        TriviaKind::Comment
+      } else if let Some(closer) = self.block_comment_opener(c) {
+        let opener = CommentOpener { closer, line: start_line, column: start_column, offset: start_offset };
+        self.consume_block_comment(opener)?;
+        TriviaKind::Comment
+      } else {
+        self.ungetchar(c);
+        return Ok(trivia);
+      };
+
+      if self.options.keep_trivia {
+        trivia.push(Trivia { kind, text: self.text_since(start_offset), line: start_line, column: start_column });
       }
     }
-    self.ungetchar(c);
   }
 
-  fn consume_comment(&mut self) {
+  /// The input from `start` up to the current position.
+  fn text_since(&mut self, start: u64) -> String {
+    let end = self.offset();
+    let mut bytes = vec![0; (end - start) as usize];
+    let _ = self.file_reader.seek(SeekFrom::Start(start));
+    let _ = self.file_reader.read_exact(&mut bytes);
+    // Each byte is one character, as in `getchar`.
+    bytes.into_iter().map(|b| b as char).collect()
+  }
+
+  /// Skips to the end of the line, leaving the line break as whitespace.
+  fn consume_line_comment(&mut self) {
     let mut c: char = self.getchar();
-    while (c != '\n') && (c != END_OF_FILE) {
+    while !matches!(c, '\n' | '\r' | END_OF_FILE) {
       c = self.getchar();
     }
+    self.ungetchar(c);
+  }
 
-    if c == END_OF_FILE {
-      self.done = true;
-      self.error("Unexpected end of file while reading comment!", c);
+  fn consume_block_comment(&mut self, opener: CommentOpener) -> Result<(), LexError> {
+    let mut open: Vec<CommentOpener> = vec![opener];
+
+    while let Some(innermost) = open.last() {
+      let closer = innermost.closer;
+      let (line, column, offset) = (self.line, self.column, self.offset());
+      let c: char = self.getchar();
+
+      if c == END_OF_FILE {
+        let unclosed = open.pop().unwrap();
+        let length = if unclosed.closer == "}" { 1 } else { 2 };
+        return Err(self.report_at(format!("Unterminated comment! Expected '{}' to close this comment before the end of the file.", unclosed.closer).as_str(), &unclosed, length));
+      }
+      if c.to_string() == closer || (closer == "*)" && c == '*' && self.accept_char(')')) {
+        open.pop();
+      } else if let Some(closer) = self.block_comment_opener(c) {
+        open.push(CommentOpener { closer, line, column, offset });
+      }
     }
+
+    Ok(())
+  }
+
+  fn offset(&mut self) -> u64 {
+    self.file_reader.stream_position().expect("Error: Could not read the position in the file!")
+  }
+
+  /// Reports an error at the start of a block comment, which may be on an
+  /// earlier line than the one being read.
+  fn report_at(&mut self, msg: &str, opener: &CommentOpener, length: i32) -> LexError {
+    let _ = self.file_reader.seek(SeekFrom::Start(opener.offset));
+    self.line = opener.line;
+    self.column = opener.column;
+    self.report(msg, opener.column, length)
   }
 
-  fn lexer_ident(&mut self, c: char, mut tok: Token) -> Token {
+  fn lexer_ident(&mut self, c: char, mut tok: Token) -> Result<Token, LexError> {
     let mut ident = String::new();
-    let mut i: u8 = 0;
+    let mut length: usize = 1;
 
     ident.push(c);
 
     let mut c: char = self.getchar();
 
     while is_alpha(c) || is_numeric(c) {
-      if i >= MAX_IDENTIFIER_LENGTH as u8 {
-        self.error(format!("Identifier starting with: '{}' is too long!", ident).as_str(), c);
+      if length >= self.options.max_identifier_length {
+        return Err(self.error(format!("Identifier starting with: '{}' is too long! Identifiers can be at most {} characters long.", ident, self.options.max_identifier_length).as_str(), c));
       }
 
       ident.push(c);
-      i += 1;
+      length += 1;
       c = self.getchar();
     }
 
     self.ungetchar(c);
     tok.text = ident;
-    tok.typ = get_reserved_word(&tok.text);
-    tok
+    tok.typ = get_reserved_word(&tok.text, self.options.dialect, self.options.ignore_keyword_case);
+    Ok(tok)
   }
 
-  fn lexer_num(&mut self, c: char, mut tok: Token) -> Token {
+  /// A sign directly in front of a number is part of the literal (the
+  /// grammar's `<sign> <number>`) unless the previous token ends an operand,
+  /// in which case the sign is a binary operator.
+  fn sign_allowed(&self) -> bool {
+    !matches!(self.token_stream.last().map(|t| t.typ),
+      Some(TokenType::Identsym) | Some(TokenType::Numbersym) | Some(TokenType::Rparensym) | Some(TokenType::Rbracketsym))
+  }
+
+  fn lexer_num(&mut self, sign: Option<char>, c: char, mut tok: Token) -> Result<Token, LexError> {
     let mut num: String = String::new();
-    let mut i: u8 = 1;
+    let mut radix: u32 = 10;
+    let mut c: char = c;
 
-    num.push(c);
-    let mut c: char = self.getchar();
+    if let Some(s) = sign {
+      num.push(s);
+    }
 
-    while is_numeric(c) {
-      if i >= MAX_NUMBER_LENGTH as u8 {
-        self.error(format!("Number starting with: '{}' is too long! Numbers can only be of value 99999 or less.", num).as_str(), c);
+    if cfg!(feature = "radix-literals") && c == '0' {
+      let prefix: char = self.getchar();
+      match prefix {
+        'x' | 'X' => radix = 16,
+        'b' | 'B' => radix = 2,
+        _ => self.ungetchar(prefix),
       }
+
+      if radix != 10 {
+        num.push(c);
+        num.push(prefix);
+        c = self.getchar();
+        if !c.is_digit(radix) {
+          return Err(self.error(format!("Expected digits after '{}'.", num).as_str(), c));
+        }
+      }
+    }
+
+    // Accumulate in a wider type and saturate, so that overflow is reported
+    // once the whole literal has been read.
+    let mut magnitude: i64 = 0;
+    while c.is_digit(radix) {
       num.push(c);
-      i += 1;
+      magnitude = magnitude.saturating_mul(radix as i64).saturating_add(c.to_digit(radix).unwrap() as i64);
       c = self.getchar();
     }
 
-    self.ungetchar(c);
-    let mut val: i32 = 0;
-    for digit in num.chars() {
-      val = val * 10 + digit.to_digit(10).unwrap() as i32;
+    let val: i64 = if sign == Some('-') { -magnitude } else { magnitude };
+    if val < self.options.min_number as i64 || val > self.options.max_number as i64 {
+      return Err(self.error_span(format!("Number '{}' is out of range! Numbers must be between {} and {}.", num, self.options.min_number, self.options.max_number).as_str(), c, tok.column));
     }
 
+    self.ungetchar(c);
     tok.text = num;
-    tok.value = val;
+    tok.value = val as Word;
     tok.typ = TokenType::Numbersym;
-    tok
+    Ok(tok)
   }
 
-  fn lexer_assign(&mut self, c: char, mut tok: Token) -> Token {
+  /// Reads a string literal. The token's text is the literal as written,
+  /// quotes and escapes included; `string_value` decodes it.
+  fn lexer_string(&mut self, quote: char, mut tok: Token) -> Result<Token, LexError> {
+    let mut text: String = quote.to_string();
+    let mut c: char = self.getchar();
+
+    while c != quote {
+      if c == '\\' {
+        text.push(c);
+        c = self.getchar();
+        if unescape(c).is_some() {
+          text.push(c);
+          c = self.getchar();
+          continue;
+        }
+        if !is_line_end(c) {
+          return Err(self.report(format!("Unknown escape sequence '\\{}' in string literal.", c).as_str(), self.column - 2, 2));
+        }
+      }
+      if is_line_end(c) {
+        return Err(self.error_span("Unterminated string literal! Strings must end with '\"' on the same line.", c, tok.column));
+      }
+      text.push(c);
+      c = self.getchar();
+    }
+
+    text.push(c);
+    tok.text = text;
+    tok.typ = TokenType::Stringsym;
+    Ok(tok)
+  }
+
+  fn lexer_assign(&mut self, c: char, mut tok: Token) -> Result<Token, LexError> {
     let s: char = self.getchar();
     if s != '=' {
-      self.error(format!("Expected '=' after colon, not '{}'.", s).as_str(), c);
+      return Err(self.error(format!("Expected '=' after colon, not '{}'.", s).as_str(), c));
     }
 
     tok.text = c.to_string() + &s.to_string();
     tok.typ = TokenType::Becomessym;
-    tok
+    Ok(tok)
   }
 
-  fn get_less_than(&mut self, c: char, mut tok: Token) -> Token {
+  fn get_less_than(&mut self, c: char, mut tok: Token) -> Result<Token, LexError> {
     let s: char = self.getchar();
 
     match s {
@@ -274,10 +535,10 @@ impl Lexer {
       },
     }
 
-    tok
+    Ok(tok)
   }
 
-  fn get_greater_than(&mut self, c: char, mut tok: Token) -> Token {
+  fn get_greater_than(&mut self, c: char, mut tok: Token) -> Result<Token, LexError> {
     let s: char = self.getchar();
 
     match s {
@@ -292,17 +553,42 @@ impl Lexer {
       },
     }
 
-    tok
+    Ok(tok)
   }
   
 }
 
-pub fn lexer_open(filename: &String, debug: bool) -> Vec<Token> {
+pub fn lexer_open(filename: &String, debug: bool, options: LexerOptions) -> Vec<Token> {
   let reader = create_reader(filename);
-  let mut lexer = Lexer::initialize(filename, reader, debug);
-  let token_stream = lexer.lexer_run();
+  let mut lexer = Lexer::initialize(filename, reader, debug, options);
+
+  match lexer.lexer_run() {
+    Ok(token_stream) => token_stream,
+    Err(err) => {
+      let _ = stderr().flush();
+      let _ = stderr().write_all(err.to_string().as_bytes());
+      exit(101);
+    },
+  }
+}
+
+/// Lexes `source` as if it had been read from `filename`, returning the token
+/// stream (ending with `Eofsym`) or the first lexical error.
+pub fn lex_source(filename: &str, source: &str) -> Result<Vec<Token>, LexError> {
+  lex_bytes(filename, source.as_bytes())
+}
 
-  token_stream
+/// Like `lex_source`, but for raw bytes that need not be valid UTF-8; each byte
+/// is read as one character, just as when lexing a file.
+pub fn lex_bytes(filename: &str, source: &[u8]) -> Result<Vec<Token>, LexError> {
+  lex_with_options(filename, source, LexerOptions::default())
+}
+
+/// Like `lex_bytes`, with the given lexer options.
+pub fn lex_with_options(filename: &str, source: &[u8], options: LexerOptions) -> Result<Vec<Token>, LexError> {
+  let reader = Cursor::new(source.to_vec());
+  let mut lexer = Lexer::initialize(filename, reader, false, options);
+  lexer.lexer_run()
 }
 
 fn create_reader(filename: &String) -> BufReader<File> {
@@ -310,29 +596,57 @@ fn create_reader(filename: &String) -> BufReader<File> {
 
   let file = File::open(file_path.clone());
 
-  let f;
-  match file {
-    Ok(_) => {f = file.unwrap();},
+  let f = match file {
+    Ok(f) => f,
     Err(err) => {
       let _ = stderr().flush();
       let err_str: String = format!("Error: Could not open file: {}; Make sure that this path does exist, and try again.\nError: {}\n", file_path, err);
       let _ = stderr().write_all(err_str.as_bytes());
       exit(101);
     },
-  }
+  };
 
   // sizeof(char) = 4
-  let reader = BufReader::with_capacity(4, f);
-  
-  reader
+  BufReader::with_capacity(4, f)
 }
 
 fn is_space(c: char) -> bool {
   (c == ' ') || (c == '\t') || (c == '\n') || (c == '\r')
 }
 
-fn is_comment(c: char) -> bool {
-  c == '#'
+/// What the escape sequence `\c` in a string literal stands for.
+fn unescape(c: char) -> Option<char> {
+  match c {
+    'n' => Some('\n'),
+    't' => Some('\t'),
+    '0' => Some('\0'),
+    '\\' => Some('\\'),
+    '"' => Some('"'),
+    _ => None,
+  }
+}
+
+/// The characters a `Stringsym` token's text stands for, without the quotes
+/// and with escape sequences replaced. The token text holds one character
+/// per source byte, so the bytes are decoded as UTF-8 here.
+pub fn string_value(text: &str) -> String {
+  let inner = text.strip_prefix('"').and_then(|t| t.strip_suffix('"')).unwrap_or(text);
+  let mut bytes: Vec<u8> = Vec::new();
+  let mut chars = inner.chars();
+  while let Some(c) = chars.next() {
+    if c == '\\' {
+      if let Some(escaped) = chars.next().and_then(unescape) {
+        bytes.push(escaped as u8);
+      }
+    } else {
+      bytes.push(c as u8);
+    }
+  }
+  String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn is_line_end(c: char) -> bool {
+  (c == '\n') || (c == '\r') || (c == END_OF_FILE)
 }
 
 fn is_alpha(c: char) -> bool {
@@ -340,5 +654,5 @@ fn is_alpha(c: char) -> bool {
 }
 
 fn is_numeric(c: char) -> bool {
-  c.is_numeric()
+  c.is_ascii_digit()
 }
\ No newline at end of file
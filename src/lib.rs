@@ -0,0 +1,26 @@
+pub mod machine;
+pub mod lexer;
+pub mod lexer_log;
+pub mod token;
+pub mod reserved_types;
+pub mod dialect;
+pub mod ast;
+pub mod json;
+pub mod ast_dump;
+pub mod parser;
+pub mod scope;
+pub mod resolver;
+pub mod ir;
+pub mod lowering;
+pub mod opt;
+pub mod codegen;
+pub mod native;
+pub mod compile_error;
+pub mod linter;
+pub mod object;
+pub mod linker;
+pub mod compiler;
+pub mod syntax;
+pub mod formatter;
+pub mod repl;
+pub mod lsp;
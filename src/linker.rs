@@ -0,0 +1,69 @@
+use std::collections::{HashMap, HashSet};
+use crate::ast::ProcKind;
+use crate::machine::machine_types::{Address, Executable};
+use crate::object::{kind_name, ExportValue, Object, Relocation};
+
+/// Combines the objects into one program: the main program first, so that
+/// it starts running at address 0, then the modules in the given order. Each
+/// relocated operand is adjusted for where its object ended up, and calls
+/// into other modules are pointed at the exported procedures, which must
+/// still have the kind and arity the caller was compiled against.
+pub fn link(objects: &[Object]) -> Result<Executable, String> {
+  let mut mains = objects.iter().filter(|o| o.module.is_none());
+  let main = mains.next().ok_or("Error: No main program to link; every object is a module!")?;
+  if mains.next().is_some() {
+    return Err("Error: More than one main program to link!".to_string());
+  }
+  let ordered: Vec<&Object> = std::iter::once(main).chain(objects.iter().filter(|o| o.module.is_some())).collect();
+
+  let mut linked = Executable::default();
+  let mut code_bases: Vec<Address> = Vec::new();
+  let mut array_bases: Vec<Address> = Vec::new();
+  let mut procs: HashMap<(&str, &str), (ProcKind, usize, Address)> = HashMap::new();
+  let mut modules: HashSet<&str> = HashSet::new();
+
+  for object in &ordered {
+    let code_base = linked.code.len() as Address;
+    if let Some(module) = &object.module {
+      if !modules.insert(module) {
+        return Err(format!("Error: Duplicate module '{}'; it is linked more than once!", module));
+      }
+      for export in &object.exports {
+        if let ExportValue::Proc { kind, arity, address } = export.value {
+          if procs.insert((module.as_str(), export.name.as_str()), (kind, arity, code_base + address)).is_some() {
+            return Err(format!("Error: Duplicate symbol '{}.{}'!", module, export.name));
+          }
+        }
+      }
+    }
+    code_bases.push(code_base);
+    array_bases.push(linked.arrays.len() as Address);
+    linked.code.extend_from_slice(&object.code);
+    linked.arrays.extend_from_slice(&object.arrays);
+  }
+
+  for (i, object) in ordered.iter().enumerate() {
+    for relocation in &object.relocations {
+      let (at, operand) = match *relocation {
+        Relocation::Code { at } => (at, object.code[at].m + code_bases[i]),
+        Relocation::Array { at } => (at, object.code[at].m + array_bases[i]),
+        Relocation::Import { at } => {
+          let Some(import) = object.imports.get(object.code[at].m as usize) else {
+            return Err(format!("Error: Relocation of address {} refers to unknown import {}!", at, object.code[at].m));
+          };
+          match procs.get(&(import.module.as_str(), import.name.as_str())) {
+            Some(&(kind, arity, address)) if (kind, arity) == (import.kind, import.arity) => (at, address),
+            Some(&(kind, arity, _)) => return Err(format!(
+              "Error: '{}.{}' is imported as a {} of arity {} but exported as a {} of arity {}!",
+              import.module, import.name, kind_name(import.kind), import.arity, kind_name(kind), arity,
+            )),
+            None => return Err(format!("Error: Unresolved symbol '{}.{}'!", import.module, import.name)),
+          }
+        },
+      };
+      linked.code[code_bases[i] as usize + at].m = operand;
+    }
+  }
+
+  Ok(linked)
+}
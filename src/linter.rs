@@ -0,0 +1,805 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use crate::ast::*;
+use crate::compile_error::{CompileError, Severity};
+use crate::compiler::Analysis;
+use crate::lexer::{lex_with_options, LexerOptions};
+use crate::machine::machine_types::Word;
+use crate::resolver::{Origin, SymbolIndex};
+use crate::scope::Symbol;
+use crate::token::{TokenType, TriviaKind};
+
+/// The file that configures the lints for PL/0 files in its directory and
+/// the directories below.
+pub const CONFIG_FILE: &str = "rustpl0-lint.conf";
+
+/// Something legal but probably wrong. Each lint has an ID, used to
+/// configure it and shown with its warnings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+  UnusedVariable,
+  UnusedConstant,
+  UnusedProcedure,
+  /// A variable read where no assignment to it has to have run before.
+  UninitializedRead,
+  /// An assignment whose value is overwritten or dropped before any read.
+  UnusedAssignment,
+  UnreachableCode,
+  /// A declaration that hides one of the same name in an outer scope.
+  ShadowedName,
+  EmptyProcedure,
+  ConstantCondition,
+}
+
+pub const LINTS: &[Lint] = &[
+  Lint::UnusedVariable, Lint::UnusedConstant, Lint::UnusedProcedure, Lint::UninitializedRead, Lint::UnusedAssignment,
+  Lint::UnreachableCode, Lint::ShadowedName, Lint::EmptyProcedure, Lint::ConstantCondition,
+];
+
+impl Lint {
+  pub fn id(self) -> &'static str {
+    match self {
+      Lint::UnusedVariable => "unused-variable",
+      Lint::UnusedConstant => "unused-constant",
+      Lint::UnusedProcedure => "unused-procedure",
+      Lint::UninitializedRead => "uninitialized-read",
+      Lint::UnusedAssignment => "unused-assignment",
+      Lint::UnreachableCode => "unreachable-code",
+      Lint::ShadowedName => "shadowed-name",
+      Lint::EmptyProcedure => "empty-procedure",
+      Lint::ConstantCondition => "constant-condition",
+    }
+  }
+
+  pub fn from_id(id: &str) -> Option<Lint> {
+    LINTS.iter().copied().find(|lint| lint.id() == id)
+  }
+}
+
+/// What to do with a lint's findings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+  Allow,
+  Warn,
+  /// Report them as errors.
+  Deny,
+}
+
+impl Level {
+  pub fn from_name(name: &str) -> Option<Level> {
+    match name {
+      "allow" => Some(Level::Allow),
+      "warn" => Some(Level::Warn),
+      "deny" => Some(Level::Deny),
+      _ => None,
+    }
+  }
+}
+
+/// The level of each lint; those not set warn.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LintConfig {
+  levels: HashMap<Lint, Level>,
+}
+
+impl LintConfig {
+  pub fn level(&self, lint: Lint) -> Level {
+    self.levels.get(&lint).copied().unwrap_or(Level::Warn)
+  }
+
+  pub fn set(&mut self, lint: Lint, level: Level) {
+    self.levels.insert(lint, level);
+  }
+
+  /// Reads `<lint> = allow|warn|deny` lines. `#` starts a comment.
+  pub fn parse(text: &str) -> Result<LintConfig, String> {
+    let mut config = LintConfig::default();
+    for (n, line) in text.lines().enumerate() {
+      let line = line.split('#').next().unwrap_or_default().trim();
+      if line.is_empty() {
+        continue;
+      }
+      let Some((id, level)) = line.split_once('=') else {
+        return Err(format!("Error: Expected `<lint> = allow|warn|deny` on line {}!", n + 1));
+      };
+      let lint = Lint::from_id(id.trim()).ok_or_else(|| format!("Error: Unknown lint `{}` on line {}!", id.trim(), n + 1))?;
+      let level = Level::from_name(level.trim()).ok_or_else(|| format!("Error: Unknown lint level `{}` on line {}!", level.trim(), n + 1))?;
+      config.set(lint, level);
+    }
+    Ok(config)
+  }
+
+  /// The configuration in the `rustpl0-lint.conf` nearest to `path`,
+  /// looking in its directory and then each directory above, up to the root
+  /// of the git repository it is in. Without one, every lint warns.
+  pub fn for_file(path: &Path) -> Result<LintConfig, String> {
+    for dir in path.ancestors().skip(1) {
+      let file = dir.join(CONFIG_FILE);
+      if file.is_file() {
+        let text = fs::read_to_string(&file).map_err(|err| format!("Error: Could not read `{}`: {}!", file.display(), err))?;
+        return LintConfig::parse(&text).map_err(|msg| format!("{} (File: {})", msg, file.display()));
+      }
+      // A worktree's `.git` is a file rather than a directory.
+      if dir.join(".git").exists() {
+        break;
+      }
+    }
+    Ok(LintConfig::default())
+  }
+}
+
+/// Lints a file that `analyze` found no errors in, returning its findings
+/// in source order. Lints the configuration allows are left out, as are
+/// those a `rustpl0: allow(<lint>, ...)` comment names: a comment after
+/// code covers its own line, and a comment on a line of its own covers the
+/// next line of code. Denied lints are reported as errors.
+pub fn lint(filename: &str, source: &[u8], analysis: &Analysis, lexer: &LexerOptions, config: &LintConfig) -> Result<Vec<CompileError>, CompileError> {
+  if let Some(error) = &analysis.error {
+    return Err(error.clone());
+  }
+  let Some(program) = &analysis.program else {
+    return Ok(Vec::new());
+  };
+  let allowed = allow_comments(filename, source, lexer)?;
+
+  let mut linter = Linter::new(program, &analysis.index);
+  linter.program(program);
+  linter.found.sort_by_key(|(_, span, _)| (span.line, span.column));
+
+  let mut warnings: Vec<CompileError> = Vec::new();
+  for (lint, span, message) in linter.found {
+    let severity = match config.level(lint) {
+      Level::Allow => continue,
+      Level::Warn => Severity::Warning,
+      Level::Deny => Severity::Error,
+    };
+    if allowed.contains(&(span.line, lint)) {
+      continue;
+    }
+    warnings.push(CompileError { severity, lint: Some(lint), ..CompileError::new(filename, span, message) });
+  }
+  Ok(warnings)
+}
+
+/// The lines each `rustpl0: allow(...)` comment covers, with the lints it
+/// names.
+fn allow_comments(filename: &str, source: &[u8], lexer: &LexerOptions) -> Result<HashSet<(i32, Lint)>, CompileError> {
+  const MARKER: &str = "rustpl0: allow(";
+  let tokens = lex_with_options(filename, source, LexerOptions { keep_trivia: true, ..lexer.clone() })?;
+  let code_lines: Vec<i32> = tokens.iter().filter(|t| t.typ != TokenType::Eofsym).map(|t| t.line).collect();
+
+  let mut allowed: HashSet<(i32, Lint)> = HashSet::new();
+  for comment in tokens.iter().flat_map(|t| &t.trivia).filter(|t| t.kind == TriviaKind::Comment) {
+    let Some(start) = comment.text.find(MARKER) else {
+      continue;
+    };
+    let list = &comment.text[start + MARKER.len()..];
+    let list = &list[..list.find(')').unwrap_or(list.len())];
+    let line = if code_lines.contains(&comment.line) {
+      comment.line
+    } else {
+      code_lines.iter().copied().find(|&line| line >= comment.end_line()).unwrap_or(comment.end_line())
+    };
+    for id in list.split(',').map(str::trim) {
+      match Lint::from_id(id) {
+        Some(lint) => allowed.insert((line, lint)),
+        None => {
+          let span = Span { line: comment.line, column: comment.column };
+          return Err(CompileError::new(filename, span, format!("Unknown lint '{}' in allow comment.", id)));
+        },
+      };
+    }
+  }
+  Ok(allowed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Access {
+  Read,
+  Write,
+}
+
+/// Calls `f` for every identifier used in `block`'s procedures and body.
+fn visit_block(block: &Block, f: &mut impl FnMut(&Ident, Access)) {
+  for p in &block.procs {
+    visit_block(&p.block, f);
+  }
+  visit_stmt(&block.body, f);
+}
+
+fn visit_stmt(stmt: &Stmt, f: &mut impl FnMut(&Ident, Access)) {
+  match &stmt.kind {
+    StmtKind::Assign { target, index, value } => {
+      if let Some(index) = index {
+        visit_expr(index, f);
+      }
+      visit_expr(value, f);
+      f(target, Access::Write);
+    },
+    StmtKind::Call { name, args } => {
+      args.iter().for_each(|arg| visit_expr(arg, f));
+      f(name, Access::Read);
+    },
+    StmtKind::Begin(stmts) => stmts.iter().for_each(|s| visit_stmt(s, f)),
+    StmtKind::Repeat { body, cond } => {
+      body.iter().for_each(|s| visit_stmt(s, f));
+      visit_condition(cond, f);
+    },
+    StmtKind::If { cond, then_stmt, else_stmt } => {
+      visit_condition(cond, f);
+      visit_stmt(then_stmt, f);
+      if let Some(else_stmt) = else_stmt {
+        visit_stmt(else_stmt, f);
+      }
+    },
+    StmtKind::While { cond, body } => {
+      visit_condition(cond, f);
+      visit_stmt(body, f);
+    },
+    // The loop itself reads the counter to test and step it.
+    StmtKind::For { var, from, to, body, .. } => {
+      visit_expr(from, f);
+      visit_expr(to, f);
+      f(var, Access::Write);
+      f(var, Access::Read);
+      visit_stmt(body, f);
+    },
+    StmtKind::Read { target, index } => {
+      if let Some(index) = index {
+        visit_expr(index, f);
+      }
+      f(target, Access::Write);
+    },
+    StmtKind::Write(arg) => visit_write_arg(arg, f),
+    StmtKind::Writeln(args) => args.iter().for_each(|arg| visit_write_arg(arg, f)),
+    StmtKind::Break | StmtKind::Continue | StmtKind::Skip => {},
+  }
+}
+
+fn visit_write_arg(arg: &WriteArg, f: &mut impl FnMut(&Ident, Access)) {
+  if let WriteArg::Expr(expr) = arg {
+    visit_expr(expr, f);
+  }
+}
+
+fn visit_condition(cond: &Condition, f: &mut impl FnMut(&Ident, Access)) {
+  match cond {
+    Condition::Odd(expr) => visit_expr(expr, f),
+    Condition::Rel { left, right, .. } => {
+      visit_expr(left, f);
+      visit_expr(right, f);
+    },
+    Condition::Not(cond) => visit_condition(cond, f),
+    Condition::And(left, right) | Condition::Or(left, right) => {
+      visit_condition(left, f);
+      visit_condition(right, f);
+    },
+  }
+}
+
+fn visit_expr(expr: &Expr, f: &mut impl FnMut(&Ident, Access)) {
+  match &expr.kind {
+    ExprKind::Number(_) => {},
+    ExprKind::Name(name) => f(name, Access::Read),
+    ExprKind::Call { name, args } => {
+      args.iter().for_each(|arg| visit_expr(arg, f));
+      f(name, Access::Read);
+    },
+    ExprKind::Index { name, index } => {
+      visit_expr(index, f);
+      f(name, Access::Read);
+    },
+    ExprKind::Neg(inner) => visit_expr(inner, f),
+    ExprKind::Binary { left, right, .. } => {
+      visit_expr(left, f);
+      visit_expr(right, f);
+    },
+  }
+}
+
+/// Whether a `break` leaves the loop whose body is `stmt`, rather than a
+/// loop nested inside it.
+fn breaks_out(stmt: &Stmt) -> bool {
+  match &stmt.kind {
+    StmtKind::Break => true,
+    StmtKind::Begin(stmts) => stmts.iter().any(breaks_out),
+    StmtKind::If { then_stmt, else_stmt, .. } => breaks_out(then_stmt) || else_stmt.as_deref().is_some_and(breaks_out),
+    _ => false,
+  }
+}
+
+/// Whether running `stmt` does nothing.
+fn is_empty(stmt: &Stmt) -> bool {
+  match &stmt.kind {
+    StmtKind::Skip => true,
+    StmtKind::Begin(stmts) => stmts.iter().all(is_empty),
+    _ => false,
+  }
+}
+
+type Set = HashSet<usize>;
+
+struct Linter<'a> {
+  index: &'a SymbolIndex,
+  /// The declaration each identifier use or declaration refers to, by
+  /// where it is in the source.
+  declarations: HashMap<(i32, i32), usize>,
+  /// Declarations whose value is read, or that are called, somewhere.
+  read: Set,
+  is_module: bool,
+  /// The names declared in each enclosing scope, outermost first.
+  scopes: Vec<Vec<&'a Ident>>,
+  found: Vec<(Lint, Span, String)>,
+}
+
+impl<'a> Linter<'a> {
+  fn new(program: &Program, index: &'a SymbolIndex) -> Linter<'a> {
+    let mut declarations: HashMap<(i32, i32), usize> = HashMap::new();
+    for (i, d) in index.declarations.iter().enumerate() {
+      if !matches!(d.origin, Origin::Imported(_)) {
+        declarations.insert((d.name.span.line, d.name.span.column), i);
+      }
+    }
+    for r in &index.references {
+      declarations.insert((r.span.line, r.span.column), r.declaration);
+    }
+
+    let mut linter = Linter { index, declarations, read: Set::new(), is_module: program.module.is_some(), scopes: Vec::new(), found: Vec::new() };
+    let mut read = Set::new();
+    visit_block(&program.block, &mut |name, access| {
+      if let (Access::Read, Some(d)) = (access, linter.declaration(name)) {
+        read.insert(d);
+      }
+    });
+    linter.read = read;
+    linter
+  }
+
+  fn declaration(&self, name: &Ident) -> Option<usize> {
+    self.declarations.get(&(name.span.line, name.span.column)).copied()
+  }
+
+  fn report(&mut self, lint: Lint, span: Span, message: String) {
+    self.found.push((lint, span, message));
+  }
+
+  fn program(&mut self, program: &'a Program) {
+    let index = self.index;
+    let imports = index.declarations.iter().filter(|d| matches!(d.origin, Origin::Imported(_))).map(|d| &d.name);
+    self.scopes.push(imports.collect());
+    self.block(&program.block, &[]);
+    self.unused();
+  }
+
+  /// Declarations never used. A module's constants and procedures are
+  /// exported, so are used elsewhere.
+  fn unused(&mut self) {
+    let references: Set = self.index.references.iter().map(|r| r.declaration).collect();
+    for (i, d) in self.index.declarations.iter().enumerate() {
+      if d.origin != Origin::Declared || (self.is_module && d.level == 0) || self.read.contains(&i) {
+        continue;
+      }
+      let name = &d.name.name;
+      let (lint, message) = match d.symbol {
+        Symbol::Const(_) => (Lint::UnusedConstant, format!("Constant '{}' is never used.", name)),
+        Symbol::Var { .. } | Symbol::Array { .. } if references.contains(&i) => (Lint::UnusedVariable, format!("Variable '{}' is assigned but never read.", name)),
+        Symbol::Var { .. } | Symbol::Array { .. } => (Lint::UnusedVariable, format!("Variable '{}' is never used.", name)),
+        Symbol::Proc { kind: ProcKind::Function, .. } => (Lint::UnusedProcedure, format!("Function '{}' is never called.", name)),
+        Symbol::Proc { .. } => (Lint::UnusedProcedure, format!("Procedure '{}' is never called.", name)),
+      };
+      self.report(lint, d.name.span, message);
+    }
+  }
+
+  /// Reports `name` if a scope outside the innermost one declares it too,
+  /// then declares it in the innermost one.
+  fn declare(&mut self, name: &'a Ident) {
+    let (inner, outer) = self.scopes.split_last_mut().expect("no open scope");
+    if let Some(hidden) = outer.iter().rev().flat_map(|scope| scope.iter()).find(|d| d.name == name.name) {
+      let message = format!("'{}' shadows the declaration on line {}.", name.name, hidden.span.line);
+      self.found.push((Lint::ShadowedName, name.span, message));
+    }
+    inner.push(name);
+  }
+
+  fn block(&mut self, block: &'a Block, params: &[Ident]) {
+    for c in &block.consts {
+      self.declare(&c.name);
+    }
+    for v in &block.vars {
+      self.declare(&v.name);
+    }
+    for p in &block.procs {
+      self.declare(&p.name);
+      if is_empty(&p.block.body) {
+        let what = if p.kind == ProcKind::Function { "Function" } else { "Procedure" };
+        self.report(Lint::EmptyProcedure, p.name.span, format!("{} '{}' does nothing.", what, p.name.name));
+      }
+      self.scopes.push(Vec::new());
+      for param in &p.params {
+        self.declare(param);
+      }
+      self.block(&p.block, &p.params);
+      self.scopes.pop();
+    }
+    self.stmt(&block.body);
+    self.flow(block, params);
+  }
+
+  /// The value of an expression made only of numbers and constants.
+  fn value(&self, expr: &Expr) -> Option<Word> {
+    match &expr.kind {
+      ExprKind::Number(n) => Some(*n),
+      ExprKind::Name(name) => match self.index.declarations[self.declaration(name)?].symbol {
+        Symbol::Const(value) => Some(value),
+        _ => None,
+      },
+      ExprKind::Neg(inner) => self.value(inner)?.checked_neg(),
+      ExprKind::Binary { left, op, right } => {
+        let (left, right) = (self.value(left)?, self.value(right)?);
+        match op {
+          BinOp::Add => left.checked_add(right),
+          BinOp::Sub => left.checked_sub(right),
+          BinOp::Mul => left.checked_mul(right),
+          BinOp::Div => left.checked_div(right),
+          BinOp::Mod => left.checked_rem(right),
+        }
+      },
+      _ => None,
+    }
+  }
+
+  fn truth(&self, cond: &Condition) -> Option<bool> {
+    match cond {
+      Condition::Odd(expr) => Some(self.value(expr)? % 2 != 0),
+      Condition::Rel { left, op, right } => {
+        let (left, right) = (self.value(left)?, self.value(right)?);
+        Some(match op {
+          RelOp::Eq => left == right,
+          RelOp::Neq => left != right,
+          RelOp::Lt => left < right,
+          RelOp::Leq => left <= right,
+          RelOp::Gt => left > right,
+          RelOp::Geq => left >= right,
+        })
+      },
+      Condition::Not(cond) => Some(!self.truth(cond)?),
+      Condition::And(left, right) => Some(self.truth(left)? && self.truth(right)?),
+      Condition::Or(left, right) => Some(self.truth(left)? || self.truth(right)?),
+    }
+  }
+
+  fn constant_condition(&mut self, span: Span, truth: bool) {
+    self.report(Lint::ConstantCondition, span, format!("This condition is always {}.", truth));
+  }
+
+  /// Whether control never comes out of `stmt` to the statement after it.
+  fn never_ends(&self, stmt: &Stmt) -> bool {
+    match &stmt.kind {
+      StmtKind::Break | StmtKind::Continue => true,
+      StmtKind::While { cond, body } => self.truth(cond) == Some(true) && !breaks_out(body),
+      StmtKind::Repeat { body, cond } => self.truth(cond) == Some(false) && !body.iter().any(breaks_out),
+      _ => false,
+    }
+  }
+
+  /// Checks the statements in order; only the first unreachable one is
+  /// reported.
+  fn stmts(&mut self, stmts: &[Stmt]) {
+    let mut ended = false;
+    for stmt in stmts {
+      if ended {
+        self.report(Lint::UnreachableCode, stmt.span, "This statement is never reached.".to_string());
+        ended = false;
+      } else if self.never_ends(stmt) {
+        ended = true;
+      }
+      self.stmt(stmt);
+    }
+  }
+
+  /// Finds constant conditions and the code they keep from running. `while`
+  /// and `repeat` loops that run until a `break` are left alone.
+  fn stmt(&mut self, stmt: &Stmt) {
+    match &stmt.kind {
+      StmtKind::Begin(stmts) => self.stmts(stmts),
+      StmtKind::If { cond, then_stmt, else_stmt } => {
+        match self.truth(cond) {
+          Some(true) => {
+            self.constant_condition(stmt.span, true);
+            if let Some(else_stmt) = else_stmt {
+              self.report(Lint::UnreachableCode, else_stmt.span, "This branch is never taken.".to_string());
+            }
+          },
+          Some(false) => {
+            self.constant_condition(stmt.span, false);
+            self.report(Lint::UnreachableCode, then_stmt.span, "This branch is never taken.".to_string());
+          },
+          None => {},
+        }
+        self.stmt(then_stmt);
+        if let Some(else_stmt) = else_stmt {
+          self.stmt(else_stmt);
+        }
+      },
+      StmtKind::While { cond, body } => {
+        if self.truth(cond) == Some(false) {
+          self.constant_condition(stmt.span, false);
+          self.report(Lint::UnreachableCode, body.span, "This loop body never runs.".to_string());
+        }
+        self.stmt(body);
+      },
+      StmtKind::Repeat { body, cond } => {
+        if self.truth(cond) == Some(true) {
+          self.constant_condition(stmt.span, true);
+        }
+        self.stmts(body);
+      },
+      StmtKind::For { body, .. } => self.stmt(body),
+      _ => {},
+    }
+  }
+
+  /// Looks for reads before assignment and assignments never read, among
+  /// the block's own variables and parameters. Those its procedures use
+  /// are left out, since a call may read or assign them.
+  fn flow(&mut self, block: &Block, params: &[Ident]) {
+    let mut nested = Set::new();
+    for p in &block.procs {
+      visit_block(&p.block, &mut |name, _| {
+        if let Some(d) = self.declaration(name) {
+          nested.insert(d);
+        }
+      });
+    }
+    let scalar = |d: &usize| matches!(self.index.declarations[*d].symbol, Symbol::Var { .. }) && !nested.contains(d);
+    let vars: Set = block.vars.iter().filter_map(|v| self.declaration(&v.name)).filter(scalar).collect();
+
+    let mut flow = Flow { linter: self, tracked: vars.clone(), reported: Set::new(), loops: Vec::new(), found: Vec::new() };
+    flow.assigned(&block.body, Set::new());
+
+    // Parameters arrive assigned, but their values can still go unread.
+    // Variables never read at all are reported as unused instead.
+    let params = params.iter().filter_map(|param| flow.linter.declaration(param)).filter(scalar);
+    flow.tracked = vars.iter().copied().chain(params).filter(|d| flow.linter.read.contains(d)).collect();
+    flow.live(&block.body, Set::new(), true);
+
+    let found = flow.found;
+    self.found.extend(found);
+  }
+}
+
+/// Data flow through one block's body, over the variables in `tracked`.
+struct Flow<'l, 'a> {
+  linter: &'l Linter<'a>,
+  tracked: Set,
+  /// Variables already reported as read before assignment.
+  reported: Set,
+  /// For each enclosing loop, the variables live where `break` and
+  /// `continue` go.
+  loops: Vec<(Set, Set)>,
+  found: Vec<(Lint, Span, String)>,
+}
+
+impl Flow<'_, '_> {
+  fn tracked(&self, name: &Ident) -> Option<usize> {
+    self.linter.declaration(name).filter(|d| self.tracked.contains(d))
+  }
+
+  fn name(&self, d: usize) -> &str {
+    &self.linter.index.declarations[d].name.name
+  }
+
+  /// The tracked variables read by whatever `visit` visits.
+  fn reads(&self, visit: impl FnOnce(&mut dyn FnMut(&Ident, Access))) -> Vec<(usize, Span)> {
+    let mut reads: Vec<(usize, Span)> = Vec::new();
+    visit(&mut |name, access| {
+      if let (Access::Read, Some(d)) = (access, self.tracked(name)) {
+        reads.push((d, name.span));
+      }
+    });
+    reads
+  }
+
+  fn expr_reads(&self, expr: &Expr) -> Vec<(usize, Span)> {
+    self.reads(|f| visit_expr(expr, &mut |name, access| f(name, access)))
+  }
+
+  fn condition_reads(&self, cond: &Condition) -> Vec<(usize, Span)> {
+    self.reads(|f| visit_condition(cond, &mut |name, access| f(name, access)))
+  }
+
+  fn write_reads(&self, args: &[WriteArg]) -> Vec<(usize, Span)> {
+    self.reads(|f| args.iter().for_each(|arg| visit_write_arg(arg, &mut |name, access| f(name, access))))
+  }
+
+  /// Reports the reads of variables not in `assigned`.
+  fn check(&mut self, reads: Vec<(usize, Span)>, assigned: &Set) {
+    for (d, span) in reads {
+      if !assigned.contains(&d) && self.reported.insert(d) {
+        let message = format!("Variable '{}' may be read before it is assigned.", self.name(d));
+        self.found.push((Lint::UninitializedRead, span, message));
+      }
+    }
+  }
+
+  /// The variables certainly assigned after `stmt`, given those assigned
+  /// before it.
+  fn assigned(&mut self, stmt: &Stmt, mut set: Set) -> Set {
+    match &stmt.kind {
+      StmtKind::Assign { target, index, value } => {
+        let mut reads = self.expr_reads(value);
+        if let Some(index) = index {
+          reads.extend(self.expr_reads(index));
+        }
+        self.check(reads, &set);
+        set.extend(self.tracked(target));
+        set
+      },
+      StmtKind::Call { args, .. } => {
+        let reads = args.iter().flat_map(|arg| self.expr_reads(arg)).collect();
+        self.check(reads, &set);
+        set
+      },
+      StmtKind::Begin(stmts) => stmts.iter().fold(set, |set, s| self.assigned(s, set)),
+      StmtKind::If { cond, then_stmt, else_stmt } => {
+        self.check(self.condition_reads(cond), &set);
+        let then_set = self.assigned(then_stmt, set.clone());
+        let else_set = match else_stmt {
+          Some(else_stmt) => self.assigned(else_stmt, set),
+          None => set,
+        };
+        then_set.intersection(&else_set).copied().collect()
+      },
+      StmtKind::While { cond, body } => {
+        self.check(self.condition_reads(cond), &set);
+        self.assigned(body, set.clone());
+        set
+      },
+      // The body runs at least once, unless a `break` cuts it short.
+      StmtKind::Repeat { body, cond } => {
+        let after = body.iter().fold(set.clone(), |set, s| self.assigned(s, set));
+        self.check(self.condition_reads(cond), &after);
+        if body.iter().any(breaks_out) { set } else { after }
+      },
+      StmtKind::For { var, from, to, body, .. } => {
+        self.check(self.expr_reads(from), &set);
+        self.check(self.expr_reads(to), &set);
+        let mut inside = set.clone();
+        inside.extend(self.tracked(var));
+        self.assigned(body, inside);
+        set.extend(self.tracked(var));
+        set
+      },
+      StmtKind::Read { target, index } => {
+        if let Some(index) = index {
+          self.check(self.expr_reads(index), &set);
+        }
+        set.extend(self.tracked(target));
+        set
+      },
+      StmtKind::Write(arg) => {
+        self.check(self.write_reads(std::slice::from_ref(arg)), &set);
+        set
+      },
+      StmtKind::Writeln(args) => {
+        self.check(self.write_reads(args), &set);
+        set
+      },
+      StmtKind::Break | StmtKind::Continue | StmtKind::Skip => set,
+    }
+  }
+
+  fn add_reads(&self, mut set: Set, reads: Vec<(usize, Span)>) -> Set {
+    set.extend(reads.into_iter().map(|(d, _)| d));
+    set
+  }
+
+  /// The variables live before `stmt`, given those live after it. With
+  /// `report`, assignments to variables that are not live after them are
+  /// reported.
+  fn live(&mut self, stmt: &Stmt, out: Set, report: bool) -> Set {
+    match &stmt.kind {
+      StmtKind::Assign { target, index, value } => {
+        let mut set = out;
+        if let Some(d) = self.tracked(target) {
+          if report && !set.contains(&d) {
+            let message = format!("The value assigned to '{}' is never read.", target.name);
+            self.found.push((Lint::UnusedAssignment, target.span, message));
+          }
+          set.remove(&d);
+        }
+        let set = self.add_reads(set, self.expr_reads(value));
+        match index {
+          Some(index) => self.add_reads(set, self.expr_reads(index)),
+          None => set,
+        }
+      },
+      StmtKind::Call { args, .. } => {
+        let reads = args.iter().flat_map(|arg| self.expr_reads(arg)).collect();
+        self.add_reads(out, reads)
+      },
+      StmtKind::Begin(stmts) => self.live_list(stmts, out, report),
+      StmtKind::If { cond, then_stmt, else_stmt } => {
+        let mut set = self.live(then_stmt, out.clone(), report);
+        match else_stmt {
+          Some(else_stmt) => set.extend(self.live(else_stmt, out, report)),
+          None => set.extend(out),
+        }
+        self.add_reads(set, self.condition_reads(cond))
+      },
+      StmtKind::While { cond, body } => {
+        let cond_reads = self.add_reads(Set::new(), self.condition_reads(cond));
+        self.live_loop(&out, report, |flow, head, report| {
+          flow.loops.push((out.clone(), head.clone()));
+          let body_in = flow.live(body, head.clone(), report);
+          flow.loops.pop();
+          body_in.union(&cond_reads).chain(&out).copied().collect()
+        })
+      },
+      StmtKind::Repeat { body, cond } => {
+        let cond_reads = self.add_reads(Set::new(), self.condition_reads(cond));
+        self.live_loop(&out, report, |flow, body_in, report| {
+          // After the body, the condition either loops back or exits.
+          let after: Set = body_in.union(&cond_reads).chain(&out).copied().collect();
+          flow.loops.push((out.clone(), after.clone()));
+          let body_in = flow.live_list(body, after, report);
+          flow.loops.pop();
+          body_in
+        })
+      },
+      StmtKind::For { var, from, to, body, .. } => {
+        let counter: Set = self.tracked(var).into_iter().collect();
+        let mut set = self.live_loop(&out, report, |flow, head, report| {
+          // Stepping and testing the counter reads it.
+          let head: Set = head.union(&counter).copied().collect();
+          flow.loops.push((out.clone(), head.clone()));
+          let body_in = flow.live(body, head, report);
+          flow.loops.pop();
+          body_in.union(&counter).chain(&out).copied().collect()
+        });
+        set.retain(|d| !counter.contains(d));
+        let set = self.add_reads(set, self.expr_reads(from));
+        self.add_reads(set, self.expr_reads(to))
+      },
+      StmtKind::Read { target, index } => {
+        let mut set = out;
+        if let Some(d) = self.tracked(target) {
+          set.remove(&d);
+        }
+        match index {
+          Some(index) => self.add_reads(set, self.expr_reads(index)),
+          None => set,
+        }
+      },
+      StmtKind::Write(arg) => self.add_reads(out, self.write_reads(std::slice::from_ref(arg))),
+      StmtKind::Writeln(args) => self.add_reads(out, self.write_reads(args)),
+      StmtKind::Break => self.loops.last().map(|(exit, _)| exit.clone()).unwrap_or_default(),
+      StmtKind::Continue => self.loops.last().map(|(_, next)| next.clone()).unwrap_or_default(),
+      StmtKind::Skip => out,
+    }
+  }
+
+  fn live_list(&mut self, stmts: &[Stmt], out: Set, report: bool) -> Set {
+    stmts.iter().rev().fold(out, |set, s| self.live(s, set, report))
+  }
+
+  /// Runs `step`, which maps what is live at the top of a loop to what is
+  /// live there after one more iteration, until nothing changes. Only the
+  /// last run reports.
+  fn live_loop(&mut self, out: &Set, report: bool, mut step: impl FnMut(&mut Self, &Set, bool) -> Set) -> Set {
+    let mut head = out.clone();
+    loop {
+      let next = step(self, &head, false);
+      if next == head {
+        break;
+      }
+      head = next;
+    }
+    if report {
+      step(self, &head, true);
+    }
+    head
+  }
+}
@@ -0,0 +1,507 @@
+use crate::ast::{self, Block as AstBlock, Condition, Expr, ExprKind, Ident, ProcKind, Program, RelOp, Stmt, StmtKind, WriteArg};
+use crate::ir::*;
+use crate::machine::arithmetic;
+use crate::machine::machine_types::{Address, ArrayInfo, Word};
+use crate::object::{ExportValue, Import, Object};
+use crate::scope::{Scopes, Symbol};
+
+// Every activation record starts with the static link, dynamic link and
+// return address, so declared variables start at offset 3. Parameter i of n
+// lives at offset i - n, and a function's result at -(n + 1).
+pub const AR_SIZE: Address = 3;
+
+/// Where `break` and `continue` go in the innermost loop.
+struct Loop {
+  breaks: BlockId,
+  continues: BlockId,
+}
+
+/// The procedure being lowered. Blocks get their terminator once complete.
+#[derive(Default)]
+struct Builder {
+  blocks: Vec<(Vec<Inst>, Option<Terminator>)>,
+  current: BlockId,
+  /// Blocks in the order they were filled, which is the order they are laid
+  /// out in.
+  order: Vec<BlockId>,
+  temps: Temp,
+  loops: Vec<Loop>,
+}
+
+struct Lowerer {
+  functions: Vec<Function>,
+  arrays: Vec<ArrayInfo>,
+  imports: Vec<Import>,
+  scopes: Scopes<Symbol>,
+  builder: Builder,
+}
+
+impl Lowerer {
+  fn temp(&mut self) -> Temp {
+    self.builder.temps += 1;
+    self.builder.temps - 1
+  }
+
+  fn emit(&mut self, inst: Inst) {
+    let current = self.builder.current;
+    self.builder.blocks[current].0.push(inst);
+  }
+
+  fn new_block(&mut self) -> BlockId {
+    self.builder.blocks.push((Vec::new(), None));
+    self.builder.blocks.len() - 1
+  }
+
+  fn terminate(&mut self, term: Terminator) {
+    let current = self.builder.current;
+    self.builder.blocks[current].1 = Some(term);
+  }
+
+  /// Continues in `block`, which must not have been filled yet.
+  fn switch_to(&mut self, block: BlockId) {
+    self.builder.current = block;
+    self.builder.order.push(block);
+  }
+
+  /// Ends the current block with a jump to `target`, and continues there.
+  fn jump_to(&mut self, target: BlockId) {
+    self.terminate(Terminator::Jump(target));
+    self.switch_to(target);
+  }
+
+  fn lookup(&self, name: &Ident) -> (Symbol, usize) {
+    let (symbol, level) = self.scopes.lookup(&name.name)
+      .unwrap_or_else(|| panic!("unresolved identifier '{}' reached code generation", name.name));
+    (*symbol, level)
+  }
+
+  /// How many static links lead from the current block to the one at `level`.
+  fn depth(&self, level: usize) -> usize {
+    self.scopes.level() - level
+  }
+
+  fn var(&self, name: &Ident, level: usize, offset: Address) -> Var {
+    Var { name: name.name.clone(), depth: self.depth(level), offset }
+  }
+
+  fn array(&self, name: &Ident) -> ArrayRef {
+    let (symbol, level) = self.lookup(name);
+    let Symbol::Array { offset, id, .. } = symbol else {
+      panic!("'{}' is not an array", name.name);
+    };
+    ArrayRef { base: self.var(name, level, offset), id }
+  }
+
+  /// Lowers a procedure body, or the main block, into a new function.
+  fn lower_function(&mut self, index: usize, name: &str, kind: FunctionKind, params: &[Ident], block: &AstBlock) {
+    let outer = std::mem::take(&mut self.builder);
+    let entry = self.new_block();
+    self.switch_to(entry);
+    let locals = self.declare_block(block);
+    self.lower_stmt(&block.body);
+    self.terminate(if kind == FunctionKind::Main { Terminator::Halt } else { Terminator::Return });
+
+    let mut builder = std::mem::replace(&mut self.builder, outer);
+    let mut renumbered: Vec<BlockId> = vec![0; builder.blocks.len()];
+    for (new, old) in builder.order.iter().enumerate() {
+      renumbered[*old] = new;
+    }
+    let blocks = builder.order.iter().map(|old| {
+      let (insts, term) = std::mem::take(&mut builder.blocks[*old]);
+      let term = match term.expect("block left without a terminator") {
+        Terminator::Jump(target) => Terminator::Jump(renumbered[target]),
+        Terminator::Branch { cond, then, otherwise } => Terminator::Branch { cond, then: renumbered[then], otherwise: renumbered[otherwise] },
+        term => term,
+      };
+      Block { insts, term }
+    }).collect();
+    let params = params.iter().map(|p| p.name.clone()).collect();
+    self.functions[index] = Function { name: name.to_string(), kind, params, locals, declared: locals, blocks, temps: builder.temps };
+  }
+
+  /// Declares a block's names, lowering its procedures, and returns the words
+  /// its variables take.
+  fn declare_block(&mut self, block: &AstBlock) -> Address {
+    for c in &block.consts {
+      self.scopes.declare(&c.name.name, Symbol::Const(c.value));
+    }
+    let mut offset = AR_SIZE;
+    for v in &block.vars {
+      let symbol = match v.length {
+        Some(length) => {
+          self.arrays.push(ArrayInfo { name: v.name.name.clone(), length });
+          Symbol::Array { offset, length, id: self.arrays.len() - 1 }
+        },
+        None => Symbol::Var { offset },
+      };
+      self.scopes.declare(&v.name.name, symbol);
+      offset += v.length.unwrap_or(1);
+    }
+
+    for p in &block.procs {
+      // A procedure's address is its index among the functions until code
+      // is generated.
+      let index = self.functions.len();
+      self.functions.push(Function { name: String::new(), kind: FunctionKind::Main, params: Vec::new(), locals: 0, declared: 0, blocks: Vec::new(), temps: 0 });
+      self.scopes.declare(&p.name.name, Symbol::Proc { kind: p.kind, arity: p.params.len(), address: index as Address, import: None });
+      self.scopes.push();
+      let arity = p.params.len() as Address;
+      for (i, param) in p.params.iter().enumerate() {
+        self.scopes.declare(&param.name, Symbol::Var { offset: i as Address - arity });
+      }
+      self.lower_function(index, &p.name.name, FunctionKind::Proc(p.kind), &p.params, &p.block);
+      self.scopes.pop();
+    }
+    offset - AR_SIZE
+  }
+
+  fn lower_call(&mut self, name: &Ident, args: &[Expr]) -> Option<Temp> {
+    let (symbol, level) = self.lookup(name);
+    let Symbol::Proc { kind, address, import, .. } = symbol else {
+      panic!("'{}' is not a procedure", name.name);
+    };
+    let args: Vec<Operand> = args.iter().map(|arg| self.lower_expr(arg)).collect();
+    let callee = match import {
+      Some(index) => Callee::Import(index),
+      None => Callee::Local(address as usize),
+    };
+    let dst = if kind == ProcKind::Function { Some(self.temp()) } else { None };
+    let depth = self.depth(level);
+    self.emit(Inst::Call { dst, callee, depth, args });
+    dst
+  }
+
+  fn lower_stmt(&mut self, stmt: &Stmt) {
+    match &stmt.kind {
+      StmtKind::Assign { target, index, value } => {
+        let (symbol, level) = self.lookup(target);
+        match symbol {
+          Symbol::Var { offset } => {
+            let src = self.lower_expr(value);
+            let var = self.var(target, level, offset);
+            self.emit(Inst::Store { var, src });
+          },
+          Symbol::Array { .. } => {
+            let index = self.lower_expr(index.as_ref().expect("unindexed array assignment reached code generation"));
+            let src = self.lower_expr(value);
+            let array = self.array(target);
+            self.emit(Inst::StoreElem { array, index, src });
+          },
+          Symbol::Proc { arity, .. } => {
+            // Setting a function's result, inside its body.
+            let src = self.lower_expr(value);
+            let var = Var { name: target.name.clone(), depth: self.depth(level + 1), offset: -(arity as Address) - 1 };
+            self.emit(Inst::Store { var, src });
+          },
+          Symbol::Const(_) => panic!("assignment to constant '{}' reached code generation", target.name),
+        }
+      },
+      StmtKind::Call { name, args } => {
+        self.lower_call(name, args);
+      },
+      StmtKind::Begin(stmts) => {
+        for s in stmts {
+          self.lower_stmt(s);
+        }
+      },
+      StmtKind::If { cond, then_stmt, else_stmt } => {
+        let (then_block, end) = (self.new_block(), self.new_block());
+        let else_block = if else_stmt.is_some() { self.new_block() } else { end };
+        self.lower_branch(cond, then_block, else_block);
+        self.switch_to(then_block);
+        self.lower_stmt(then_stmt);
+        match else_stmt {
+          Some(else_stmt) => {
+            self.terminate(Terminator::Jump(end));
+            self.switch_to(else_block);
+            self.lower_stmt(else_stmt);
+            self.jump_to(end);
+          },
+          None => self.jump_to(end),
+        }
+      },
+      StmtKind::While { cond, body } => {
+        let (top, body_block, exit) = (self.new_block(), self.new_block(), self.new_block());
+        self.jump_to(top);
+        self.lower_branch(cond, body_block, exit);
+        self.switch_to(body_block);
+        self.lower_loop_body(std::slice::from_ref(body.as_ref()), exit, top);
+        self.terminate(Terminator::Jump(top));
+        self.switch_to(exit);
+      },
+      StmtKind::Repeat { body, cond } => {
+        let (top, test, exit) = (self.new_block(), self.new_block(), self.new_block());
+        self.jump_to(top);
+        self.lower_loop_body(body, exit, test);
+        self.jump_to(test);
+        self.lower_branch(cond, exit, top);
+        self.switch_to(exit);
+      },
+      StmtKind::For { var, from, to, downto, body } => {
+        let (symbol, level) = self.lookup(var);
+        let Symbol::Var { offset } = symbol else {
+          panic!("for loop counter '{}' is not a variable", var.name);
+        };
+        let counter = self.var(var, level, offset);
+        let src = self.lower_expr(from);
+        self.emit(Inst::Store { var: counter.clone(), src });
+        let limit = self.lower_expr(to);
+
+        // Test against the limit before the first iteration...
+        let (top, body_block, step, increment, exit) = (self.new_block(), self.new_block(), self.new_block(), self.new_block(), self.new_block());
+        self.jump_to(top);
+        let value = self.load(&counter);
+        let in_range = self.binary(if *downto { BinOp::Geq } else { BinOp::Leq }, value, limit);
+        self.terminate(Terminator::Branch { cond: in_range, then: body_block, otherwise: exit });
+        self.switch_to(body_block);
+        self.lower_loop_body(std::slice::from_ref(body.as_ref()), exit, step);
+        self.jump_to(step);
+
+        // ...and stop on reaching it, so the counter never steps past it.
+        let value = self.load(&counter);
+        let at_limit = self.binary(BinOp::Eq, value, limit);
+        self.terminate(Terminator::Branch { cond: at_limit, then: exit, otherwise: increment });
+        self.switch_to(increment);
+        let value = self.load(&counter);
+        let next = self.binary(if *downto { BinOp::Sub } else { BinOp::Add }, value, Operand::Const(1));
+        self.emit(Inst::Store { var: counter, src: next });
+        self.terminate(Terminator::Jump(top));
+        self.switch_to(exit);
+      },
+      StmtKind::Break | StmtKind::Continue => {
+        let innermost = self.builder.loops.last().expect("'break' or 'continue' outside a loop reached code generation");
+        let target = if matches!(stmt.kind, StmtKind::Break) { innermost.breaks } else { innermost.continues };
+        // Anything after the jump is unreachable, but still needs a block.
+        self.terminate(Terminator::Jump(target));
+        let rest = self.new_block();
+        self.switch_to(rest);
+      },
+      StmtKind::Read { target, index } => {
+        let (symbol, level) = self.lookup(target);
+        match (symbol, index) {
+          (Symbol::Var { offset }, None) => {
+            let dst = self.temp();
+            self.emit(Inst::Read { dst });
+            let var = self.var(target, level, offset);
+            self.emit(Inst::Store { var, src: Operand::Temp(dst) });
+          },
+          (Symbol::Array { .. }, Some(index)) => {
+            let index = self.lower_expr(index);
+            let dst = self.temp();
+            self.emit(Inst::Read { dst });
+            let array = self.array(target);
+            self.emit(Inst::StoreElem { array, index, src: Operand::Temp(dst) });
+          },
+          _ => panic!("read into non-variable '{}' reached code generation", target.name),
+        }
+      },
+      StmtKind::Write(arg) => self.lower_write(arg, WriteMode::Line),
+      StmtKind::Writeln(args) => {
+        for arg in args {
+          self.lower_write(arg, WriteMode::Number);
+        }
+        self.lower_write(&WriteArg::Str("\n".to_string()), WriteMode::Char);
+      },
+      StmtKind::Skip => {},
+    }
+  }
+
+  fn lower_loop_body(&mut self, body: &[Stmt], breaks: BlockId, continues: BlockId) {
+    self.builder.loops.push(Loop { breaks, continues });
+    for s in body {
+      self.lower_stmt(s);
+    }
+    self.builder.loops.pop();
+  }
+
+  /// Writes an expression with `mode`, or a string one character at a time.
+  fn lower_write(&mut self, arg: &WriteArg, mode: WriteMode) {
+    match arg {
+      WriteArg::Expr(expr) => {
+        let src = self.lower_expr(expr);
+        self.emit(Inst::Write { src, mode });
+      },
+      WriteArg::Str(text) => {
+        for c in text.chars() {
+          self.emit(Inst::Write { src: Operand::Const(c as Word), mode: WriteMode::Char });
+        }
+      },
+    }
+  }
+
+  /// Ends the current block by going to `then` when `cond` holds and to
+  /// `otherwise` when not. `and` and `or` skip their right operand once the
+  /// left one decides the result.
+  fn lower_branch(&mut self, cond: &Condition, then: BlockId, otherwise: BlockId) {
+    match cond {
+      Condition::Not(inner) => self.lower_branch(inner, otherwise, then),
+      Condition::And(left, right) => {
+        let middle = self.new_block();
+        self.lower_branch(left, middle, otherwise);
+        self.switch_to(middle);
+        self.lower_branch(right, then, otherwise);
+      },
+      Condition::Or(left, right) => {
+        let middle = self.new_block();
+        self.lower_branch(left, then, middle);
+        self.switch_to(middle);
+        self.lower_branch(right, then, otherwise);
+      },
+      Condition::Odd(expr) => {
+        let src = self.lower_expr(expr);
+        let dst = self.temp();
+        self.emit(Inst::Unary { dst, op: UnOp::Odd, src });
+        self.terminate(Terminator::Branch { cond: Operand::Temp(dst), then, otherwise });
+      },
+      Condition::Rel { left, op, right } => {
+        let (left, right) = (self.lower_expr(left), self.lower_expr(right));
+        let op = match op {
+          RelOp::Eq => BinOp::Eq,
+          RelOp::Neq => BinOp::Neq,
+          RelOp::Lt => BinOp::Lt,
+          RelOp::Leq => BinOp::Leq,
+          RelOp::Gt => BinOp::Gt,
+          RelOp::Geq => BinOp::Geq,
+        };
+        let cond = self.binary(op, left, right);
+        self.terminate(Terminator::Branch { cond, then, otherwise });
+      },
+    }
+  }
+
+  fn load(&mut self, var: &Var) -> Operand {
+    let dst = self.temp();
+    self.emit(Inst::Load { dst, var: var.clone() });
+    Operand::Temp(dst)
+  }
+
+  fn binary(&mut self, op: BinOp, left: Operand, right: Operand) -> Operand {
+    let dst = self.temp();
+    self.emit(Inst::Binary { dst, op, left, right });
+    Operand::Temp(dst)
+  }
+
+  /// The value of an expression made only of numbers and constants, computed
+  /// with the VM's own arithmetic. Division by zero is left for run time.
+  fn const_value(&self, expr: &Expr) -> Option<Word> {
+    match &expr.kind {
+      ExprKind::Number(value) => Some(*value),
+      ExprKind::Name(name) => match self.scopes.lookup(&name.name) {
+        Some((Symbol::Const(value), _)) => Some(*value),
+        _ => None,
+      },
+      ExprKind::Neg(inner) => Some(self.const_value(inner)?.wrapping_neg()),
+      ExprKind::Binary { left, op, right } => {
+        let (left, right) = (self.const_value(left)?, self.const_value(right)?);
+        match op {
+          ast::BinOp::Add => Some(left.wrapping_add(right)),
+          ast::BinOp::Sub => Some(left.wrapping_sub(right)),
+          ast::BinOp::Mul => Some(left.wrapping_mul(right)),
+          ast::BinOp::Div => arithmetic::divide(left, right).ok(),
+          ast::BinOp::Mod => arithmetic::modulo(left, right).ok(),
+        }
+      },
+      _ => None,
+    }
+  }
+
+  fn lower_expr(&mut self, expr: &Expr) -> Operand {
+    if let Some(value) = self.const_value(expr) {
+      return Operand::Const(value);
+    }
+    match &expr.kind {
+      ExprKind::Number(value) => Operand::Const(*value),
+      ExprKind::Name(name) => {
+        let (symbol, level) = self.lookup(name);
+        match symbol {
+          Symbol::Const(value) => Operand::Const(value),
+          Symbol::Var { offset } => {
+            let var = self.var(name, level, offset);
+            self.load(&var)
+          },
+          Symbol::Proc { .. } => Operand::Temp(self.lower_call(name, &[]).expect("procedure used as a value reached code generation")),
+          Symbol::Array { .. } => panic!("unindexed array '{}' reached code generation", name.name),
+        }
+      },
+      ExprKind::Index { name, index } => {
+        let index = self.lower_expr(index);
+        let array = self.array(name);
+        let dst = self.temp();
+        self.emit(Inst::LoadElem { dst, array, index });
+        Operand::Temp(dst)
+      },
+      ExprKind::Call { name, args } => {
+        Operand::Temp(self.lower_call(name, args).expect("procedure used as a value reached code generation"))
+      },
+      ExprKind::Neg(inner) => {
+        let src = self.lower_expr(inner);
+        let dst = self.temp();
+        self.emit(Inst::Unary { dst, op: UnOp::Neg, src });
+        Operand::Temp(dst)
+      },
+      ExprKind::Binary { left, op, right } => {
+        let (left, right) = (self.lower_expr(left), self.lower_expr(right));
+        let op = match op {
+          ast::BinOp::Add => BinOp::Add,
+          ast::BinOp::Sub => BinOp::Sub,
+          ast::BinOp::Mul => BinOp::Mul,
+          ast::BinOp::Div => BinOp::Div,
+          ast::BinOp::Mod => BinOp::Mod,
+        };
+        self.binary(op, left, right)
+      },
+    }
+  }
+}
+
+/// Lowers a program or module that has passed name resolution with the same
+/// `imports`.
+pub fn lower(program: &Program, imports: &[&Object]) -> Unit {
+  let mut lowerer = Lowerer {
+    functions: Vec::new(),
+    arrays: Vec::new(),
+    imports: Vec::new(),
+    scopes: Scopes::new(),
+    builder: Builder::default(),
+  };
+  lowerer.scopes.push();
+  for (module, object) in program.imports.iter().zip(imports) {
+    for export in &object.exports {
+      let symbol = match export.value {
+        ExportValue::Const(value) => Symbol::Const(value),
+        ExportValue::Proc { kind, arity, .. } => {
+          lowerer.imports.push(Import { module: module.name.clone(), name: export.name.clone(), kind, arity });
+          Symbol::Proc { kind, arity, address: 0, import: Some(lowerer.imports.len() - 1) }
+        },
+      };
+      lowerer.scopes.declare(&export.name, symbol);
+    }
+  }
+
+  let mut exports: Vec<(String, ExportRef)> = Vec::new();
+  match &program.module {
+    Some(_) => {
+      lowerer.declare_block(&program.block);
+      let names = program.block.consts.iter().map(|c| &c.name).chain(program.block.procs.iter().map(|p| &p.name));
+      for name in names {
+        match lowerer.lookup(name).0 {
+          Symbol::Const(value) => exports.push((name.name.clone(), ExportRef::Const(value))),
+          Symbol::Proc { address, .. } => exports.push((name.name.clone(), ExportRef::Function(address as usize))),
+          _ => {},
+        }
+      }
+    },
+    None => {
+      lowerer.functions.push(Function { name: String::new(), kind: FunctionKind::Main, params: Vec::new(), locals: 0, declared: 0, blocks: Vec::new(), temps: 0 });
+      lowerer.lower_function(0, "main", FunctionKind::Main, &[], &program.block);
+    },
+  }
+
+  Unit {
+    module: program.module.as_ref().map(|m| m.name.clone()),
+    functions: lowerer.functions,
+    arrays: lowerer.arrays,
+    imports: lowerer.imports,
+    exports,
+  }
+}
@@ -0,0 +1,482 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use crate::ast::{Block, ProcKind, Span};
+use crate::compile_error::{CompileError, Severity};
+use crate::compiler::{analyze, Analysis, CompileOptions};
+use crate::dialect::Dialect;
+use crate::formatter::{format_source, FormatOptions};
+use crate::json::Json;
+use crate::lexer::{lex_with_options, LexerOptions};
+use crate::linter::{lint, LintConfig};
+use crate::reserved_types::KEYWORDS;
+use crate::resolver::{Declaration, Origin};
+use crate::scope::Symbol;
+use crate::syntax;
+use crate::token::TokenType;
+
+// LSP enumeration values.
+const SYNC_FULL: i64 = 1;
+const SEVERITY_ERROR: i64 = 1;
+const SEVERITY_WARNING: i64 = 2;
+const COMPLETION_KEYWORD: i64 = 14;
+const SYMBOL_FUNCTION: i64 = 12;
+const METHOD_NOT_FOUND: i64 = -32601;
+const PARSE_ERROR: i64 = -32700;
+const REQUEST_FAILED: i64 = -32803;
+
+/// A language server for PL/0 files. Each open document is checked and
+/// linted again whenever it changes, and requests are answered from that
+/// last check.
+pub struct Server {
+  options: CompileOptions,
+  documents: HashMap<String, Document>,
+  shut_down: bool,
+  exited: bool,
+}
+
+struct Document {
+  text: String,
+  analysis: Analysis,
+}
+
+impl Server {
+  /// `options` are used until the client's `initializationOptions` override
+  /// them.
+  pub fn new(options: CompileOptions) -> Server {
+    Server { options, documents: HashMap::new(), shut_down: false, exited: false }
+  }
+
+  /// Whether the client has sent `exit`.
+  pub fn exited(&self) -> bool {
+    self.exited
+  }
+
+  /// Handles one message from the client and returns the messages to send
+  /// back: the response to a request, and any diagnostics it caused.
+  pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+    let id = message.get("id").clone();
+    let params = message.get("params");
+    let method = message.get("method").as_str().unwrap_or_default();
+    let result = match method {
+      "initialize" => self.initialize(params),
+      "shutdown" => {
+        self.shut_down = true;
+        Json::Null
+      },
+      "exit" => {
+        self.exited = true;
+        return Vec::new();
+      },
+      "textDocument/didOpen" => {
+        let document = params.get("textDocument");
+        return self.update(uri(params), document.get("text").as_str().unwrap_or_default().to_string());
+      },
+      "textDocument/didChange" => {
+        // With full sync the last change holds the whole text.
+        match params.get("contentChanges").as_array().last().and_then(|c| c.get("text").as_str()) {
+          Some(text) => return self.update(uri(params), text.to_string()),
+          None => return Vec::new(),
+        }
+      },
+      "textDocument/didClose" => {
+        self.documents.remove(uri(params));
+        return vec![diagnostics(uri(params), Vec::new())];
+      },
+      "textDocument/definition" => self.definition(params),
+      "textDocument/references" => self.references(params),
+      "textDocument/hover" => self.hover(params),
+      "textDocument/completion" => self.completion(),
+      "textDocument/documentSymbol" => self.document_symbols(params),
+      "textDocument/formatting" => self.formatting(params),
+      "textDocument/rename" => match self.rename(params) {
+        Ok(edit) => edit,
+        Err(msg) => return vec![error_response(id, REQUEST_FAILED, msg)],
+      },
+      _ if id == Json::Null => return Vec::new(),
+      _ => return vec![error_response(id, METHOD_NOT_FOUND, format!("Unknown method '{}'.", method))],
+    };
+    if id == Json::Null {
+      return Vec::new();
+    }
+    vec![Json::object([("jsonrpc", "2.0".into()), ("id", id), ("result", result)])]
+  }
+
+  fn initialize(&mut self, params: &Json) -> Json {
+    let settings = params.get("initializationOptions");
+    if let Some(dialect) = settings.get("dialect").as_str().and_then(Dialect::from_name) {
+      self.options.lexer.dialect = dialect;
+    }
+    if let Some(ignore_case) = settings.get("ignoreCase").as_bool() {
+      self.options.lexer.ignore_keyword_case = ignore_case;
+    }
+    for dir in settings.get("modulePath").as_array().iter().filter_map(Json::as_str) {
+      self.options.module_path.push(PathBuf::from(dir));
+    }
+
+    let capabilities = Json::object([
+      ("textDocumentSync", SYNC_FULL.into()),
+      ("definitionProvider", true.into()),
+      ("referencesProvider", true.into()),
+      ("hoverProvider", true.into()),
+      ("completionProvider", Json::object([])),
+      ("documentSymbolProvider", true.into()),
+      ("documentFormattingProvider", true.into()),
+      ("renameProvider", true.into()),
+    ]);
+    let info = Json::object([("name", "rustpl0-lsp".into()), ("version", env!("CARGO_PKG_VERSION").into())]);
+    Json::object([("capabilities", capabilities), ("serverInfo", info)])
+  }
+
+  fn update(&mut self, uri: &str, text: String) -> Vec<Json> {
+    let path = uri_to_path(uri);
+    let analysis = analyze(&path, text.as_bytes(), &self.options);
+    // A broken configuration file leaves every lint warning.
+    let config = LintConfig::for_file(Path::new(&path)).unwrap_or_default();
+    let found = match lint(&path, text.as_bytes(), &analysis, &self.options.lexer, &config) {
+      Ok(warnings) => warnings.iter().map(|warning| diagnostic(warning, &text)).collect(),
+      Err(err) => vec![diagnostic(&err, &text)],
+    };
+    self.documents.insert(uri.to_string(), Document { text, analysis });
+    vec![diagnostics(uri, found)]
+  }
+
+  /// The declaration of the identifier under the request's cursor.
+  fn declaration_at(&self, params: &Json) -> Option<(&Document, usize)> {
+    let document = self.documents.get(uri(params))?;
+    let analysis = &document.analysis;
+    let position = params.get("position");
+    let line = position.get("line").as_i64()? as i32 + 1;
+    let column = byte_column(&document.text, line, position.get("character").as_i64()?);
+    let index = &analysis.index;
+    let hit = |span: Span, length: usize| span.line == line && (span.column..=span.column + length as i32).contains(&column);
+
+    let declared = index.declarations.iter().enumerate()
+      .find(|(_, d)| !matches!(d.origin, Origin::Imported(_)) && hit(d.name.span, d.name.name.len()))
+      .map(|(i, _)| i);
+    let used = || index.references.iter()
+      .find(|r| hit(r.span, index.declarations[r.declaration].name.name.len()))
+      .map(|r| r.declaration);
+    declared.or_else(used).map(|i| (document, i))
+  }
+
+  fn definition(&self, params: &Json) -> Json {
+    match self.declaration_at(params) {
+      Some((document, i)) => location(uri(params), declaration_range(&document.text, &document.analysis.index.declarations[i])),
+      None => Json::Null,
+    }
+  }
+
+  fn references(&self, params: &Json) -> Json {
+    let Some((document, i)) = self.declaration_at(params) else {
+      return Json::Null;
+    };
+    let index = &document.analysis.index;
+    let declaration = &index.declarations[i];
+    let mut found: Vec<Json> = Vec::new();
+    if params.get("context").get("includeDeclaration").as_bool().unwrap_or(true) {
+      found.push(location(uri(params), declaration_range(&document.text, declaration)));
+    }
+    for reference in index.references.iter().filter(|r| r.declaration == i) {
+      found.push(location(uri(params), range(&document.text, reference.span, declaration.name.name.len())));
+    }
+    found.into()
+  }
+
+  fn hover(&self, params: &Json) -> Json {
+    let Some((document, i)) = self.declaration_at(params) else {
+      return Json::Null;
+    };
+    let declaration = &document.analysis.index.declarations[i];
+    let contents = Json::object([("kind", "markdown".into()), ("value", format!("```pl0\n{}\n```\n{}", describe(declaration), where_declared(declaration)).into())]);
+    Json::object([("contents", contents)])
+  }
+
+  fn completion(&self) -> Json {
+    KEYWORDS.iter()
+      .filter(|&&(_, _, dialect)| dialect == Dialect::Standard || dialect == self.options.lexer.dialect)
+      .map(|&(text, _, _)| Json::object([("label", text.into()), ("kind", COMPLETION_KEYWORD.into())]))
+      .collect::<Vec<Json>>()
+      .into()
+  }
+
+  fn document_symbols(&self, params: &Json) -> Json {
+    let Some(document) = self.documents.get(uri(params)) else {
+      return Json::Array(Vec::new());
+    };
+    match &document.analysis.program {
+      Some(program) => procedure_symbols(&document.text, &program.block).into(),
+      None => Json::Array(Vec::new()),
+    }
+  }
+
+  /// Edits that format the whole document, or `null` if it does not parse.
+  fn formatting(&self, params: &Json) -> Json {
+    let Some(document) = self.documents.get(uri(params)) else {
+      return Json::Null;
+    };
+    let indent_width = params.get("options").get("tabSize").as_i64().map_or(2, |n| n.max(0) as usize);
+    let options = FormatOptions { indent_width, ..FormatOptions::default() };
+    match format_source(&uri_to_path(uri(params)), document.text.as_bytes(), &self.options.lexer, &options) {
+      Ok(formatted) if formatted == document.text => Json::Array(Vec::new()),
+      Ok(formatted) => vec![replace_all(&document.text, formatted)].into(),
+      Err(_) => Json::Null,
+    }
+  }
+
+  /// Renames the identifier under the cursor everywhere it refers to the
+  /// same declaration, keeping the comments and layout around it.
+  fn rename(&self, params: &Json) -> Result<Json, String> {
+    let document = self.documents.get(uri(params)).ok_or("Cannot rename: the document is not open.")?;
+    if document.analysis.error.is_some() {
+      return Err("Cannot rename: the document has errors.".to_string());
+    }
+    let (_, i) = self.declaration_at(params).ok_or("Cannot rename: there is no identifier here.")?;
+    let index = &document.analysis.index;
+    let declaration = &index.declarations[i];
+    if let Origin::Imported(module) = &declaration.origin {
+      return Err(format!("Cannot rename '{}': it is declared in module '{}'.", declaration.name.name, module));
+    }
+    let new_name = params.get("newName").as_str().unwrap_or_default();
+    if !is_identifier(new_name, &self.options.lexer) {
+      return Err(format!("Cannot rename to '{}': it is not an identifier.", new_name));
+    }
+    if index.declarations.iter().any(|d| d.name.name == new_name) {
+      return Err(format!("Cannot rename to '{}': the name is already declared.", new_name));
+    }
+
+    let mut spans: Vec<(i32, i32)> = vec![(declaration.name.span.line, declaration.name.span.column)];
+    spans.extend(index.references.iter().filter(|r| r.declaration == i).map(|r| (r.span.line, r.span.column)));
+    let lexer = LexerOptions { keep_trivia: true, ..self.options.lexer.clone() };
+    let tokens = lex_with_options(&uri_to_path(uri(params)), document.text.as_bytes(), lexer).map_err(|err| err.message)?;
+    let mut tree = syntax::parse(&tokens);
+    tree.replace_tokens(&spans, new_name);
+    let renamed = String::from_utf8_lossy(&tree.to_source()).into_owned();
+
+    let changes = Json::Object(vec![(uri(params).to_string(), vec![replace_all(&document.text, renamed)].into())]);
+    Ok(Json::object([("changes", changes)]))
+  }
+}
+
+/// Whether `name` lexes as a single identifier.
+fn is_identifier(name: &str, options: &LexerOptions) -> bool {
+  match lex_with_options("rename", name.as_bytes(), options.clone()) {
+    Ok(tokens) => tokens.len() == 2 && tokens[0].typ == TokenType::Identsym && tokens[0].text == name,
+    Err(_) => false,
+  }
+}
+
+/// A text edit that replaces all of `text` with `new_text`.
+fn replace_all(text: &str, new_text: String) -> Json {
+  let last_line = text.rsplit('\n').next().unwrap_or_default();
+  let end = Json::object([
+    ("line", (text.matches('\n').count() as i64).into()),
+    ("character", (last_line.encode_utf16().count() as i64).into()),
+  ]);
+  let start = Json::object([("line", 0.into()), ("character", 0.into())]);
+  Json::object([("range", Json::object([("start", start), ("end", end)])), ("newText", new_text.into())])
+}
+
+fn procedure_symbols(text: &str, block: &Block) -> Vec<Json> {
+  block.procs.iter().map(|p| {
+    let kind = if p.kind == ProcKind::Function { "function" } else { "procedure" };
+    let params: Vec<&str> = p.params.iter().map(|param| param.name.as_str()).collect();
+    let name_range = range(text, p.name.span, p.name.name.len());
+    Json::object([
+      ("name", p.name.name.as_str().into()),
+      ("detail", format!("{}({})", kind, params.join(", ")).into()),
+      ("kind", SYMBOL_FUNCTION.into()),
+      ("range", name_range.clone()),
+      ("selectionRange", name_range),
+      ("children", procedure_symbols(text, &p.block).into()),
+    ])
+  }).collect()
+}
+
+/// How a declaration reads in the source.
+fn describe(declaration: &Declaration) -> String {
+  let name = &declaration.name.name;
+  match (declaration.symbol, &declaration.origin) {
+    (Symbol::Const(value), _) => format!("const {} = {}", name, value),
+    (Symbol::Var { .. }, Origin::Parameter) => format!("parameter {}", name),
+    (Symbol::Var { .. }, _) => format!("var {}", name),
+    (Symbol::Array { length, .. }, _) => format!("var {}[{}]", name, length),
+    (Symbol::Proc { kind, arity, .. }, origin) => {
+      let kind = if kind == ProcKind::Function { "function" } else { "procedure" };
+      match origin {
+        Origin::Imported(_) => format!("{} {} ({} parameter(s))", kind, name, arity),
+        _ => format!("{} {}({})", kind, name, declaration.params.join(", ")),
+      }
+    },
+  }
+}
+
+fn where_declared(declaration: &Declaration) -> String {
+  match &declaration.origin {
+    Origin::Imported(module) => format!("Imported from module `{}`.", module),
+    _ if declaration.level == 0 => "Declared at level 0 (global).".to_string(),
+    _ => format!("Declared at level {}.", declaration.level),
+  }
+}
+
+/// An imported name is declared by the module name in its `import`.
+fn declaration_range(text: &str, declaration: &Declaration) -> Json {
+  match &declaration.origin {
+    Origin::Imported(module) => range(text, declaration.name.span, module.len()),
+    _ => range(text, declaration.name.span, declaration.name.name.len()),
+  }
+}
+
+/// Line `line` of `text`, counting from 1 and without its line break.
+fn line_text(text: &str, line: i32) -> &str {
+  text.split('\n').nth((line - 1).max(0) as usize).unwrap_or_default()
+}
+
+/// The LSP `character` for byte column `column` of a line. The lexer counts
+/// columns in bytes from 1, and LSP counts UTF-16 code units from 0.
+fn utf16_column(text: &str, line: i32, column: i32) -> i64 {
+  let bytes = (column - 1).max(0) as usize;
+  let line = line_text(text, line);
+  let units: usize = line.char_indices().take_while(|&(i, _)| i < bytes).map(|(_, c)| c.len_utf16()).sum();
+  (units + bytes.saturating_sub(line.len())) as i64
+}
+
+/// The byte column of LSP `character` on a line; the reverse of
+/// `utf16_column`.
+fn byte_column(text: &str, line: i32, character: i64) -> i32 {
+  let line = line_text(text, line);
+  let mut units: i64 = 0;
+  for (i, c) in line.char_indices() {
+    if units >= character {
+      return i as i32 + 1;
+    }
+    units += c.len_utf16() as i64;
+  }
+  (line.len() as i64 + (character - units).max(0)) as i32 + 1
+}
+
+fn position(text: &str, line: i32, column: i32) -> Json {
+  Json::object([("line", ((line - 1).max(0) as i64).into()), ("character", utf16_column(text, line, column).into())])
+}
+
+fn range(text: &str, span: Span, length: usize) -> Json {
+  Json::object([("start", position(text, span.line, span.column)), ("end", position(text, span.line, span.column + length as i32))])
+}
+
+fn location(uri: &str, range: Json) -> Json {
+  Json::object([("uri", uri.into()), ("range", range)])
+}
+
+/// Underlines the word the error points at, or a single character. A lint
+/// finding carries the lint's ID as its code.
+fn diagnostic(err: &CompileError, text: &str) -> Json {
+  let line = text.lines().nth((err.line - 1).max(0) as usize).unwrap_or_default();
+  let word = line.bytes().skip((err.column - 1).max(0) as usize).take_while(|b| b.is_ascii_alphanumeric()).count();
+  let span = Span { line: err.line, column: err.column };
+  let severity = match err.severity {
+    Severity::Error => SEVERITY_ERROR,
+    Severity::Warning => SEVERITY_WARNING,
+  };
+  let mut diagnostic = Json::object([
+    ("range", range(text, span, word.max(1))),
+    ("severity", severity.into()),
+    ("source", "rustpl0".into()),
+    ("message", err.message.as_str().into()),
+  ]);
+  if let (Some(lint), Json::Object(members)) = (err.lint, &mut diagnostic) {
+    members.push(("code".to_string(), lint.id().into()));
+  }
+  diagnostic
+}
+
+fn diagnostics(uri: &str, found: Vec<Json>) -> Json {
+  let params = Json::object([("uri", uri.into()), ("diagnostics", found.into())]);
+  Json::object([("jsonrpc", "2.0".into()), ("method", "textDocument/publishDiagnostics".into()), ("params", params)])
+}
+
+fn error_response(id: Json, code: i64, message: String) -> Json {
+  let error = Json::object([("code", code.into()), ("message", message.into())]);
+  Json::object([("jsonrpc", "2.0".into()), ("id", id), ("error", error)])
+}
+
+fn uri(params: &Json) -> &str {
+  params.get("textDocument").get("uri").as_str().unwrap_or_default()
+}
+
+/// The file a `file://` URI names, so that imports are looked up next to it.
+fn uri_to_path(uri: &str) -> String {
+  let Some(path) = uri.strip_prefix("file://") else {
+    return uri.to_string();
+  };
+  let mut bytes: Vec<u8> = Vec::new();
+  let mut rest = path.as_bytes();
+  while let Some((&b, tail)) = rest.split_first() {
+    match (b, tail.get(..2).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())) {
+      (b'%', Some(decoded)) => {
+        bytes.push(decoded);
+        rest = &tail[2..];
+      },
+      _ => {
+        bytes.push(b);
+        rest = tail;
+      },
+    }
+  }
+  String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// Reads one `Content-Length` framed message, or `None` at end of input.
+pub fn read_message(input: &mut dyn BufRead) -> Result<Option<String>, String> {
+  let mut length: Option<usize> = None;
+  loop {
+    let mut header = String::new();
+    if input.read_line(&mut header).map_err(|err| err.to_string())? == 0 {
+      return Ok(None);
+    }
+    let header = header.trim_end();
+    if header.is_empty() {
+      break;
+    }
+    if let Some(value) = header.strip_prefix("Content-Length:") {
+      length = Some(value.trim().parse().map_err(|_| format!("Error: Bad Content-Length `{}`!", value.trim()))?);
+    }
+  }
+  let length = length.ok_or("Error: Message has no Content-Length header!")?;
+  let mut body = vec![0; length];
+  input.read_exact(&mut body).map_err(|err| err.to_string())?;
+  String::from_utf8(body).map(Some).map_err(|_| "Error: Message is not UTF-8!".to_string())
+}
+
+pub fn write_message(output: &mut dyn Write, message: &Json) -> std::io::Result<()> {
+  let body = message.to_string();
+  write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+  output.flush()
+}
+
+/// Serves one client over `input` and `output` until it sends `exit` or
+/// closes the input. Returns the process exit code: 0 only if the client
+/// asked for a shutdown first.
+pub fn run(options: CompileOptions, input: &mut dyn BufRead, output: &mut dyn Write) -> i32 {
+  let mut server = Server::new(options);
+  loop {
+    let replies = match read_message(input) {
+      Ok(Some(body)) => match Json::parse(&body) {
+        Ok(message) => server.handle(&message),
+        Err(msg) => vec![error_response(Json::Null, PARSE_ERROR, msg)],
+      },
+      Ok(None) => return 1,
+      Err(msg) => {
+        eprintln!("{}", msg);
+        return 1;
+      },
+    };
+    for reply in &replies {
+      if write_message(output, reply).is_err() {
+        return 1;
+      }
+    }
+    if server.exited() {
+      return if server.shut_down { 0 } else { 1 };
+    }
+  }
+}
@@ -0,0 +1,23 @@
+use crate::machine::machine_types::Word;
+
+// Integer division truncates toward zero and the remainder takes the sign of
+// the dividend, so `a == (a / b) * b + a mod b` always holds. Like the rest
+// of the VM's arithmetic, results wrap on overflow: the only such case,
+// MIN / -1, gives MIN (and MIN mod -1 gives 0).
+//
+// The VM and the compiler's constant folding both go through these, so a
+// folded expression always has the value it would have had at run time.
+
+pub fn divide(left: Word, right: Word) -> Result<Word, String> {
+  if right == 0 {
+    return Err("Error: Division by zero!".to_string());
+  }
+  Ok(left.wrapping_div(right))
+}
+
+pub fn modulo(left: Word, right: Word) -> Result<Word, String> {
+  if right == 0 {
+    return Err("Error: Modulo by zero!".to_string());
+  }
+  Ok(left.wrapping_rem(right))
+}
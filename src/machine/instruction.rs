@@ -1,6 +1,7 @@
+use std::io::Write;
 use super::machine_types::{Address, Instruction};
 
-const NUM_OPCODES: usize = 31;
+const NUM_OPCODES: usize = 32;
 
 // NOP, LIT, RTN, CAL, POP,
 //   PSI, LOD, STO, INC, JMP,
@@ -8,7 +9,7 @@ const NUM_OPCODES: usize = 31;
 //   NEG, ADD, SUB, MUL, DIV,
 //   MOD, EQL, NEQ, LSS, LEQ,
 //   GTR, GEQ, PSP, PBP, PPC,
-//   JMI,
+//   JMI, CHK,
 
 const OPCODES: [&str; NUM_OPCODES] = [
   "NOP", "LIT", "RTN", "CAL", "POP",
@@ -17,33 +18,80 @@ const OPCODES: [&str; NUM_OPCODES] = [
   "NEG", "ADD", "SUB", "MUL", "DIV",
   "MOD", "EQL", "NEQ", "LSS", "LEQ",
   "GTR", "GEQ", "PSP", "PBP", "PPC",
-  "JMI",
+  "JMI", "CHK",
 ];
 
+// Op codes, in the same order as OPCODES.
+pub const NOP: i32 = 0;
+pub const LIT: i32 = 1;
+pub const RTN: i32 = 2;
+pub const CAL: i32 = 3;
+pub const POP: i32 = 4;
+pub const PSI: i32 = 5;
+pub const LOD: i32 = 6;
+pub const STO: i32 = 7;
+pub const INC: i32 = 8;
+pub const JMP: i32 = 9;
+pub const JPC: i32 = 10;
+pub const CHO: i32 = 11;
+pub const CHI: i32 = 12;
+pub const HLT: i32 = 13;
+pub const NDB: i32 = 14;
+pub const NEG: i32 = 15;
+pub const ADD: i32 = 16;
+pub const SUB: i32 = 17;
+pub const MUL: i32 = 18;
+pub const DIV: i32 = 19;
+pub const MOD: i32 = 20;
+pub const EQL: i32 = 21;
+pub const NEQ: i32 = 22;
+pub const LSS: i32 = 23;
+pub const LEQ: i32 = 24;
+pub const GTR: i32 = 25;
+pub const GEQ: i32 = 26;
+pub const PSP: i32 = 27;
+pub const PBP: i32 = 28;
+pub const PPC: i32 = 29;
+pub const JMI: i32 = 30;
+/// Stops the program if the index on top of the stack is outside array `m`
+/// of the program's table. No other instruction can stop with an error.
+pub const CHK: i32 = 31;
+
+// Output modes, selected by CHO's operand.
+/// Prints the number as `OUTPUT: n` on a line of its own.
+pub const CHO_LINE: i32 = 0;
+/// Prints the character with that code.
+pub const CHO_CHAR: i32 = 1;
+/// Prints the number alone, with no newline.
+pub const CHO_NUMBER: i32 = 2;
+
 fn legal_op_code(op: i32) -> bool {
   0 <= op && op < NUM_OPCODES as i32
 }
 
-pub fn read_instruction(instruction_line: &str) -> Instruction {
-  let mut new_instruction: Instruction = Instruction { op: 0, m: 0 };
+/// Parses one `op m` line of a program. Lines that do not have exactly two
+/// fields are not instructions and give `Ok(None)`.
+pub fn read_instruction(instruction_line: &str) -> Result<Option<Instruction>, String> {
   let instruction_line: Vec<&str> = instruction_line.split_whitespace().collect();
 
   if instruction_line.len() != 2 {
-    new_instruction.op = -1;
-    return new_instruction;
+    return Ok(None);
   }
-  new_instruction.op = instruction_line[0].parse::<i32>().unwrap();
-  new_instruction.m = instruction_line[1].parse::<i32>().unwrap();
 
-  if !legal_op_code(new_instruction.op) {
-    panic!("Error: Illegal op code! (Op code: {})", new_instruction.op);
+  let op = instruction_line[0].parse::<i32>()
+    .map_err(|_| format!("Error: Op code is not a number! (Op code: {})", instruction_line[0]))?;
+  let m = instruction_line[1].parse::<i32>()
+    .map_err(|_| format!("Error: Operand is not a number! (M: {})", instruction_line[1]))?;
+
+  if !legal_op_code(op) {
+    return Err(format!("Error: Illegal op code! (Op code: {})", op));
   }
 
-  new_instruction
+  Ok(Some(Instruction { op, m }))
 }
 
 
-fn mnemonic(op: i32) -> String {
+pub fn mnemonic(op: i32) -> String {
   if !legal_op_code(op) {
     panic!("Error: Illegal op code! (Op code: {})", op);
   }
@@ -51,12 +99,12 @@ fn mnemonic(op: i32) -> String {
   OPCODES[op as usize].to_string()
 }
 
-pub fn print_instruction_heading() {
-  println!("{:>5} {:>5} {:>5}", "ADDR", "OP", "M");
+pub fn print_instruction_heading(out: &mut dyn Write) {
+  let _ = writeln!(out, "{:>5} {:>5} {:>5}", "ADDR", "OP", "M");
 }
 
-pub fn print_formatted_instruction(address: Address, instruction: &Instruction) {
-  println!("{:>5} {:>5} {:>5}", address, mnemonic(instruction.op), instruction.m);
+pub fn print_formatted_instruction(out: &mut dyn Write, address: Address, instruction: &Instruction) {
+  let _ = writeln!(out, "{:>5} {:>5} {:>5}", address, mnemonic(instruction.op), instruction.m);
 }
 
 pub fn print_machine_instruction(instruction: Instruction) {
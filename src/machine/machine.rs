@@ -1,21 +1,25 @@
-use std::{fs::File, io::{Read, stdin, stdout, Write}};
-use super::machine_types::{Word, Address, Instruction};
+use std::{fs::File, io::{BufRead, Read, stdin, stdout, Write}, process::exit};
+use super::machine_types::{Word, Address, Instruction, ArrayInfo, Executable};
 use super::stack::Stack;
 use super::instruction;
+use super::arithmetic;
 
 const MAX_CODE_LENGTH: usize = 512;
-struct Machine {
+struct Machine<'a> {
   pc: Address,
   halt: bool,
   no_out: bool,
   code: Vec<Instruction>,
+  arrays: Vec<ArrayInfo>,
   debug: bool,
+  step_limit: Option<u64>,
+  input: &'a mut dyn BufRead,
+  output: &'a mut dyn Write,
 }
 
-impl Machine {
-  fn initialize() -> Self {
-    let new_machine = Machine { pc: 0, halt: false, no_out: true, code: Vec::new(), debug: false };
-    new_machine
+impl<'a> Machine<'a> {
+  fn initialize(input: &'a mut dyn BufRead, output: &'a mut dyn Write) -> Self {
+    Machine { pc: 0, halt: false, no_out: true, code: Vec::new(), arrays: Vec::new(), debug: false, step_limit: None, input, output }
   }
 
   fn enable_debug(&mut self) {
@@ -26,368 +30,500 @@ impl Machine {
     self.no_out = false;
   }
 
-  fn run_machine(&mut self, stack: &mut Stack, program_length: i32) {
-    println!("Running...");
+  fn run_machine(&mut self, stack: &mut Stack, program_length: i32) -> Result<(), String> {
+    let _ = writeln!(self.output, "Running...");
     if !self.debug && self.no_out {
-      println!("Hint: to enable debug mode, run the program with the '-debug' flag.");
-      println!("To enable tracing, run the program with the '-trace' flag.");
+      let _ = writeln!(self.output, "Hint: to enable debug mode, run the program with the '-debug' flag.");
+      let _ = writeln!(self.output, "To enable tracing, run the program with the '-trace' flag.");
     }
-    println!("\n");
+    let _ = writeln!(self.output, "\n");
 
     if !self.no_out {
       if self.debug {
-        println!("Program length: {}", program_length);
+        let _ = writeln!(self.output, "Program length: {}", program_length);
       }
       self.print_program(program_length);
-      println!("\nTracing...");
+      let _ = writeln!(self.output, "\nTracing...");
       self.print_machine(stack);
     }
 
+    self.run_steps(stack)
+  }
+
+  fn run_steps(&mut self, stack: &mut Stack) -> Result<(), String> {
+    let mut steps: u64 = 0;
     while !self.halt {
-      self.okay_to_run();
+      if self.step_limit.is_some_and(|limit| steps >= limit) {
+        return Err(format!("Error: Step limit reached! (Steps: {})", steps));
+      }
+      self.okay_to_run()?;
       let instr = self.code[self.pc as usize].copy();
-      self.trace_and_execute(stack, &instr);
+      self.trace_and_execute(stack, &instr)?;
+      steps += 1;
     }
+
+    Ok(())
   }
 
-  fn okay_to_run(&self)  {
-    if !self.pc >= 0 || !(self.pc < self.code.len().try_into().unwrap()) {
-      panic!("Error: Program Counter out of bounds! (PC: {}, Program Length: {})", self.pc, self.code.len());
+  fn okay_to_run(&self) -> Result<(), String> {
+    if self.pc < 0 || self.pc >= self.code.len() as Address {
+      return Err(format!("Error: Program Counter out of bounds! (PC: {}, Program Length: {})", self.pc, self.code.len()));
     }
+    Ok(())
   }
 
-  fn read_program(&mut self, file_contents: &String) -> i32 {
-    let mut program: Vec<Instruction> = Vec::new();
-
-    let mut count: i32 = 0;
-    for line in file_contents.lines() {
-      count += 1;
-      let instr = instruction::read_instruction(line);
-      if instr.op == -1 {
-        count -= 1;
-        continue;
-      }
-      program.push(instr);
-    }
+  fn load_code(&mut self, program: Executable) -> Result<i32, String> {
+    let count = program.code.len() as i32;
 
     if count >= MAX_CODE_LENGTH as i32 {
-      panic!("Error: Too many instructions! (Code length: {}, Max: {})", count, MAX_CODE_LENGTH);
+      return Err(format!("Error: Too many instructions! (Code length: {}, Max: {})", count, MAX_CODE_LENGTH));
     }
 
-    self.code = program;
-    count
+    self.code = program.code;
+    self.arrays = program.arrays;
+    Ok(count)
   }
 
-  fn print_program(&self, program_length: i32) {
-    instruction::print_instruction_heading();
+  fn print_program(&mut self, program_length: i32) {
+    instruction::print_instruction_heading(self.output);
     for i in 0..program_length {
-      instruction::print_formatted_instruction(i, &self.code[i as usize]);
+      instruction::print_formatted_instruction(self.output, i, &self.code[i as usize]);
     }
   }
 
-  fn print_machine(&self, stack: &Stack) {
-    println!("Machine:");
-    println!("PC: {}, BP: {}, SP: {}", self.pc, stack.ar_base(), stack.size());
-    println!("Stack:");
-    stack.print_stack();
+  fn print_machine(&mut self, stack: &Stack) {
+    let _ = writeln!(self.output, "Machine:");
+    let _ = writeln!(self.output, "PC: {}, BP: {}, SP: {}", self.pc, stack.ar_base(), stack.size());
+    let _ = writeln!(self.output, "Stack:");
+    stack.print_stack(self.output);
   }
 
-  fn trace_and_execute(&mut self, stack: &mut Stack, instr: &Instruction) {
+  fn trace_and_execute(&mut self, stack: &mut Stack, instr: &Instruction) -> Result<(), String> {
     if !self.no_out {
-      print!("--> addr: ");
-      instruction::print_formatted_instruction(self.pc, instr);
+      let _ = write!(self.output, "--> addr: ");
+      instruction::print_formatted_instruction(self.output, self.pc, instr);
     }
 
-    self.execute(stack, instr);
+    self.execute(stack, instr)?;
 
     if !self.no_out {
       self.print_machine(stack);
     }
+
+    Ok(())
+  }
+
+  fn print_debug_msg(&mut self, msg: &str) {
+    let _ = writeln!(self.output, "{}\n{:->40}", msg, "");
   }
 
-  fn execute(&mut self, stack: &mut Stack, instr: &Instruction) {
+  fn execute(&mut self, stack: &mut Stack, instr: &Instruction) -> Result<(), String> {
     self.pc += 1;
     self.halt = false;
 
     match instr.op {
       0 => { // NOP
         if self.debug {
-          print_debug_msg(format!("NOP"));
+          self.print_debug_msg("NOP");
         }
       },
       1 => { // LIT
         if self.debug {
-          print_debug_msg(format!("LIT {}", instr.m));
+          self.print_debug_msg(&format!("LIT {}", instr.m));
         }
-        stack.push(instr.m);
+        stack.push(instr.m)?;
       },
       2 => { // RTN
         if self.debug {
-          print_debug_msg(format!("RTN"));
+          self.print_debug_msg("RTN");
         }
-        stack.return_stack(self.pc);
+        self.pc = stack.return_stack()?;
       },
       3 => { // CAL
         if self.debug {
-          print_debug_msg(format!("CAL"));
+          self.print_debug_msg(&format!("CAL {}", instr.m));
         }
-        stack.call(self.pc);
+        let static_link: Address = stack.pop()?;
+        stack.call(static_link, self.pc)?;
+        self.pc = instr.m;
       },
       4 => { // POP
         if self.debug {
-          print_debug_msg(format!("POP"));
+          self.print_debug_msg("POP");
         }
-        stack.pop();
+        stack.pop()?;
       },
       5 => { // PSI
         if self.debug {
-          print_debug_msg(format!("PSI"));
+          self.print_debug_msg("PSI");
         }
-        let address: Address = stack.pop();
-        stack.push(stack.fetch(address));
+        let address: Address = stack.pop()?;
+        stack.push(stack.fetch(address)?)?;
       },
       6 => { // LOD
         if self.debug {
-          print_debug_msg(format!("LOD {}", instr.m));
+          self.print_debug_msg(&format!("LOD {}", instr.m));
         }
-        let address: Address = stack.pop() + instr.m;
-        stack.push(stack.fetch(address));
+        let address: Address = stack.pop()?.wrapping_add(instr.m);
+        stack.push(stack.fetch(address)?)?;
       },
       7 => { // STO
         if self.debug {
-          print_debug_msg(format!("STO {}", instr.m));
+          self.print_debug_msg(&format!("STO {}", instr.m));
         }
-        let word: Word = stack.pop();
-        let destination: Address = stack.pop() + instr.m;
-        stack.assign(destination, word);
+        let word: Word = stack.pop()?;
+        let destination: Address = stack.pop()?.wrapping_add(instr.m);
+        stack.assign(destination, word)?;
       },
       8 => { // INC
         if self.debug {
-          print_debug_msg(format!("INC {}", instr.m));
+          self.print_debug_msg(&format!("INC {}", instr.m));
         }
-        stack.allocate(instr.m);
+        stack.allocate(instr.m)?;
       },
       9 => { // JMP
         if self.debug {
-          print_debug_msg(format!("JMP {}", instr.m));
+          self.print_debug_msg(&format!("JMP {}", instr.m));
         }
-        self.pc += instr.m - 1;
+        self.pc = self.pc.wrapping_add(instr.m.wrapping_sub(1));
       },
       10 => { // JPC
         if self.debug {
-          print_debug_msg(format!("JPC {}", instr.m));
+          self.print_debug_msg(&format!("JPC {}", instr.m));
         }
-        if stack.pop() != 0 {
-          self.pc += instr.m - 1;
+        if stack.pop()? != 0 {
+          self.pc = self.pc.wrapping_add(instr.m.wrapping_sub(1));
         }
       },
       11 => { // CHO
         if self.debug {
-          print_debug_msg(format!("CHO"));
-        }
-        let output: Word = stack.pop();
-        println!("OUTPUT: {}", output);
+          self.print_debug_msg(&format!("CHO {}", instr.m));
+        }
+        let output: Word = stack.pop()?;
+        let _ = match instr.m {
+          instruction::CHO_LINE => writeln!(self.output, "OUTPUT: {}", output),
+          instruction::CHO_CHAR => match char::from_u32(output as u32) {
+            Some(c) if output >= 0 => write!(self.output, "{}", c),
+            _ => return Err(format!("Error: {} is not a valid character code!", output)),
+          },
+          instruction::CHO_NUMBER => write!(self.output, "{}", output),
+          _ => return Err(format!("Error: Unknown output mode {} for CHO!", instr.m)),
+        };
       },
       12 => { // CHI
         if self.debug {
-          print_debug_msg(format!("CHI"));
+          self.print_debug_msg("CHI");
         }
-        let input: Word = read_console_input();
-        stack.push(input);
+        let input: Word = self.read_console_input()?;
+        stack.push(input)?;
       },
       13 => { // HLT
         if self.debug {
-          print_debug_msg(format!("HLT"));
+          self.print_debug_msg("HLT");
         }
         self.halt = true;
       },
       14 => { // NDB
         if self.debug {
-          print_debug_msg(format!("NDB"));
+          self.print_debug_msg("NDB");
         }
-        println!("\nno_out");
+        let _ = writeln!(self.output, "\nno_out");
         self.no_out = true;
       },
       15 => { // NEG
         if self.debug {
-          print_debug_msg(format!("NEG"));
+          self.print_debug_msg("NEG");
         }
-        let neg_value: Word = - stack.pop();
-        stack.push(neg_value);
+        let neg_value: Word = stack.pop()?.wrapping_neg();
+        stack.push(neg_value)?;
       },
       16 => { // ADD
         if self.debug {
-          print_debug_msg(format!("ADD"));
+          self.print_debug_msg("ADD");
         }
-        let top_value: Word = stack.pop();
-        let bottom_value: Word = stack.pop();
-        stack.push(bottom_value + top_value);
+        let top_value: Word = stack.pop()?;
+        let bottom_value: Word = stack.pop()?;
+        stack.push(bottom_value.wrapping_add(top_value))?;
       },
       17 => { // SUB
         if self.debug {
-          print_debug_msg(format!("SUB"));
+          self.print_debug_msg("SUB");
         }
-        let top_value: Word = stack.pop();
-        let bottom_value: Word = stack.pop();
-        stack.push(bottom_value - top_value);
+        let top_value: Word = stack.pop()?;
+        let bottom_value: Word = stack.pop()?;
+        stack.push(bottom_value.wrapping_sub(top_value))?;
       },
       18 => { // MUL
         if self.debug {
-          print_debug_msg(format!("MUL"));
+          self.print_debug_msg("MUL");
         }
-        let top_value: Word = stack.pop();
-        let bottom_value: Word = stack.pop();
-        stack.push(bottom_value * top_value);
+        let top_value: Word = stack.pop()?;
+        let bottom_value: Word = stack.pop()?;
+        stack.push(bottom_value.wrapping_mul(top_value))?;
       },
       19 => { // DIV
         if self.debug {
-          print_debug_msg(format!("DIV"));
-        }
-        let top_value: Word = stack.pop();
-        if top_value == 0 {
-          panic!("Error: Division by zero!");
+          self.print_debug_msg("DIV");
         }
-        let bottom_value: Word = stack.pop();
-        stack.push(bottom_value / top_value);
+        let top_value: Word = stack.pop()?;
+        let bottom_value: Word = stack.pop()?;
+        stack.push(arithmetic::divide(bottom_value, top_value)?)?;
       },
       20 => { // MOD
         if self.debug {
-          print_debug_msg(format!("MOD"));
-        }
-        let top_value: Word = stack.pop();
-        if top_value == 0 {
-          panic!("Error: Modulo by zero!");
+          self.print_debug_msg("MOD");
         }
-        let bottom_value: Word = stack.pop();
-        stack.push(bottom_value % top_value);
+        let top_value: Word = stack.pop()?;
+        let bottom_value: Word = stack.pop()?;
+        stack.push(arithmetic::modulo(bottom_value, top_value)?)?;
       },
       21 => { // EQL
         if self.debug {
-          print_debug_msg(format!("EQL"));
+          self.print_debug_msg("EQL");
         }
-        let top_value: Word = stack.pop();
-        let bottom_value: Word = stack.pop();
+        let top_value: Word = stack.pop()?;
+        let bottom_value: Word = stack.pop()?;
         match bottom_value == top_value {
-          true => stack.push(1),
-          false => stack.push(0),
+          true => stack.push(1)?,
+          false => stack.push(0)?,
         }
       },
       22 => { // NEQ
         if self.debug {
-          print_debug_msg(format!("NEQ"));
+          self.print_debug_msg("NEQ");
         }
-        let top_value: Word = stack.pop();
-        let bottom_value: Word = stack.pop();
+        let top_value: Word = stack.pop()?;
+        let bottom_value: Word = stack.pop()?;
         match bottom_value != top_value {
-          true => stack.push(1),
-          false => stack.push(0),
+          true => stack.push(1)?,
+          false => stack.push(0)?,
         }
       },
       23 => { // LSS
         if self.debug {
-          print_debug_msg(format!("LSS"));
+          self.print_debug_msg("LSS");
         }
-        let top_value: Word = stack.pop();
-        let bottom_value: Word = stack.pop();
+        let top_value: Word = stack.pop()?;
+        let bottom_value: Word = stack.pop()?;
         match bottom_value < top_value {
-          true => stack.push(1),
-          false => stack.push(0),
+          true => stack.push(1)?,
+          false => stack.push(0)?,
         }
       },
       24 => { // LEQ
         if self.debug {
-          print_debug_msg(format!("LEQ"));
+          self.print_debug_msg("LEQ");
         }
-        let top_value: Word = stack.pop();
-        let bottom_value: Word = stack.pop();
+        let top_value: Word = stack.pop()?;
+        let bottom_value: Word = stack.pop()?;
         match bottom_value <= top_value {
-          true => stack.push(1),
-          false => stack.push(0),
+          true => stack.push(1)?,
+          false => stack.push(0)?,
         }
       },
       25 => { // GTR
         if self.debug {
-          print_debug_msg(format!("GTR"));
+          self.print_debug_msg("GTR");
         }
-        let top_value: Word = stack.pop();
-        let bottom_value: Word = stack.pop();
+        let top_value: Word = stack.pop()?;
+        let bottom_value: Word = stack.pop()?;
         match bottom_value > top_value {
-          true => stack.push(1),
-          false => stack.push(0),
+          true => stack.push(1)?,
+          false => stack.push(0)?,
         }
       },
       26 => { // GEQ
         if self.debug {
-          print_debug_msg(format!("GEQ"));
+          self.print_debug_msg("GEQ");
         }
-        let top_value: Word = stack.pop();
-        let bottom_value: Word = stack.pop();
+        let top_value: Word = stack.pop()?;
+        let bottom_value: Word = stack.pop()?;
         match bottom_value >= top_value {
-          true => stack.push(1),
-          false => stack.push(0),
+          true => stack.push(1)?,
+          false => stack.push(0)?,
         }
       },
       27 => { // PSP
         if self.debug {
-          print_debug_msg(format!("PSP"));
+          self.print_debug_msg("PSP");
         }
-        stack.push(stack.size());
+        stack.push(stack.size())?;
       },
       28 => { // PBP
         if self.debug {
-          print_debug_msg(format!("PBP"));
+          self.print_debug_msg("PBP");
         }
-        stack.push(stack.ar_base());
+        stack.push(stack.ar_base())?;
       },
       29 => { // PPC
         if self.debug {
-          print_debug_msg(format!("PPC"));
+          self.print_debug_msg("PPC");
         }
-        stack.push(self.pc);
+        stack.push(self.pc)?;
       },
       30 => { // JMI
         if self.debug {
-          print_debug_msg(format!("JMI"));
+          self.print_debug_msg("JMI");
+        }
+        self.pc = stack.pop()?;
+      },
+      31 => { // CHK
+        if self.debug {
+          self.print_debug_msg(&format!("CHK {}", instr.m));
+        }
+        let index: Word = stack.peek()?;
+        let (name, length) = match self.arrays.get(instr.m as usize) {
+          Some(array) => (array.name.clone(), array.length),
+          None => return Err(format!("Error: CHK refers to unknown array {}!", instr.m)),
+        };
+        if index < 0 || index >= length {
+          return Err(format!("Error: Index {} is out of bounds for array '{}' of length {}!", index, name, length));
         }
-        self.pc = stack.pop();
       },
       _ => {
-        panic!("Error: Undefined opcode: {}!", instr.op);
+        return Err(format!("Error: Undefined opcode: {}!", instr.op));
       }
     }
+
+    Ok(())
   }
-}
 
-pub fn start_machine(file_name: &String, debug: bool, trace: bool) {
-  let mut stack: Stack = Stack::initialize();
-  let mut machine: Machine = Machine::initialize();
+  fn read_console_input(&mut self) -> Result<i32, String> {
+    let _ = write!(self.output, "INPUT > ");
+    let _ = self.output.flush();
+    let mut input = String::new();
+    let res = self.input.read_line(&mut input);
+
+    match res {
+      Ok(_) => {},
+      Err(_) => return Err("Error: Could not read input!".to_string()),
+    }
+
+    let value: i32 = match input.trim().parse() {
+      Ok(num) => num,
+      Err(_) => {
+        let _ = writeln!(self.output, "ERROR: Could not parse input! Defaulting to 0...");
+        0
+      },
+    };
 
+    Ok(value)
+  }
+}
+
+pub fn start_machine(file_name: &str, debug: bool, trace: bool) {
   if debug {
     println!("DEBUG: ON");
-    machine.enable_debug();
   }
 
   if trace {
     println!("TRACE: ON");
-    machine.enable_trace();
   }
 
-  if machine.debug {
+  if debug {
     println!("Reading file `{}`...", file_name);
   }
 
   let file_contents: String = open_file(file_name);
 
-  if machine.debug {
+  if debug {
     println!("File contents:\n\n{}", file_contents);
   }
 
-  let program_length = machine.read_program(&file_contents);
+  if let Err(msg) = run_program(&file_contents, debug, trace, None, &mut stdin().lock(), &mut stdout()) {
+    let _ = stdout().flush();
+    eprintln!("{}", msg);
+    exit(101);
+  }
+}
+
+/// Reads a program: one `op m` instruction per line, plus an `array <name>
+/// <length>` line for each entry of the array table that CHK refers to.
+pub fn load_program(file_contents: &str) -> Result<Executable, String> {
+  let mut program: Executable = Executable::default();
+
+  for (line_number, line) in file_contents.lines().enumerate() {
+    if let Some(array) = line.trim_start().strip_prefix("array ") {
+      let array = read_array(array).map_err(|msg| format!("{} (Line: {})", msg, line_number + 1))?;
+      program.arrays.push(array);
+      continue;
+    }
+    match instruction::read_instruction(line) {
+      Ok(Some(instr)) => program.code.push(instr),
+      Ok(None) => continue,
+      Err(msg) => return Err(format!("{} (Line: {})", msg, line_number + 1)),
+    }
+  }
+
+  Ok(program)
+}
+
+fn read_array(line: &str) -> Result<ArrayInfo, String> {
+  let fields: Vec<&str> = line.split_whitespace().collect();
+  match fields[..] {
+    [name, length] => match length.parse::<Word>() {
+      Ok(length) if length > 0 => Ok(ArrayInfo { name: name.to_string(), length }),
+      _ => Err(format!("Error: Array length is not a positive number! (Length: {})", length)),
+    },
+    _ => Err("Error: Expected `array <name> <length>`!".to_string()),
+  }
+}
 
-  machine.run_machine(&mut stack, program_length);
+/// Writes a program in the format `load_program` reads.
+pub fn format_program(program: &Executable) -> String {
+  let mut text = String::new();
+  for array in &program.arrays {
+    text += &format!("array {} {}\n", array.name, array.length);
+  }
+  for instr in &program.code {
+    text += &format!("{} {}\n", instr.op, instr.m);
+  }
+  text
 }
 
-fn open_file(file_name: &String) -> String {
+/// Loads the program text (one `op m` pair per line) and runs it to completion,
+/// reading `CHI` input from `input` and writing all machine output to `output`.
+/// Loading and runtime errors are returned rather than aborting the process;
+/// `step_limit` stops programs that would otherwise run forever.
+pub fn run_program(program: &str, debug: bool, trace: bool, step_limit: Option<u64>, input: &mut dyn BufRead, output: &mut dyn Write) -> Result<(), String> {
+  run_code(load_program(program)?, debug, trace, step_limit, input, output)
+}
+
+/// Runs already loaded (or compiled) instructions, like `run_program`.
+pub fn run_code(code: Executable, debug: bool, trace: bool, step_limit: Option<u64>, input: &mut dyn BufRead, output: &mut dyn Write) -> Result<(), String> {
+  let mut stack: Stack = Stack::initialize();
+  let mut machine: Machine = Machine::initialize(input, output);
+
+  if debug {
+    machine.enable_debug();
+  }
+
+  if trace {
+    machine.enable_trace();
+  }
+
+  machine.step_limit = step_limit;
+
+  let program_length = machine.load_code(code)?;
+
+  let result = machine.run_machine(&mut stack, program_length);
+  let _ = machine.output.flush();
+  result
+}
+
+/// Runs instructions on a stack kept from earlier runs, printing only what
+/// the program itself prints. The stack is rewound first, so a main block
+/// that declares the same variables as the last program finds their values.
+pub fn resume_code(code: Executable, stack: &mut Stack, input: &mut dyn BufRead, output: &mut dyn Write) -> Result<(), String> {
+  stack.rewind();
+  let mut machine: Machine = Machine::initialize(input, output);
+  machine.load_code(code)?;
+  let result = machine.run_steps(stack);
+  let _ = machine.output.flush();
+  result
+}
+
+fn open_file(file_name: &str) -> String {
   let file_path: String = file_name.to_owned();
 
   let file = File::open(file_path.clone());
@@ -412,30 +548,3 @@ fn open_file(file_name: &String) -> String {
   
   contents
 }
-
-fn read_console_input() -> i32 {
-  print!("INPUT > ");
-  let _ = stdout().flush();
-  let mut input = String::new();
-  let res = stdin().read_line(&mut input);
-
-  match res {
-    Ok(_) => {},
-    Err(_) => panic!("Error: Could not read input!"),
-  }
-
-  let value: i32;
-  value = match input.trim().parse() {
-    Ok(num) => num,
-    Err(_) => {
-      println!("ERROR: Could not parse input! Defaulting to 0...");
-      0
-    },
-  };
-
-  value
-}
-
-fn print_debug_msg(msg: String) {
-  println!("{}\n{:->40}", msg, "");
-}
\ No newline at end of file
@@ -2,12 +2,27 @@ pub type Word = i32;
 
 pub type Address = i32;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Instruction {
   pub op: i32,
   pub m: i32,
 }
 
+/// An array that CHK instructions check indices against, so that bounds
+/// errors can name it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArrayInfo {
+  pub name: String,
+  pub length: Word,
+}
+
+/// Instructions ready to run, with the arrays their CHK instructions refer to.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Executable {
+  pub code: Vec<Instruction>,
+  pub arrays: Vec<ArrayInfo>,
+}
+
 impl Instruction {
   pub fn copy(&self) -> Self {
     Instruction { op: self.op, m: self.m }
@@ -1,4 +1,6 @@
 pub mod machine_types;
+pub mod arithmetic;
+#[allow(clippy::module_inception)]
 pub mod machine;
 pub mod stack;
 pub mod instruction;
\ No newline at end of file
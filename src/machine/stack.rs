@@ -1,6 +1,7 @@
+use std::io::Write;
 use super::machine_types::{Word, Address};
 
-const MAX_STACK_HEIGHT: usize = 2048;
+pub const MAX_STACK_HEIGHT: usize = 2048;
 
 pub struct Stack {
   stack: [Word; MAX_STACK_HEIGHT],
@@ -9,28 +10,34 @@ pub struct Stack {
 }
 
 impl Stack {
-  fn stack_invariant(&self) -> bool {
-    if !(self.bp >= 0) {
-      panic!("VM stack invariant failure: BP ({}) < 0!", self.bp);
-    }  else if !(self.sp >= 0) {
-      panic!("VM stack invariant failure: SP ({}) < 0!", self.sp);
-    } else if !(self.sp >= self.bp) {
-      panic!("VM stack invariant failure: SP ({}) < BP ({})!", self.sp, self.bp);
-    } else if !(self.sp < MAX_STACK_HEIGHT.try_into().unwrap()) {
-      panic!("VM stack invariant failure: SP ({}) >= MAX_STACK_HEIGHT ({})!", self.sp, MAX_STACK_HEIGHT);
+  fn stack_invariant(&self) -> Result<(), String> {
+    if self.bp < 0 {
+      return Err(format!("VM stack invariant failure: BP ({}) < 0!", self.bp));
+    }  else if self.sp < 0 {
+      return Err(format!("VM stack invariant failure: SP ({}) < 0!", self.sp));
+    } else if self.sp < self.bp {
+      return Err(format!("VM stack invariant failure: SP ({}) < BP ({})!", self.sp, self.bp));
+    } else if self.sp >= MAX_STACK_HEIGHT as Address {
+      return Err(format!("VM stack invariant failure: SP ({}) >= MAX_STACK_HEIGHT ({})!", self.sp, MAX_STACK_HEIGHT));
     }
 
-    true
+    Ok(())
   }
 
   fn legal_stack_index(addr: Address) -> bool {
-    addr >= 0 && addr < MAX_STACK_HEIGHT.try_into().unwrap()
+    addr >= 0 && addr < MAX_STACK_HEIGHT as Address
   }
 
   pub fn initialize() -> Self {
-    let new_stack = Stack { stack: [0; MAX_STACK_HEIGHT], sp: 0, bp: 0 };
-    new_stack.stack_invariant();
-    new_stack
+    Stack { stack: [0; MAX_STACK_HEIGHT], sp: 0, bp: 0 }
+  }
+
+  /// Moves back to the bottom of the stack without clearing it, so that the
+  /// next program run on it finds its main block's locals as the last run
+  /// left them.
+  pub fn rewind(&mut self) {
+    self.sp = 0;
+    self.bp = 0;
   }
 
   pub fn size(&self) -> Address {
@@ -46,77 +53,84 @@ impl Stack {
   }
 
   pub fn is_full(&self) -> bool {
-    self.sp == self.stack.len().try_into().unwrap()
+    self.sp == self.stack.len() as Address
   }
 
-  pub fn push(&mut self, value: Word) {
+  pub fn push(&mut self, value: Word) -> Result<(), String> {
     if self.is_full() {
-      panic!("Error: Stack overflow! (SP: {}, Max: {})", self.sp, self.stack.len());
+      return Err(format!("Error: Stack overflow! (SP: {}, Max: {})", self.sp, self.stack.len()));
     }
     self.stack[self.sp as usize] = value;
     self.sp += 1;
+    Ok(())
   }
 
-  pub fn allocate(&mut self, size: i32) {
-    let new_sp: i32 = self.sp + size;
-    if Stack::legal_stack_index(new_sp) {
-      self.sp = new_sp;
-    } else {
-      panic!("Error: Cannot increase stack size by {}! (New SP: {}, Max: {})", size, new_sp, MAX_STACK_HEIGHT);
+  pub fn allocate(&mut self, size: i32) -> Result<(), String> {
+    match self.sp.checked_add(size) {
+      Some(new_sp) if Stack::legal_stack_index(new_sp) => self.sp = new_sp,
+      _ => return Err(format!("Error: Cannot increase stack size by {}! (SP: {}, Max: {})", size, self.sp, MAX_STACK_HEIGHT)),
     }
-    self.stack_invariant();
+    self.stack_invariant()
   }
 
-  pub fn pop(&mut self) -> Word {
+  pub fn pop(&mut self) -> Result<Word, String> {
     if self.is_empty() {
-      panic!("Error: Trying to pop an empty stack!");
+      return Err("Error: Trying to pop an empty stack!".to_string());
     }
     self.sp -= 1;
-    self.stack_invariant();
-    self.stack[self.sp as usize]
+    self.stack_invariant()?;
+    Ok(self.stack[self.sp as usize])
   }
 
-  pub fn peek(&self) -> Word {
+  pub fn peek(&self) -> Result<Word, String> {
     if self.is_empty() {
-      panic!("Error: Trying to peek an empty stack!");
+      return Err("Error: Trying to peek an empty stack!".to_string());
     }
-    self.stack[(self.sp as usize) - 1]
+    Ok(self.stack[(self.sp as usize) - 1])
   }
 
-  pub fn fetch(&self, addr: Address) -> Word {
+  pub fn fetch(&self, addr: Address) -> Result<Word, String> {
     if !Stack::legal_stack_index(addr) {
-      panic!("Error: Illegal stack index {}!", addr);
+      return Err(format!("Error: Illegal stack index {}!", addr));
     }
-    self.stack[addr as usize]
+    Ok(self.stack[addr as usize])
   }
 
-  pub fn assign(&mut self, addr: Address, value: Word) {
+  pub fn assign(&mut self, addr: Address, value: Word) -> Result<(), String> {
     if !Stack::legal_stack_index(addr) {
-      panic!("Error: Illegal stack index {}!", addr);
+      return Err(format!("Error: Illegal stack index {}!", addr));
     }
     self.stack[addr as usize] = value;
+    Ok(())
   }
 
-  pub fn return_stack(&mut self, mut _pc: Address) {
-    _pc = self.pop();
-    self.bp = self.pop();
-    self.pop();
-    self.stack_invariant();
+  /// Discards the current activation record (and everything above it) and
+  /// returns the saved return address.
+  pub fn return_stack(&mut self) -> Result<Address, String> {
+    let return_address = self.fetch(self.bp + 2)?;
+    let dynamic_link = self.fetch(self.bp + 1)?;
+    self.sp = self.bp;
+    self.bp = dynamic_link;
+    self.stack_invariant()?;
+    Ok(return_address)
   }
 
-  pub fn call(&mut self, return_address: Address) {
+  /// Pushes a new activation record (static link, dynamic link, return
+  /// address) and makes it the current one.
+  pub fn call(&mut self, static_link: Address, return_address: Address) -> Result<(), String> {
     let old_bp = self.bp;
     let old_sp = self.sp;
-    self.push(self.fetch(old_bp));
-    self.push(old_bp);
-    self.push(return_address);
+    self.push(static_link)?;
+    self.push(old_bp)?;
+    self.push(return_address)?;
     self.bp = old_sp;
+    Ok(())
   }
 
-  pub fn print_stack(&self) {
+  pub fn print_stack(&self, out: &mut dyn Write) {
     for i in self.bp..self.sp {
-      print!("S[{}]: {} ", i, self.stack[i as usize]);
+      let _ = write!(out, "S[{}]: {} ", i, self.stack[i as usize]);
     }
-    println!();
+    let _ = writeln!(out);
   }
-}
\ No newline at end of file
+}
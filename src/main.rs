@@ -1,41 +1,371 @@
-pub mod machine;
-pub mod lexer;
-pub mod lexer_log;
-pub mod token;
-pub mod reserved_types;
-
-use std::{env, process::exit};
-//use machine::machine::start_machine;
-use lexer::lexer_open;
+use std::{env, fs, io::{stdin, stdout}, path::{Path, PathBuf}, process::exit, slice::Iter};
+use rustpl0::ast_dump::{dump, AstFormat};
+use rustpl0::compile_error::{CompileError, Severity};
+use rustpl0::compiler::{analyze, compile_ir, compile_object, compile_program, CompileOptions};
+use rustpl0::dialect::Dialect;
+use rustpl0::formatter::{format_source, FormatOptions, KeywordCase};
+use rustpl0::lexer::lexer_open;
+use rustpl0::linker::link;
+use rustpl0::linter::{lint, LintConfig};
+use rustpl0::machine::machine::{format_program, run_code, start_machine};
+use rustpl0::native;
+use rustpl0::object::Object;
+use rustpl0::opt::OptLevel;
+use rustpl0::parser::parse;
+use rustpl0::repl;
+
+/// What `--emit` prints instead of running the program.
+enum Emit {
+    Ast(AstFormat),
+    Ir,
+}
+
+const USAGE: &str = "Usage: `rustpl0 <filename> [-d | -debug] [-t | -trace] [-i | --ignore-case] [--dialect=standard|extended] [--module-path=<dir>]... [-O0 | -O1 | -O2] [--print-passes] [-c [-o <object>] | --emit=ast|ast-json|ast-sexp|ir]`
+       `rustpl0 link <object>... -o <program.vm>`
+       `rustpl0 build <filename> [-o <program>] [-S] [-i | --ignore-case] [--dialect=standard|extended] [--module-path=<dir>]... [-O0 | -O1 | -O2]`
+       `rustpl0 fmt [--check] [--indent=<width>] [--keyword-case=lower|upper] [--dialect=standard|extended] [-i | --ignore-case] <filename>...`
+       `rustpl0 lint [--config=<file>] [--dialect=standard|extended] [-i | --ignore-case] [--module-path=<dir>]... <filename>...`
+       `rustpl0 repl [--dialect=standard|extended] [-i | --ignore-case] [--module-path=<dir>]...`";
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     let mut debug: bool = false;
     let mut trace: bool = false;
+    let mut compile_only: bool = false;
+    let mut output: Option<String> = None;
+    let mut emit: Option<Emit> = None;
+    let mut opt_level: OptLevel = OptLevel::O0;
+    let mut print_passes: bool = false;
 
     if args.len() < 2 {
-        println!("Usage: `rustpl0 <filename> [-d | -debug] [-t | -trace]`");
+        println!("{}", USAGE);
         exit(0);
     }
 
-    if args.len() > 2 {
-      for i in 2..args.len() {
-        match args[i].as_str() {
-          "-d" | "-debug" => {
-            debug = true;
-          },
-          "-t" | "-trace" => {
-            trace = true;
-          },
-          _ => {
-            println!("Usage: `rustpl0 <filename> [-d | -debug] [-t | -trace]`");
-            exit(0);
-          }
+    if args[1] == "link" {
+        link_objects(&args[2..]);
+        return;
+    }
+
+    if args[1] == "build" {
+        build_program(&args[2..]);
+        return;
+    }
+
+    if args[1] == "fmt" {
+        format_files(&args[2..]);
+        return;
+    }
+
+    if args[1] == "lint" {
+        lint_files(&args[2..]);
+        return;
+    }
+
+    if args[1] == "repl" {
+        start_repl(&args[2..]);
+        return;
+    }
+
+    let (options, files) = parse_args(&args[2..], |arg, rest| {
+        match arg.as_str() {
+            "-d" | "-debug" => debug = true,
+            "-t" | "-trace" => trace = true,
+            "-c" => compile_only = true,
+            "-o" => output = Some(expect_value(rest.next())),
+            "--print-passes" => print_passes = true,
+            "--emit=ast" => emit = Some(Emit::Ast(AstFormat::Text)),
+            "--emit=ast-json" => emit = Some(Emit::Ast(AstFormat::Json)),
+            "--emit=ast-sexp" => emit = Some(Emit::Ast(AstFormat::Sexp)),
+            "--emit=ir" => emit = Some(Emit::Ir),
+            _ if arg.starts_with("-O") => opt_level = OptLevel::from_name(&arg[2..]).unwrap_or_else(|| usage()),
+            _ => return false,
         }
-      }
+        true
+    });
+    if !files.is_empty() {
+        usage();
     }
-    
+
     let filepath = &args[1];
-    //start_machine(&filepath, debug, trace);
-    lexer_open(&filepath, debug);
-}
\ No newline at end of file
+
+    // `.vm` files hold already compiled instructions.
+    if filepath.ends_with(".vm") {
+        start_machine(filepath, debug, trace);
+        return;
+    }
+
+    let options = CompileOptions { opt_level, print_passes, ..options };
+    let tokens = lexer_open(filepath, debug, options.lexer.clone());
+
+    if let Some(Emit::Ast(format)) = emit {
+        let program = parse(&tokens, options.lexer.dialect).unwrap_or_else(|err| report_compile_error(err));
+        print!("{}", dump(&program, format));
+        return;
+    }
+
+    if let Some(Emit::Ir) = emit {
+        let unit = compile_ir(&tokens, &options).unwrap_or_else(|err| report_compile_error(err));
+        print!("{}", unit);
+        return;
+    }
+    if compile_only {
+        let object = compile_object(&tokens, &options).unwrap_or_else(|err| report_compile_error(err));
+        let path = output.unwrap_or_else(|| Path::new(filepath).with_extension("o").to_string_lossy().to_string());
+        write_output(&path, &object.to_text());
+        return;
+    }
+
+    let code = match compile_program(&tokens, &options) {
+        Ok(code) => code,
+        Err(err) => report_compile_error(err),
+    };
+
+    if let Err(msg) = run_code(code, debug, trace, None, &mut stdin().lock(), &mut stdout()) {
+        eprintln!("{}", msg);
+        exit(101);
+    }
+}
+
+/// `rustpl0 link a.o b.o -o prog.vm`: links object files into one program
+/// that can then be run like any other `.vm` file.
+fn link_objects(args: &[String]) {
+  let mut inputs: Vec<&String> = Vec::new();
+  let mut output: Option<String> = None;
+  let mut rest = args.iter();
+  while let Some(arg) = rest.next() {
+    match arg.as_str() {
+      "-o" => output = Some(expect_value(rest.next())),
+      _ if arg.starts_with('-') => usage(),
+      _ => inputs.push(arg),
+    }
+  }
+  let (Some(output), false) = (output, inputs.is_empty()) else {
+    println!("{}", USAGE);
+    exit(0);
+  };
+
+  let mut objects: Vec<Object> = Vec::new();
+  for input in inputs {
+    let text = fs::read_to_string(input).unwrap_or_else(|err| {
+      eprintln!("Error: Could not read object file `{}`: {}!", input, err);
+      exit(101);
+    });
+    match Object::parse(&text) {
+      Ok(object) => objects.push(object),
+      Err(msg) => {
+        eprintln!("{} (File: {})", msg, input);
+        exit(101);
+      }
+    }
+  }
+
+  match link(&objects) {
+    Ok(program) => write_output(&output, &format_program(&program)),
+    Err(msg) => {
+      eprintln!("{}", msg);
+      exit(101);
+    }
+  }
+}
+
+/// `rustpl0 build prog.pl0 -o prog`: compiles the program to x86-64 and
+/// links it into a native executable. With `-S`, writes the assembly instead.
+fn build_program(args: &[String]) {
+  let mut output: Option<String> = None;
+  let mut assembly_only: bool = false;
+  let mut opt_level: OptLevel = OptLevel::O0;
+  let (options, files) = parse_args(args, |arg, rest| {
+    match arg.as_str() {
+      "-o" => output = Some(expect_value(rest.next())),
+      "-S" => assembly_only = true,
+      _ if arg.starts_with("-O") => opt_level = OptLevel::from_name(&arg[2..]).unwrap_or_else(|| usage()),
+      _ => return false,
+    }
+    true
+  });
+  let [file] = files[..] else {
+    usage();
+  };
+  let options = CompileOptions { opt_level, ..options };
+
+  let tokens = lexer_open(file, false, options.lexer.clone());
+  let program = compile_program(&tokens, &options).unwrap_or_else(|err| report_compile_error(err));
+  let output = output.unwrap_or_else(|| native::default_output(Path::new(file), assembly_only).to_string_lossy().to_string());
+  if same_file(Path::new(file), Path::new(&output)) {
+    eprintln!("Error: Refusing to overwrite the source file `{}`!", file);
+    exit(101);
+  }
+  if assembly_only {
+    write_output(&output, &native::assemble(&program));
+  } else if let Err(msg) = native::build(&program, Path::new(&output)) {
+    eprintln!("{}", msg);
+    exit(101);
+  }
+}
+
+/// `rustpl0 fmt a.pl0 b.pl0`: formats the files in place. With `--check`,
+/// changes nothing and exits with 1 if any file is not formatted.
+fn format_files(args: &[String]) {
+  let mut options = FormatOptions::default();
+  let mut check: bool = false;
+  let (compile, files) = parse_args(args, |arg, _| {
+    if arg == "--check" {
+      check = true;
+    } else if let Some(width) = arg.strip_prefix("--indent=") {
+      options.indent_width = width.parse::<usize>().unwrap_or_else(|_| usage());
+    } else if let Some(case) = arg.strip_prefix("--keyword-case=") {
+      options.keyword_case = KeywordCase::from_name(case).unwrap_or_else(|| usage());
+    } else if arg.starts_with("--module-path=") {
+      // Formatting never reads imported modules.
+      usage();
+    } else {
+      return false;
+    }
+    true
+  });
+  if files.is_empty() {
+    usage();
+  }
+  let lexer = compile.lexer;
+  if options.keyword_case == KeywordCase::Upper && !lexer.ignore_keyword_case {
+    eprintln!("Error: `--keyword-case=upper` needs `-i`, since upper case keywords only compile when case is ignored!");
+    exit(101);
+  }
+
+  let mut unformatted: bool = false;
+  for file in files {
+    let source = fs::read(file).unwrap_or_else(|err| {
+      eprintln!("Error: Could not read `{}`: {}!", file, err);
+      exit(101);
+    });
+    let formatted = match format_source(file, &source, &lexer, &options) {
+      Ok(formatted) => formatted,
+      Err(err) => report_compile_error(err),
+    };
+    if formatted.as_bytes() == source.as_slice() {
+      continue;
+    }
+    if check {
+      println!("Would reformat: {}", file);
+      unformatted = true;
+    } else {
+      write_output(file, &formatted);
+    }
+  }
+  if unformatted {
+    exit(1);
+  }
+}
+
+/// `rustpl0 lint a.pl0 b.pl0`: prints the lint warnings for each file. Exits
+/// with 1 if a denied lint was found. Without `--config`, each file uses the
+/// nearest `rustpl0-lint.conf`.
+fn lint_files(args: &[String]) {
+  let mut config: Option<LintConfig> = None;
+  let (options, files) = parse_args(args, |arg, _| {
+    let Some(path) = arg.strip_prefix("--config=") else {
+      return false;
+    };
+    let text = fs::read_to_string(path).unwrap_or_else(|err| {
+      eprintln!("Error: Could not read `{}`: {}!", path, err);
+      exit(101);
+    });
+    config = Some(LintConfig::parse(&text).unwrap_or_else(|msg| {
+      eprintln!("{} (File: {})", msg, path);
+      exit(101);
+    }));
+    true
+  });
+  if files.is_empty() {
+    usage();
+  }
+
+  let mut denied: bool = false;
+  for file in files {
+    let source = fs::read(file).unwrap_or_else(|err| {
+      eprintln!("Error: Could not read `{}`: {}!", file, err);
+      exit(101);
+    });
+    let config = config.clone().unwrap_or_else(|| LintConfig::for_file(Path::new(file)).unwrap_or_else(|msg| {
+      eprintln!("{}", msg);
+      exit(101);
+    }));
+    let analysis = analyze(file, &source, &options);
+    let warnings = lint(file, &source, &analysis, &options.lexer, &config).unwrap_or_else(|err| report_compile_error(err));
+    let text = String::from_utf8_lossy(&source);
+    for warning in warnings {
+      eprint!("{}", warning.render(&text));
+      denied |= warning.severity == Severity::Error;
+    }
+  }
+  if denied {
+    exit(1);
+  }
+}
+
+/// `rustpl0 repl`: reads declarations, statements and commands from stdin.
+fn start_repl(args: &[String]) {
+  let (options, files) = parse_args(args, |_, _| false);
+  if !files.is_empty() {
+    usage();
+  }
+  repl::run(options, &mut stdin().lock(), &mut stdout());
+}
+
+/// Splits a subcommand's arguments into the options shared by every command
+/// that reads PL/0 source and the files named. `flag` is offered each flag
+/// first, along with the arguments after it, and returns whether it was one
+/// of the subcommand's own. Any other flag prints the usage text.
+fn parse_args<'a>(args: &'a [String], mut flag: impl FnMut(&'a String, &mut Iter<'a, String>) -> bool) -> (CompileOptions, Vec<&'a String>) {
+  let mut options = CompileOptions::default();
+  let mut files: Vec<&String> = Vec::new();
+  let mut rest = args.iter();
+  while let Some(arg) = rest.next() {
+    if flag(arg, &mut rest) {
+      continue;
+    }
+    match arg.as_str() {
+      "-i" | "--ignore-case" => options.lexer.ignore_keyword_case = true,
+      _ if arg.starts_with("--dialect=") => {
+        options.lexer.dialect = Dialect::from_name(&arg["--dialect=".len()..]).unwrap_or_else(|| usage());
+      },
+      _ if arg.starts_with("--module-path=") => options.module_path.push(PathBuf::from(&arg["--module-path=".len()..])),
+      _ if arg.starts_with('-') => usage(),
+      _ => files.push(arg),
+    }
+  }
+  (options, files)
+}
+
+fn usage() -> ! {
+  println!("{}", USAGE);
+  exit(0);
+}
+
+fn expect_value(value: Option<&String>) -> String {
+  value.cloned().unwrap_or_else(|| usage())
+}
+
+fn report_compile_error(err: CompileError) -> ! {
+  // The error may be in an imported module rather than the file compiled.
+  let source = fs::read_to_string(&err.filename).unwrap_or_default();
+  eprint!("{}", err.render(&source));
+  exit(101);
+}
+
+/// Whether `a` and `b` name the same file, if both exist, or the same path.
+fn same_file(a: &Path, b: &Path) -> bool {
+  match (fs::canonicalize(a), fs::canonicalize(b)) {
+    (Ok(a), Ok(b)) => a == b,
+    _ => a == b,
+  }
+}
+
+fn write_output(path: &str, text: &str) {
+  if let Err(err) = fs::write(path, text) {
+    eprintln!("Error: Could not write `{}`: {}!", path, err);
+    exit(101);
+  }
+}
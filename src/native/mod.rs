@@ -0,0 +1,335 @@
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use crate::machine::instruction::*;
+use crate::machine::machine_types::{Address, Executable, Instruction};
+use crate::machine::stack::MAX_STACK_HEIGHT;
+
+// A linked program is translated to x86-64 one VM instruction at a time, so
+// modules, static links and array checks work exactly as on the VM. The VM's
+// stack lives in `.bss` as an array of 32-bit words, with its base in %rbx,
+// SP in %r12d and BP in %r13d. Each instruction gets the label `.Lpc<address>`
+// and jumps go straight to their target, while RTN and JMI, whose targets
+// are only known at run time, go through a table of every instruction's
+// label.
+//
+// The program stops with the VM's message and exit status on the runtime
+// errors compiled programs can run into: a full stack, a division by zero, an
+// index out of bounds, a bad character code, or running off the end of the
+// code. Otherwise the code is trusted to keep its stack balanced, as the
+// compiler's always does. The runtime in `runtime.s` does the input and
+// output through system calls, without the C library.
+
+const RUNTIME: &str = include_str!("runtime.s");
+
+/// The top of the VM stack.
+const TOP: &str = "-4(%rbx,%r12,4)";
+
+struct Assembler<'a> {
+  program: &'a Executable,
+  text: String,
+  /// Code for the errors, placed after the program's, labelled
+  /// `.Lerror<index>`.
+  errors: Vec<String>,
+  /// Strings for the errors and NDB, labelled `.Lmsg<index>`.
+  messages: Vec<String>,
+}
+
+impl Assembler<'_> {
+  fn emit(&mut self, line: &str) {
+    let _ = writeln!(self.text, "  {}", line);
+  }
+
+  fn message(&mut self, text: &str) -> String {
+    let index = match self.messages.iter().position(|message| message == text) {
+      Some(index) => index,
+      None => {
+        self.messages.push(text.to_string());
+        self.messages.len() - 1
+      },
+    };
+    format!(".Lmsg{}", index)
+  }
+
+  /// Adds code that stops the program with `prefix`, or with `prefix`, %edi
+  /// and `suffix`, after running `setup`. Returns its label.
+  fn error(&mut self, setup: &str, prefix: &str, suffix: Option<&str>) -> String {
+    let label = format!(".Lerror{}", self.errors.len());
+    let prefix = self.message(prefix);
+    let suffix = match suffix {
+      Some(suffix) => format!("leaq {}(%rip), %rdx", self.message(suffix)),
+      None => "xorl %edx, %edx".to_string(),
+    };
+    let mut code = format!("{}:\n", label);
+    if !setup.is_empty() {
+      code += &format!("  {}\n", setup);
+    }
+    code += &format!("  leaq {}(%rip), %rsi\n  {}\n  jmp rt_fail\n", prefix, suffix);
+    self.errors.push(code);
+    label
+  }
+
+  /// The label of the instruction at `address`, or of an error if there is
+  /// none. The address right after the last instruction has a label that
+  /// stops the program.
+  fn target(&mut self, address: Address) -> String {
+    match address {
+      _ if address >= 0 && address <= self.program.code.len() as Address => format!(".Lpc{}", address),
+      _ => self.pc_error(&format!("movl ${}, %edi", address)),
+    }
+  }
+
+  fn pc_error(&mut self, setup: &str) -> String {
+    let suffix = format!(", Program Length: {})", self.program.code.len());
+    self.error(setup, "Error: Program Counter out of bounds! (PC: ", Some(&suffix))
+  }
+
+  fn push(&mut self, operand: &str) {
+    self.emit(&format!("cmpl ${}, %r12d", MAX_STACK_HEIGHT));
+    self.emit("je pl0_overflow");
+    self.emit(&format!("movl {}, (%rbx,%r12,4)", operand));
+    self.emit("incl %r12d");
+  }
+
+  fn pop(&mut self, register: &str) {
+    self.emit("decl %r12d");
+    self.emit(&format!("movl (%rbx,%r12,4), {}", register));
+  }
+
+  /// Pops the top and replaces the one below with `set` applied to the two.
+  fn compare(&mut self, set: &str) {
+    self.pop("%eax");
+    self.emit(&format!("cmpl %eax, {}", TOP));
+    self.emit(&format!("{} %al", set));
+    self.emit("movzbl %al, %eax");
+    self.emit(&format!("movl %eax, {}", TOP));
+  }
+
+  fn instruction(&mut self, address: Address, instr: &Instruction) {
+    let m = instr.m;
+    let _ = writeln!(self.text, ".Lpc{}: # {} {}", address, mnemonic(instr.op), m);
+    match instr.op {
+      LIT => self.push(&format!("${}", m)),
+      RTN => {
+        self.emit("movl 8(%rbx,%r13,4), %eax");
+        self.emit("movl 4(%rbx,%r13,4), %ecx");
+        self.emit("movl %r13d, %r12d");
+        self.emit("movl %ecx, %r13d");
+        self.emit("jmp pl0_dispatch");
+      },
+      CAL => {
+        // The static link already on top starts the new record.
+        let target = self.target(m);
+        self.emit(&format!("cmpl ${}, %r12d", MAX_STACK_HEIGHT - 2));
+        self.emit("ja pl0_overflow");
+        self.emit("movl %r13d, (%rbx,%r12,4)");
+        self.emit(&format!("movl ${}, 4(%rbx,%r12,4)", address + 1));
+        self.emit("leal -1(%r12), %r13d");
+        self.emit("addl $2, %r12d");
+        self.emit(&format!("jmp {}", target));
+      },
+      POP => self.emit("decl %r12d"),
+      PSI | LOD => {
+        self.emit(&format!("movl {}, %eax", TOP));
+        if instr.op == LOD && m != 0 {
+          self.emit(&format!("addl ${}, %eax", m));
+        }
+        self.emit("movl (%rbx,%rax,4), %eax");
+        self.emit(&format!("movl %eax, {}", TOP));
+      },
+      STO => {
+        self.emit(&format!("movl {}, %eax", TOP));
+        self.emit("movl -8(%rbx,%r12,4), %ecx");
+        self.emit("subl $2, %r12d");
+        if m != 0 {
+          self.emit(&format!("addl ${}, %ecx", m));
+        }
+        self.emit("movl %eax, (%rbx,%rcx,4)");
+      },
+      INC if m > 0 => {
+        let prefix = format!("Error: Cannot increase stack size by {}! (SP: ", m);
+        let suffix = format!(", Max: {})", MAX_STACK_HEIGHT);
+        let error = self.error("movl %r12d, %edi", &prefix, Some(&suffix));
+        self.emit(&format!("leaq {}(%r12), %rax", m));
+        self.emit(&format!("cmpq ${}, %rax", MAX_STACK_HEIGHT - 1));
+        self.emit(&format!("ja {}", error));
+        self.emit("movl %eax, %r12d");
+      },
+      INC if m < 0 => self.emit(&format!("addl ${}, %r12d", m)),
+      JMP => {
+        let target = self.target(address.wrapping_add(m));
+        self.emit(&format!("jmp {}", target));
+      },
+      JPC => {
+        let target = self.target(address.wrapping_add(m));
+        self.pop("%eax");
+        self.emit("testl %eax, %eax");
+        self.emit(&format!("jnz {}", target));
+      },
+      CHO => {
+        self.pop("%edi");
+        match m {
+          CHO_LINE => self.emit("call rt_write_line"),
+          CHO_CHAR => self.emit("call rt_write_char"),
+          CHO_NUMBER => self.emit("call rt_write_number"),
+          _ => {
+            let error = self.error("", &format!("Error: Unknown output mode {} for CHO!", m), None);
+            self.emit(&format!("jmp {}", error));
+          },
+        }
+      },
+      CHI => {
+        self.emit("call rt_read");
+        self.push("%eax");
+      },
+      HLT => self.emit("jmp rt_exit"),
+      NDB => {
+        let text = self.message("\nno_out\n");
+        self.emit(&format!("leaq {}(%rip), %rsi", text));
+        self.emit("call rt_out_str");
+      },
+      NEG => self.emit(&format!("negl {}", TOP)),
+      ADD | SUB => {
+        self.pop("%eax");
+        self.emit(&format!("{} %eax, {}", if instr.op == ADD { "addl" } else { "subl" }, TOP));
+      },
+      MUL => {
+        self.pop("%eax");
+        self.emit(&format!("imull {}, %eax", TOP));
+        self.emit(&format!("movl %eax, {}", TOP));
+      },
+      DIV | MOD => {
+        // `idiv` faults on MIN / -1, which the VM wraps to MIN, leaving 0.
+        let (by_zero, by_minus_one, result) = match instr.op {
+          DIV => ("rt_divide_by_zero", "negl %eax", "%eax"),
+          _ => ("rt_modulo_by_zero", "xorl %edx, %edx", "%edx"),
+        };
+        self.pop("%ecx");
+        self.emit("testl %ecx, %ecx");
+        self.emit(&format!("jz {}", by_zero));
+        self.emit(&format!("movl {}, %eax", TOP));
+        self.emit("cmpl $-1, %ecx");
+        self.emit("jne 1f");
+        self.emit(by_minus_one);
+        self.emit("jmp 2f");
+        let _ = writeln!(self.text, "1:");
+        self.emit("cltd");
+        self.emit("idivl %ecx");
+        let _ = writeln!(self.text, "2:");
+        self.emit(&format!("movl {}, {}", result, TOP));
+      },
+      EQL => self.compare("sete"),
+      NEQ => self.compare("setne"),
+      LSS => self.compare("setl"),
+      LEQ => self.compare("setle"),
+      GTR => self.compare("setg"),
+      GEQ => self.compare("setge"),
+      PSP => self.push("%r12d"),
+      PBP => self.push("%r13d"),
+      PPC => self.push(&format!("${}", address + 1)),
+      JMI => {
+        self.pop("%eax");
+        self.emit("jmp pl0_dispatch");
+      },
+      CHK => match self.program.arrays.get(m as usize) {
+        Some(array) => {
+          let suffix = format!(" is out of bounds for array '{}' of length {}!", array.name, array.length);
+          let length = array.length;
+          let error = self.error("movl %eax, %edi", "Error: Index ", Some(&suffix));
+          self.emit(&format!("movl {}, %eax", TOP));
+          self.emit(&format!("cmpl ${}, %eax", length));
+          self.emit(&format!("jae {}", error));
+        },
+        None => {
+          let error = self.error("", &format!("Error: CHK refers to unknown array {}!", m), None);
+          self.emit(&format!("jmp {}", error));
+        },
+      },
+      // NOP, and INC 0.
+      _ => {},
+    }
+  }
+}
+
+/// A string as a GNU assembler string literal.
+fn quote(text: &str) -> String {
+  let mut quoted = String::from("\"");
+  for byte in text.bytes() {
+    match byte {
+      b'"' | b'\\' => quoted += &format!("\\{}", byte as char),
+      b' '..=b'~' => quoted.push(byte as char),
+      _ => quoted += &format!("\\{:03o}", byte),
+    }
+  }
+  quoted + "\""
+}
+
+/// Translates a linked program to GNU assembler x86-64 for Linux, runtime
+/// included.
+pub fn assemble(program: &Executable) -> String {
+  let mut asm = Assembler { program, text: String::new(), errors: Vec::new(), messages: Vec::new() };
+  for (address, instr) in program.code.iter().enumerate() {
+    asm.instruction(address as Address, instr);
+  }
+  let length = program.code.len();
+  let end = asm.pc_error(&format!("movl ${}, %edi", length));
+  let _ = writeln!(asm.text, ".Lpc{}:\n  jmp {}", length, end);
+  let bad_pc = asm.pc_error("movl %eax, %edi");
+  let overflow = asm.message(&format!("Error: Stack overflow! (SP: {}, Max: {})", MAX_STACK_HEIGHT, MAX_STACK_HEIGHT));
+
+  let mut out = String::from("# Generated by rustpl0.\n\n  .text\n  .globl _start\n_start:\n");
+  out += "  leaq pl0_stack(%rip), %rbx\n  xorl %r12d, %r12d\n  xorl %r13d, %r13d\n";
+  out += &asm.text;
+  out += "\n# Jumps to the instruction whose address is in %eax.\npl0_dispatch:\n";
+  let _ = writeln!(out, "  cmpl ${}, %eax\n  jae {}\n  leaq pl0_code(%rip), %rcx\n  jmp *(%rcx,%rax,8)", length, bad_pc);
+  let _ = writeln!(out, "\npl0_overflow:\n  leaq {}(%rip), %rsi\n  xorl %edx, %edx\n  jmp rt_fail", overflow);
+  out += &asm.errors.concat();
+
+  out += "\n  .section .rodata\n  .p2align 3\npl0_code:\n";
+  for address in 0..length {
+    let _ = writeln!(out, "  .quad .Lpc{}", address);
+  }
+  for (index, message) in asm.messages.iter().enumerate() {
+    let _ = writeln!(out, ".Lmsg{}: .asciz {}", index, quote(message));
+  }
+  let _ = writeln!(out, "\n  .bss\n  .p2align 2\npl0_stack: .zero {}", MAX_STACK_HEIGHT * 4);
+  out += "\n  .section .note.GNU-stack,\"\",@progbits\n\n";
+  out + RUNTIME
+}
+
+/// Where `rustpl0 build` writes when not given `-o`: the source's path
+/// without its extension, or with `.s` for assembly. When that is the source
+/// itself, `.out` is added so that the source is not overwritten.
+pub fn default_output(source: &Path, assembly: bool) -> PathBuf {
+  let output = source.with_extension(if assembly { "s" } else { "" });
+  if output != source {
+    return output;
+  }
+  let mut name = output.into_os_string();
+  name.push(".out");
+  PathBuf::from(name)
+}
+
+/// Assembles and links `program` into the executable `output` with the
+/// system's `as` and `ld`.
+pub fn build(program: &Executable, output: &Path) -> Result<(), String> {
+  let mut object = output.as_os_str().to_owned();
+  object.push(".o");
+
+  let mut assembler = Command::new("as").arg("-o").arg(&object).stdin(Stdio::piped()).spawn()
+    .map_err(|err| format!("Error: Could not run `as`: {}!", err))?;
+  let written = assembler.stdin.take().unwrap().write_all(assemble(program).as_bytes());
+  let status = assembler.wait().map_err(|err| format!("Error: Could not run `as`: {}!", err))?;
+  if written.is_err() || !status.success() {
+    return Err(format!("Error: `as` failed! ({})", status));
+  }
+
+  let linked = Command::new("ld").arg("-o").arg(output).arg(&object).status();
+  let _ = std::fs::remove_file(&object);
+  match linked {
+    Ok(status) if status.success() => Ok(()),
+    Ok(status) => Err(format!("Error: `ld` failed! ({})", status)),
+    Err(err) => Err(format!("Error: Could not run `ld`: {}!", err)),
+  }
+}
@@ -0,0 +1,353 @@
+# Runtime for programs built by `rustpl0 build`: output buffering, the
+# machine's `write` and `read` formats, and runtime errors, all through Linux
+# system calls.
+#
+# The generated code keeps the machine's stack base in %rbx, SP in %r12d and
+# BP in %r13d. These routines never touch those three registers.
+
+  .text
+
+# Writes the buffered output to standard output.
+rt_flush:
+  leaq rt_out_buf(%rip), %rsi
+  movq rt_out_len(%rip), %rdx
+1:
+  testq %rdx, %rdx
+  jz 2f
+  movl $1, %eax
+  movl $1, %edi
+  syscall
+  testq %rax, %rax
+  js 2f
+  addq %rax, %rsi
+  subq %rax, %rdx
+  jmp 1b
+2:
+  movq $0, rt_out_len(%rip)
+  ret
+
+# Appends the %rdx bytes at %rsi to the output.
+rt_out:
+  testq %rdx, %rdx
+  jz 3f
+1:
+  movq rt_out_len(%rip), %rcx
+  cmpq $4096, %rcx
+  jne 2f
+  pushq %rsi
+  pushq %rdx
+  call rt_flush
+  popq %rdx
+  popq %rsi
+  xorl %ecx, %ecx
+2:
+  movb (%rsi), %al
+  leaq rt_out_buf(%rip), %rdi
+  movb %al, (%rdi,%rcx)
+  incq %rcx
+  movq %rcx, rt_out_len(%rip)
+  incq %rsi
+  decq %rdx
+  jnz 1b
+3:
+  ret
+
+# Appends the NUL-terminated string at %rsi to the output.
+rt_out_str:
+  movq %rsi, %rdx
+1:
+  cmpb $0, (%rdx)
+  je 2f
+  incq %rdx
+  jmp 1b
+2:
+  subq %rsi, %rdx
+  jmp rt_out
+
+# Writes the NUL-terminated string at %rsi to standard error.
+rt_err_str:
+  movq %rsi, %rdx
+1:
+  cmpb $0, (%rdx)
+  je 2f
+  incq %rdx
+  jmp 1b
+2:
+  subq %rsi, %rdx
+  movl $1, %eax
+  movl $2, %edi
+  syscall
+  ret
+
+# Formats %edi in decimal, leaving the digits at %rsi and their count in
+# %rdx.
+rt_format:
+  movslq %edi, %rax
+  leaq rt_num_buf+16(%rip), %rsi
+  movq %rax, %r8
+  testq %rax, %rax
+  jns 1f
+  negq %rax
+1:
+  movl $10, %ecx
+2:
+  xorl %edx, %edx
+  divq %rcx
+  addb $'0', %dl
+  decq %rsi
+  movb %dl, (%rsi)
+  testq %rax, %rax
+  jnz 2b
+  testq %r8, %r8
+  jns 3f
+  decq %rsi
+  movb $'-', (%rsi)
+3:
+  leaq rt_num_buf+16(%rip), %rdx
+  subq %rsi, %rdx
+  ret
+
+# `CHO 2`: prints %edi.
+rt_write_number:
+  call rt_format
+  jmp rt_out
+
+# `CHO 0`: prints %edi as `OUTPUT: n` on a line of its own.
+rt_write_line:
+  pushq %rdi
+  leaq rt_output_msg(%rip), %rsi
+  call rt_out_str
+  popq %rdi
+  call rt_write_number
+  leaq rt_newline(%rip), %rsi
+  jmp rt_out_str
+
+# `CHO 1`: prints the character with code %edi, encoded as UTF-8.
+rt_write_char:
+  cmpl $0xd800, %edi
+  jb 1f
+  cmpl $0xdfff, %edi
+  jbe 5f
+  cmpl $0x10ffff, %edi
+  ja 5f
+1:
+  leaq rt_num_buf(%rip), %rsi
+  cmpl $0x80, %edi
+  jae 2f
+  movb %dil, (%rsi)
+  movl $1, %edx
+  jmp rt_out
+2:
+  cmpl $0x800, %edi
+  jae 3f
+  movl %edi, %eax
+  shrl $6, %eax
+  orb $0xc0, %al
+  movb %al, (%rsi)
+  movl %edi, %eax
+  andb $0x3f, %al
+  orb $0x80, %al
+  movb %al, 1(%rsi)
+  movl $2, %edx
+  jmp rt_out
+3:
+  cmpl $0x10000, %edi
+  jae 4f
+  movl %edi, %eax
+  shrl $12, %eax
+  orb $0xe0, %al
+  movb %al, (%rsi)
+  movl %edi, %eax
+  shrl $6, %eax
+  andb $0x3f, %al
+  orb $0x80, %al
+  movb %al, 1(%rsi)
+  movl %edi, %eax
+  andb $0x3f, %al
+  orb $0x80, %al
+  movb %al, 2(%rsi)
+  movl $3, %edx
+  jmp rt_out
+4:
+  movl %edi, %eax
+  shrl $18, %eax
+  orb $0xf0, %al
+  movb %al, (%rsi)
+  movl %edi, %eax
+  shrl $12, %eax
+  andb $0x3f, %al
+  orb $0x80, %al
+  movb %al, 1(%rsi)
+  movl %edi, %eax
+  shrl $6, %eax
+  andb $0x3f, %al
+  orb $0x80, %al
+  movb %al, 2(%rsi)
+  movl %edi, %eax
+  andb $0x3f, %al
+  orb $0x80, %al
+  movb %al, 3(%rsi)
+  movl $4, %edx
+  jmp rt_out
+5:
+  leaq rt_char_msg(%rip), %rsi
+  leaq rt_char_msg_end(%rip), %rdx
+  jmp rt_fail
+
+# `CHI`: prompts for and reads a line, returning the number on it in %eax.
+# Like the machine, a line that is not a number after trimming whitespace
+# reads as 0. The line is read a byte at a time, so that input after it is
+# left for whoever reads next.
+#
+# %r9d is how far the line has got: 0 before the number, 1 after its sign,
+# 2 in its digits, 3 after them and 4 once it cannot be a number. %r8 holds
+# the digits' value, kept from growing past 2^31 + 1, and %r10d is 1 if the
+# number is negative.
+rt_read:
+  leaq rt_input_msg(%rip), %rsi
+  call rt_out_str
+  call rt_flush
+  xorl %r8d, %r8d
+  xorl %r9d, %r9d
+  xorl %r10d, %r10d
+1:
+  xorl %eax, %eax
+  xorl %edi, %edi
+  leaq rt_byte(%rip), %rsi
+  movl $1, %edx
+  syscall
+  testq %rax, %rax
+  js 9f
+  jz 7f
+  movzbl rt_byte(%rip), %eax
+  cmpl $'\n', %eax
+  je 7f
+  cmpl $' ', %eax
+  je 2f
+  cmpl $'\t', %eax
+  jb 3f
+  cmpl $'\r', %eax
+  ja 3f
+2:
+  # Whitespace ends the digits, and is not allowed between sign and digits.
+  cmpl $1, %r9d
+  je 6f
+  cmpl $2, %r9d
+  jne 1b
+  movl $3, %r9d
+  jmp 1b
+3:
+  cmpl $'0', %eax
+  jb 4f
+  cmpl $'9', %eax
+  ja 4f
+  cmpl $2, %r9d
+  ja 6f
+  movl $2, %r9d
+  subl $'0', %eax
+  imulq $10, %r8, %r8
+  addq %rax, %r8
+  movl $0x80000001, %eax
+  cmpq %rax, %r8
+  jbe 1b
+  movq %rax, %r8
+  jmp 1b
+4:
+  testl %r9d, %r9d
+  jnz 6f
+  movl $1, %r9d
+  cmpl $'+', %eax
+  je 1b
+  movl $1, %r10d
+  cmpl $'-', %eax
+  je 1b
+6:
+  movl $4, %r9d
+  jmp 1b
+7:
+  cmpl $2, %r9d
+  jb 8f
+  cmpl $3, %r9d
+  ja 8f
+  movq %r8, %rax
+  testl %r10d, %r10d
+  jz 10f
+  movl $0x80000000, %ecx
+  cmpq %rcx, %rax
+  ja 8f
+  negl %eax
+  ret
+10:
+  cmpq $0x7fffffff, %rax
+  ja 8f
+  ret
+8:
+  leaq rt_parse_msg(%rip), %rsi
+  call rt_out_str
+  xorl %eax, %eax
+  ret
+9:
+  leaq rt_read_msg(%rip), %rsi
+  xorl %edx, %edx
+  jmp rt_fail
+
+# Stops the program, as `HLT` does.
+rt_exit:
+  call rt_flush
+  movl $60, %eax
+  xorl %edi, %edi
+  syscall
+
+# Stops the program with an error: the string at %rsi, then, unless %rdx is
+# zero, %edi and the string at %rdx. Output printed so far comes first.
+rt_fail:
+  pushq %rdx
+  pushq %rdi
+  pushq %rsi
+  call rt_flush
+  popq %rsi
+  call rt_err_str
+  popq %rdi
+  popq %rsi
+  testq %rsi, %rsi
+  jz 1f
+  pushq %rsi
+  call rt_format
+  movl $1, %eax
+  movl $2, %edi
+  syscall
+  popq %rsi
+  call rt_err_str
+1:
+  leaq rt_newline(%rip), %rsi
+  call rt_err_str
+  movl $60, %eax
+  movl $101, %edi
+  syscall
+
+rt_divide_by_zero:
+  leaq rt_divide_msg(%rip), %rsi
+  xorl %edx, %edx
+  jmp rt_fail
+
+rt_modulo_by_zero:
+  leaq rt_modulo_msg(%rip), %rsi
+  xorl %edx, %edx
+  jmp rt_fail
+
+  .section .rodata
+rt_output_msg: .asciz "OUTPUT: "
+rt_newline: .asciz "\n"
+rt_input_msg: .asciz "INPUT > "
+rt_parse_msg: .asciz "ERROR: Could not parse input! Defaulting to 0...\n"
+rt_read_msg: .asciz "Error: Could not read input!"
+rt_char_msg: .asciz "Error: "
+rt_char_msg_end: .asciz " is not a valid character code!"
+rt_divide_msg: .asciz "Error: Division by zero!"
+rt_modulo_msg: .asciz "Error: Modulo by zero!"
+
+  .bss
+rt_out_len: .zero 8
+rt_out_buf: .zero 4096
+rt_num_buf: .zero 16
+rt_byte: .zero 1
@@ -0,0 +1,178 @@
+use crate::ast::ProcKind;
+use crate::machine::instruction::read_instruction;
+use crate::machine::machine_types::{Address, ArrayInfo, Instruction, Word};
+
+/// The compiled code of one program or module, before linking. Addresses in
+/// `code` are relative to the object's own first instruction and array
+/// table; `relocations` lists every operand the linker has to adjust.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Object {
+  /// The module's name, or `None` for a main program.
+  pub module: Option<String>,
+  pub code: Vec<Instruction>,
+  pub arrays: Vec<ArrayInfo>,
+  pub exports: Vec<Export>,
+  /// Procedures from other modules called by `code`.
+  pub imports: Vec<Import>,
+  pub relocations: Vec<Relocation>,
+}
+
+/// A constant or procedure declared at the top level of a module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export {
+  pub name: String,
+  pub value: ExportValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportValue {
+  Const(Word),
+  Proc { kind: ProcKind, arity: usize, address: Address },
+}
+
+/// A procedure of another module, with the kind and arity it was compiled
+/// against, so that linking can catch a module that has changed since.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+  pub module: String,
+  pub name: String,
+  pub kind: ProcKind,
+  pub arity: usize,
+}
+
+/// An instruction whose operand depends on where things end up once linked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Relocation {
+  /// The operand is an address in this object's code.
+  Code { at: usize },
+  /// The operand is an index into this object's array table.
+  Array { at: usize },
+  /// The operand is an index into this object's imports.
+  Import { at: usize },
+}
+
+impl Object {
+  pub fn export(&self, name: &str) -> Option<&Export> {
+    self.exports.iter().find(|e| e.name == name)
+  }
+}
+
+const OBJECT_HEADER: &str = "pl0-object 2";
+
+impl Object {
+  /// Writes the object in the text format `parse` reads: a header of
+  /// declarations, then `code` and one `op m` instruction per line.
+  pub fn to_text(&self) -> String {
+    let mut text = format!("{}\n", OBJECT_HEADER);
+    match &self.module {
+      Some(name) => text += &format!("module {}\n", name),
+      None => text += "program\n",
+    }
+    for export in &self.exports {
+      text += &match export.value {
+        ExportValue::Const(value) => format!("export const {} {}\n", export.name, value),
+        ExportValue::Proc { kind, arity, address } => format!("export {} {} {} {}\n", kind_name(kind), export.name, arity, address),
+      };
+    }
+    for import in &self.imports {
+      text += &format!("import {} {} {} {}\n", kind_name(import.kind), import.module, import.name, import.arity);
+    }
+    for array in &self.arrays {
+      text += &format!("array {} {}\n", array.name, array.length);
+    }
+    for relocation in &self.relocations {
+      text += &match relocation {
+        Relocation::Code { at } => format!("relocate code {}\n", at),
+        Relocation::Array { at } => format!("relocate array {}\n", at),
+        Relocation::Import { at } => format!("relocate import {}\n", at),
+      };
+    }
+    text += "code\n";
+    for instr in &self.code {
+      text += &format!("{} {}\n", instr.op, instr.m);
+    }
+    text
+  }
+
+  /// Reads an object written by `to_text`.
+  pub fn parse(text: &str) -> Result<Object, String> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+      Some((_, header)) if header.trim() == OBJECT_HEADER => {},
+      _ => return Err(format!("Error: Not an object file! (Expected `{}` on the first line)", OBJECT_HEADER)),
+    }
+
+    let mut object = Object::default();
+    let mut in_code = false;
+    for (line_number, line) in lines {
+      let fields: Vec<&str> = line.split_whitespace().collect();
+      if fields.is_empty() {
+        continue;
+      }
+      let parsed = if in_code {
+        read_instruction(line).map(|instr| object.code.extend(instr))
+      } else {
+        object.read_declaration(&fields, &mut in_code)
+      };
+      parsed.map_err(|msg| format!("{} (Line: {})", msg, line_number + 1))?;
+    }
+
+    if !in_code {
+      return Err("Error: Object file has no `code` section!".to_string());
+    }
+    for relocation in &object.relocations {
+      let (Relocation::Code { at } | Relocation::Array { at } | Relocation::Import { at }) = *relocation;
+      if at >= object.code.len() {
+        return Err(format!("Error: Relocation of address {} is outside the code!", at));
+      }
+    }
+    Ok(object)
+  }
+
+  fn read_declaration(&mut self, fields: &[&str], in_code: &mut bool) -> Result<(), String> {
+    match fields {
+      ["code"] => *in_code = true,
+      ["program"] => self.module = None,
+      ["module", name] => self.module = Some(name.to_string()),
+      ["export", "const", name, value] => {
+        self.exports.push(Export { name: name.to_string(), value: ExportValue::Const(number(value)?) });
+      },
+      ["export", kind @ ("procedure" | "function"), name, arity, address] => {
+        let value = ExportValue::Proc { kind: read_kind(kind), arity: read_arity(arity)?, address: number(address)? };
+        self.exports.push(Export { name: name.to_string(), value });
+      },
+      ["import", kind @ ("procedure" | "function"), module, name, arity] => {
+        self.imports.push(Import { module: module.to_string(), name: name.to_string(), kind: read_kind(kind), arity: read_arity(arity)? });
+      },
+      ["array", name, length] => self.arrays.push(ArrayInfo { name: name.to_string(), length: number(length)? }),
+      ["relocate", kind, at] => {
+        let at = at.parse::<usize>().map_err(|_| format!("Error: Relocation address is not a number! ({})", at))?;
+        self.relocations.push(match *kind {
+          "code" => Relocation::Code { at },
+          "array" => Relocation::Array { at },
+          "import" => Relocation::Import { at },
+          _ => return Err(format!("Error: Unknown relocation kind `{}`!", kind)),
+        });
+      },
+      _ => return Err(format!("Error: Unknown object file line `{}`!", fields.join(" "))),
+    }
+    Ok(())
+  }
+}
+
+/// How a procedure's kind is written in object files, and in link errors.
+pub fn kind_name(kind: ProcKind) -> &'static str {
+  if kind == ProcKind::Function { "function" } else { "procedure" }
+}
+
+fn read_kind(name: &str) -> ProcKind {
+  if name == "function" { ProcKind::Function } else { ProcKind::Procedure }
+}
+
+fn read_arity(text: &str) -> Result<usize, String> {
+  text.parse::<usize>().map_err(|_| format!("Error: Arity is not a number! ({})", text))
+}
+
+fn number(text: &str) -> Result<Word, String> {
+  text.parse::<Word>().map_err(|_| format!("Error: Expected a number, not `{}`!", text))
+}
@@ -0,0 +1,85 @@
+use crate::ir::*;
+use super::dataflow::{predecessors, reachable};
+
+/// Drops the blocks not in `keep`, renumbering the rest in order.
+fn remove_blocks(function: &mut Function, keep: &[bool]) {
+  let mut renumbered: Vec<BlockId> = vec![0; keep.len()];
+  let mut next = 0;
+  for (id, kept) in keep.iter().enumerate() {
+    renumbered[id] = next;
+    next += *kept as usize;
+  }
+  let blocks = std::mem::take(&mut function.blocks);
+  function.blocks = blocks.into_iter().zip(keep).filter(|(_, kept)| **kept).map(|(mut block, _)| {
+    block.term = match block.term {
+      Terminator::Jump(target) => Terminator::Jump(renumbered[target]),
+      Terminator::Branch { cond, then, otherwise } => Terminator::Branch { cond, then: renumbered[then], otherwise: renumbered[otherwise] },
+      term => term,
+    };
+    block
+  }).collect();
+}
+
+/// Drops the blocks that can never run.
+pub fn remove_unreachable(function: &mut Function) -> bool {
+  let keep = reachable(function);
+  if keep.iter().all(|kept| *kept) {
+    return false;
+  }
+  remove_blocks(function, &keep);
+  true
+}
+
+/// Where a jump to `target` ends up after any empty blocks that only jump on.
+fn forward(function: &Function, mut target: BlockId) -> BlockId {
+  let mut seen: Vec<BlockId> = Vec::new();
+  while let Block { insts, term: Terminator::Jump(next) } = &function.blocks[target] {
+    if !insts.is_empty() || seen.contains(next) {
+      break;
+    }
+    seen.push(target);
+    target = *next;
+  }
+  target
+}
+
+pub fn simplify_cfg(function: &mut Function) -> bool {
+  let mut changed = false;
+  for id in 0..function.blocks.len() {
+    let term = match function.blocks[id].term {
+      Terminator::Jump(target) => Terminator::Jump(forward(function, target)),
+      Terminator::Branch { cond, then, otherwise } => match (forward(function, then), forward(function, otherwise)) {
+        (then, otherwise) if then == otherwise => Terminator::Jump(then),
+        (then, otherwise) => Terminator::Branch { cond, then, otherwise },
+      },
+      ref term => term.clone(),
+    };
+    if function.blocks[id].term != term {
+      function.blocks[id].term = term;
+      changed = true;
+    }
+  }
+
+  // A block jumped to only from the block before it in the chain becomes part
+  // of that block.
+  let mut merged = true;
+  while merged {
+    merged = false;
+    let preds = predecessors(function);
+    for id in 0..function.blocks.len() {
+      let Terminator::Jump(next) = function.blocks[id].term else {
+        continue;
+      };
+      if next == id || next == 0 || preds[next] != [id] {
+        continue;
+      }
+      let Block { insts, term } = std::mem::replace(&mut function.blocks[next], Block { insts: Vec::new(), term: Terminator::Halt });
+      function.blocks[id].insts.extend(insts);
+      function.blocks[id].term = term;
+      merged = true;
+      changed = true;
+      break;
+    }
+  }
+  changed | remove_unreachable(function)
+}
@@ -0,0 +1,204 @@
+use std::collections::{BTreeSet, HashMap};
+use crate::ast::ProcKind;
+use crate::ir::*;
+use crate::machine::machine_types::Address;
+
+// Variables are told apart by where they live, `depth` static links out at
+// `offset`. Array elements are not tracked: no analysis here knows which
+// element an index picks, and bounds checks keep element accesses away from
+// other variables.
+//
+// A call may read and write any variable its callee can reach. The callee is
+// declared `depth` static links out, so it sees that block's record and the
+// ones around it, but none nested closer to the caller. Imported procedures
+// see only their own module, which has no variables.
+
+pub type VarKey = (usize, Address);
+
+pub fn key(var: &Var) -> VarKey {
+  (var.depth, var.offset)
+}
+
+/// Whether `inst` is a call that may read or write `var`.
+pub fn call_touches(inst: &Inst, var: VarKey) -> bool {
+  match inst {
+    Inst::Call { callee: Callee::Local(_), depth, .. } => var.0 >= *depth,
+    _ => false,
+  }
+}
+
+/// The variables a function loads or stores, in order.
+pub fn variables(function: &Function) -> Vec<VarKey> {
+  let vars: BTreeSet<VarKey> = function.blocks.iter()
+    .flat_map(|block| &block.insts)
+    .filter_map(|inst| match inst {
+      Inst::Load { var, .. } | Inst::Store { var, .. } => Some(key(var)),
+      _ => None,
+    })
+    .collect();
+  vars.into_iter().collect()
+}
+
+pub fn predecessors(function: &Function) -> Vec<Vec<BlockId>> {
+  let mut preds: Vec<Vec<BlockId>> = vec![Vec::new(); function.blocks.len()];
+  for (id, block) in function.blocks.iter().enumerate() {
+    for succ in block.term.successors() {
+      if !preds[succ].contains(&id) {
+        preds[succ].push(id);
+      }
+    }
+  }
+  preds
+}
+
+/// Blocks that can run, following branches from the entry.
+pub fn reachable(function: &Function) -> Vec<bool> {
+  let mut seen = vec![false; function.blocks.len()];
+  let mut work: Vec<BlockId> = vec![0];
+  while let Some(id) = work.pop() {
+    if !std::mem::replace(&mut seen[id], true) {
+      work.extend(function.blocks[id].term.successors());
+    }
+  }
+  seen
+}
+
+/// Where a variable may have got its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Def {
+  /// Its value on entry, or whatever a call left in it.
+  Unknown(VarKey),
+  Store { block: BlockId, index: usize },
+}
+
+/// For each block, the definitions that may reach its start, as indexes into
+/// `defs`. The first definitions are the unknown values of `vars`, in order.
+pub struct ReachingDefs {
+  pub defs: Vec<(VarKey, Def)>,
+  pub vars: Vec<VarKey>,
+  pub block_in: Vec<BTreeSet<usize>>,
+  stores: HashMap<(BlockId, usize), usize>,
+}
+
+impl ReachingDefs {
+  pub fn compute(function: &Function) -> ReachingDefs {
+    let vars = variables(function);
+    let mut defs: Vec<(VarKey, Def)> = vars.iter().map(|v| (*v, Def::Unknown(*v))).collect();
+    let mut stores: HashMap<(BlockId, usize), usize> = HashMap::new();
+    for (id, block) in function.blocks.iter().enumerate() {
+      for (index, inst) in block.insts.iter().enumerate() {
+        if let Inst::Store { var, .. } = inst {
+          stores.insert((id, index), defs.len());
+          defs.push((key(var), Def::Store { block: id, index }));
+        }
+      }
+    }
+    let mut reaching = ReachingDefs { defs, vars, block_in: vec![BTreeSet::new(); function.blocks.len()], stores };
+
+    let preds = predecessors(function);
+    let entry: BTreeSet<usize> = (0..reaching.vars.len()).collect();
+    let mut block_out: Vec<Option<BTreeSet<usize>>> = vec![None; function.blocks.len()];
+    let mut changed = true;
+    while changed {
+      changed = false;
+      for (id, block) in function.blocks.iter().enumerate() {
+        let mut state: BTreeSet<usize> = if id == 0 { entry.clone() } else { BTreeSet::new() };
+        for pred in &preds[id] {
+          if let Some(out) = &block_out[*pred] {
+            state.extend(out);
+          }
+        }
+        reaching.block_in[id] = state.clone();
+        for (index, inst) in block.insts.iter().enumerate() {
+          reaching.step(&mut state, id, index, inst);
+        }
+        if block_out[id].as_ref() != Some(&state) {
+          block_out[id] = Some(state);
+          changed = true;
+        }
+      }
+    }
+    reaching
+  }
+
+  /// Moves `state` past instruction `index` of `block`.
+  pub fn step(&self, state: &mut BTreeSet<usize>, block: BlockId, index: usize, inst: &Inst) {
+    match inst {
+      Inst::Store { var, .. } => {
+        let var = key(var);
+        state.retain(|d| self.defs[*d].0 != var);
+        state.insert(self.stores[&(block, index)]);
+      },
+      Inst::Call { .. } => {
+        for (i, var) in self.vars.iter().enumerate() {
+          if call_touches(inst, *var) {
+            state.retain(|d| self.defs[*d].0 != *var);
+            state.insert(i);
+          }
+        }
+      },
+      _ => {},
+    }
+  }
+
+  /// The definitions of `var` in `state`.
+  pub fn of(&self, state: &BTreeSet<usize>, var: VarKey) -> Vec<Def> {
+    state.iter().filter(|d| self.defs[**d].0 == var).map(|d| self.defs[*d].1).collect()
+  }
+}
+
+/// For each block, the variables that may be read after it before being
+/// stored again.
+pub struct Liveness {
+  pub block_out: Vec<BTreeSet<VarKey>>,
+}
+
+impl Liveness {
+  pub fn compute(function: &Function) -> Liveness {
+    let vars = variables(function);
+    // After a return, only the caller's variables and the function's result
+    // can still be read; after a halt, nothing.
+    let arity = function.params.len() as Address;
+    let exit: BTreeSet<VarKey> = vars.iter().copied()
+      .filter(|(depth, offset)| *depth > 0 || (function.kind == FunctionKind::Proc(ProcKind::Function) && *offset == -arity - 1))
+      .collect();
+
+    let mut liveness = Liveness { block_out: vec![BTreeSet::new(); function.blocks.len()] };
+    let mut block_in: Vec<BTreeSet<VarKey>> = vec![BTreeSet::new(); function.blocks.len()];
+    let mut changed = true;
+    while changed {
+      changed = false;
+      for (id, block) in function.blocks.iter().enumerate().rev() {
+        let mut state: BTreeSet<VarKey> = match block.term {
+          Terminator::Return => exit.clone(),
+          Terminator::Halt => BTreeSet::new(),
+          _ => block.term.successors().iter().flat_map(|succ| block_in[*succ].iter().copied()).collect(),
+        };
+        liveness.block_out[id] = state.clone();
+        for inst in block.insts.iter().rev() {
+          Liveness::step(&vars, &mut state, inst);
+        }
+        if block_in[id] != state {
+          block_in[id] = state;
+          changed = true;
+        }
+      }
+    }
+    liveness
+  }
+
+  /// Moves `state` back before `inst`. A call may read what it can reach,
+  /// but need not write it, so it keeps everything live.
+  pub fn step(vars: &[VarKey], state: &mut BTreeSet<VarKey>, inst: &Inst) {
+    match inst {
+      Inst::Store { var, .. } => {
+        state.remove(&key(var));
+      },
+      Inst::Load { var, .. } => {
+        state.insert(key(var));
+      },
+      Inst::Call { .. } => state.extend(vars.iter().filter(|var| call_touches(inst, **var))),
+      _ => {},
+    }
+  }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use crate::ir::*;
+use super::cfg::remove_unreachable;
+use super::dataflow::{call_touches, key, variables, Liveness, VarKey};
+
+/// Whether dropping `inst` could only change the temporary it assigns. Array
+/// loads may fail their bounds check, and division may be by zero.
+fn removable(inst: &Inst) -> bool {
+  match inst {
+    Inst::Binary { op: BinOp::Div | BinOp::Mod, right, .. } => matches!(right, Operand::Const(value) if *value != 0),
+    Inst::Binary { .. } | Inst::Unary { .. } | Inst::Copy { .. } | Inst::Load { .. } => true,
+    Inst::LoadElem { .. } | Inst::Call { .. } | Inst::Read { .. } | Inst::Store { .. } | Inst::StoreElem { .. } | Inst::Write { .. } => false,
+  }
+}
+
+pub fn dead_code(function: &mut Function) -> bool {
+  let mut changed = remove_unreachable(function);
+  loop {
+    let mut uses: HashMap<Temp, usize> = HashMap::new();
+    for block in &function.blocks {
+      let mut operands: Vec<Operand> = block.insts.iter().flat_map(|inst| inst.operands()).collect();
+      if let Terminator::Branch { cond, .. } = block.term {
+        operands.push(cond);
+      }
+      for operand in operands {
+        if let Operand::Temp(t) = operand {
+          *uses.entry(t).or_default() += 1;
+        }
+      }
+    }
+
+    let mut progress = false;
+    for block in &mut function.blocks {
+      let before = block.insts.len();
+      block.insts.retain(|inst| !(removable(inst) && inst.dst().is_some_and(|dst| !uses.contains_key(&dst))));
+      progress |= block.insts.len() != before;
+    }
+    if !progress {
+      return changed;
+    }
+    changed = true;
+  }
+}
+
+/// Also hands a stored value straight to the load after it, when that load
+/// is the last to read it.
+pub fn dead_stores(function: &mut Function) -> bool {
+  let vars = variables(function);
+  let liveness = Liveness::compute(function);
+  let mut changed = false;
+  for (id, block) in function.blocks.iter_mut().enumerate() {
+    let mut state = liveness.block_out[id].clone();
+    let mut dead: Vec<usize> = Vec::new();
+    let mut forwarded: Vec<(usize, Operand)> = Vec::new();
+    // Loads after which their variable is dead, with nothing in between
+    // touching it.
+    let mut last_loads: HashMap<VarKey, usize> = HashMap::new();
+    for (index, inst) in block.insts.iter().enumerate().rev() {
+      match inst {
+        Inst::Store { var, src } => {
+          if !state.contains(&key(var)) {
+            dead.push(index);
+          } else if let Some(load) = last_loads.remove(&key(var)) {
+            dead.push(index);
+            forwarded.push((load, *src));
+          }
+        },
+        Inst::Load { var, .. } if !state.contains(&key(var)) => {
+          last_loads.insert(key(var), index);
+        },
+        Inst::Load { var, .. } => {
+          last_loads.remove(&key(var));
+        },
+        Inst::Call { .. } => last_loads.retain(|var, _| !call_touches(inst, *var)),
+        _ => {},
+      }
+      Liveness::step(&vars, &mut state, inst);
+    }
+    changed |= !dead.is_empty();
+    for (load, src) in forwarded {
+      let dst = block.insts[load].dst().unwrap();
+      block.insts[load] = Inst::Copy { dst, src };
+    }
+    for index in dead {
+      block.insts.remove(index);
+    }
+  }
+  changed
+}
@@ -0,0 +1,98 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::ir::*;
+use crate::lowering::AR_SIZE;
+use crate::machine::machine_types::Address;
+use super::dataflow::{key, variables, Liveness, VarKey};
+
+// Each inlined call gets new words at the end of the caller's record, so
+// without this pass a record grows with every call site. The words after
+// those the function declares are only used by its own code, and are packed
+// again from scratch: arrays first, one after the other, then the other
+// variables, which share a word whenever they are never live at the same
+// time. Words no instruction uses any more are dropped. Declared variables
+// keep their place, since nested procedures reach them by offset.
+
+/// For each variable that inlining added, the variables live at some point
+/// where it is stored or where the function starts.
+fn interference(function: &Function, first: Address) -> BTreeMap<Address, BTreeSet<Address>> {
+  let vars = variables(function);
+  let inlined = |var: &VarKey| var.0 == 0 && var.1 >= first;
+  let mut edges: BTreeMap<Address, BTreeSet<Address>> = vars.iter().filter(|var| inlined(var)).map(|var| (var.1, BTreeSet::new())).collect();
+  let mut add = |live: &BTreeSet<VarKey>, var: Address| {
+    for other in live.iter().filter(|other| inlined(other) && other.1 != var) {
+      edges.get_mut(&var).unwrap().insert(other.1);
+      edges.get_mut(&other.1).unwrap().insert(var);
+    }
+  };
+
+  let liveness = Liveness::compute(function);
+  for (id, block) in function.blocks.iter().enumerate() {
+    let mut state = liveness.block_out[id].clone();
+    for inst in block.insts.iter().rev() {
+      if let Inst::Store { var, .. } = inst {
+        if inlined(&key(var)) {
+          add(&state, var.offset);
+        }
+      }
+      Liveness::step(&vars, &mut state, inst);
+    }
+    // Whatever is live on entry holds a value from before the function ran.
+    if id == 0 {
+      for var in state.iter().filter(|var| inlined(var)) {
+        add(&state, var.1);
+      }
+    }
+  }
+  edges
+}
+
+pub fn pack_frame(unit: &mut Unit, index: usize) -> bool {
+  let function = &unit.functions[index];
+  let first = AR_SIZE + function.declared;
+  let mut moved: BTreeMap<Address, Address> = BTreeMap::new();
+  let mut next = first;
+
+  let mut arrays: BTreeMap<Address, Address> = BTreeMap::new();
+  for inst in function.blocks.iter().flat_map(|block| &block.insts) {
+    if let Inst::LoadElem { array, .. } | Inst::StoreElem { array, .. } = inst {
+      if array.base.depth == 0 && array.base.offset >= first {
+        arrays.insert(array.base.offset, unit.arrays[array.id].length as Address);
+      }
+    }
+  }
+  for (offset, length) in arrays {
+    moved.insert(offset, next);
+    next += length;
+  }
+
+  let edges = interference(function, first);
+  let mut colors: BTreeMap<Address, Address> = BTreeMap::new();
+  for (var, others) in &edges {
+    let taken: BTreeSet<Address> = others.iter().filter_map(|other| colors.get(other).copied()).collect();
+    let color = (0..).find(|color| !taken.contains(color)).unwrap();
+    colors.insert(*var, color);
+  }
+  for (var, color) in &colors {
+    moved.insert(*var, next + color);
+  }
+  let locals = next + colors.values().map(|color| color + 1).max().unwrap_or(0) - AR_SIZE;
+
+  let function = &mut unit.functions[index];
+  let changed = locals != function.locals || moved.iter().any(|(from, to)| from != to);
+  let relocate = |var: &mut Var| {
+    if var.depth == 0 {
+      if let Some(offset) = moved.get(&var.offset) {
+        var.offset = *offset;
+      }
+    }
+  };
+  for inst in function.blocks.iter_mut().flat_map(|block| &mut block.insts) {
+    match inst {
+      Inst::Load { var, .. } | Inst::Store { var, .. } => relocate(var),
+      Inst::LoadElem { array, .. } | Inst::StoreElem { array, .. } => relocate(&mut array.base),
+      _ => {},
+    }
+  }
+  function.locals = locals;
+  changed
+}
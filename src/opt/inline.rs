@@ -0,0 +1,193 @@
+use crate::ir::*;
+use crate::lowering::AR_SIZE;
+use crate::machine::machine_types::Address;
+use super::size;
+
+// An inlined procedure's parameters, result and variables become new
+// variables at the end of the caller's record, in that order. Its blocks go
+// right after the block of the call, which jumps to them; a return jumps on
+// to the rest of that block.
+//
+// The callee's other variables are reached through the caller's static
+// links instead of its own, and a procedure that calls those nested in it
+// is never inlined, since they need its record to exist.
+
+/// Procedures of at most this many IR instructions are inlined.
+pub const INLINE_THRESHOLD: usize = 20;
+
+/// Inlining into a function stops once it has this many IR instructions.
+const MAX_INLINED_SIZE: usize = 256;
+
+fn callees(function: &Function) -> impl Iterator<Item = usize> + '_ {
+  function.blocks.iter().flat_map(|block| &block.insts).filter_map(|inst| match inst {
+    Inst::Call { callee: Callee::Local(index), .. } => Some(*index),
+    _ => None,
+  })
+}
+
+/// Functions in an order that puts each before those calling it, recursion
+/// aside.
+pub fn callees_first(unit: &Unit) -> Vec<usize> {
+  fn visit(unit: &Unit, index: usize, seen: &mut Vec<bool>, order: &mut Vec<usize>) {
+    if std::mem::replace(&mut seen[index], true) {
+      return;
+    }
+    for callee in callees(&unit.functions[index]) {
+      visit(unit, callee, seen, order);
+    }
+    order.push(index);
+  }
+  let mut seen = vec![false; unit.functions.len()];
+  let mut order: Vec<usize> = Vec::new();
+  for index in 0..unit.functions.len() {
+    visit(unit, index, &mut seen, &mut order);
+  }
+  order
+}
+
+/// The functions that can run, from the main block or an export.
+pub fn used_functions(unit: &Unit) -> Vec<bool> {
+  let mut used = vec![false; unit.functions.len()];
+  let mut work: Vec<usize> = unit.exports.iter().filter_map(|(_, export)| match export {
+    ExportRef::Function(index) => Some(*index),
+    ExportRef::Const(_) => None,
+  }).collect();
+  if unit.module.is_none() {
+    work.push(0);
+  }
+  while let Some(index) = work.pop() {
+    if !std::mem::replace(&mut used[index], true) {
+      work.extend(callees(&unit.functions[index]));
+    }
+  }
+  used
+}
+
+/// Whether function `index` can end up calling itself.
+fn recursive(unit: &Unit, index: usize) -> bool {
+  let mut seen = vec![false; unit.functions.len()];
+  let mut work: Vec<usize> = callees(&unit.functions[index]).collect();
+  while let Some(callee) = work.pop() {
+    if callee == index {
+      return true;
+    }
+    if !std::mem::replace(&mut seen[callee], true) {
+      work.extend(callees(&unit.functions[callee]));
+    }
+  }
+  false
+}
+
+fn inlinable(unit: &Unit, index: usize) -> bool {
+  let function = &unit.functions[index];
+  let calls_nested = function.blocks.iter().flat_map(|block| &block.insts)
+    .any(|inst| matches!(inst, Inst::Call { callee: Callee::Local(_), depth: 0, .. }));
+  matches!(function.kind, FunctionKind::Proc(_)) && size(function) <= INLINE_THRESHOLD && !calls_nested && !recursive(unit, index)
+}
+
+pub fn inline(unit: &mut Unit, index: usize) -> bool {
+  let mut changed = false;
+  while size(&unit.functions[index]) <= MAX_INLINED_SIZE {
+    let site = unit.functions[index].blocks.iter().enumerate().find_map(|(b, block)| {
+      block.insts.iter().enumerate().find_map(|(i, inst)| match inst {
+        Inst::Call { callee: Callee::Local(callee), .. } if *callee != index && inlinable(unit, *callee) => Some((b, i, *callee)),
+        _ => None,
+      })
+    });
+    let Some((block, at, callee)) = site else {
+      break;
+    };
+    let callee = unit.functions[callee].clone();
+    inline_call(&mut unit.functions[index], block, at, &callee);
+    changed = true;
+  }
+  changed
+}
+
+/// Replaces the call at instruction `at` of `block` with `callee`'s body.
+fn inline_call(caller: &mut Function, block: BlockId, at: usize, callee: &Function) {
+  let Inst::Call { dst, depth, args, .. } = caller.blocks[block].insts[at].clone() else {
+    panic!("inlining something other than a call");
+  };
+  let arity = callee.params.len() as Address;
+  let base = AR_SIZE + caller.locals;
+  caller.locals += arity + 1 + callee.locals;
+  let result = Var { name: format!("{}.result", callee.name), depth: 0, offset: base };
+  let var = |var: &Var| match var.depth {
+    0 if var.offset == -arity - 1 => result.clone(),
+    0 if var.offset < 0 => Var { name: format!("{}.{}", callee.name, var.name), depth: 0, offset: base + var.offset + arity + 1 },
+    0 => Var { name: format!("{}.{}", callee.name, var.name), depth: 0, offset: base + arity + 1 + var.offset - AR_SIZE },
+    outer => Var { name: var.name.clone(), depth: depth + outer - 1, offset: var.offset },
+  };
+
+  let temps = caller.temps;
+  caller.temps += callee.temps;
+  let first = block + 1;
+  let after = first + callee.blocks.len();
+  let moved = callee.blocks.len() + 1;
+  let target = |target: BlockId| if target > block { target + moved } else { target };
+  for b in &mut caller.blocks {
+    b.term = match b.term {
+      Terminator::Jump(t) => Terminator::Jump(target(t)),
+      Terminator::Branch { cond, then, otherwise } => Terminator::Branch { cond, then: target(then), otherwise: target(otherwise) },
+      ref term => term.clone(),
+    };
+  }
+
+  let mut rest = caller.blocks[block].insts.split_off(at);
+  rest.remove(0);
+  if let Some(dst) = dst {
+    rest.insert(0, Inst::Load { dst, var: result.clone() });
+  }
+  let term = std::mem::replace(&mut caller.blocks[block].term, Terminator::Jump(first));
+  let entry = &mut caller.blocks[block].insts;
+  for (i, arg) in args.into_iter().enumerate() {
+    entry.push(Inst::Store { var: var(&Var { name: callee.params[i].clone(), depth: 0, offset: i as Address - arity }), src: arg });
+  }
+  if dst.is_some() {
+    // A caller pushes a zero for the result before the arguments.
+    entry.push(Inst::Store { var: result.clone(), src: Operand::Const(0) });
+  }
+
+  let body = callee.blocks.iter().map(|b| Block {
+    insts: b.insts.iter().map(|inst| renumber(inst, temps, &var, depth)).collect(),
+    term: match b.term {
+      Terminator::Jump(t) => Terminator::Jump(first + t),
+      Terminator::Branch { cond, then, otherwise } => Terminator::Branch { cond: shift(cond, temps), then: first + then, otherwise: first + otherwise },
+      Terminator::Return | Terminator::Halt => Terminator::Jump(after),
+    },
+  });
+  let tail = Block { insts: rest, term };
+  caller.blocks.splice(first..first, body.chain(std::iter::once(tail)));
+}
+
+fn shift(operand: Operand, temps: Temp) -> Operand {
+  match operand {
+    Operand::Temp(t) => Operand::Temp(t + temps),
+    constant => constant,
+  }
+}
+
+/// An instruction of an inlined body, with its temporaries moved past the
+/// caller's and its variables and calls as seen from the caller.
+fn renumber(inst: &Inst, temps: Temp, var: &dyn Fn(&Var) -> Var, depth: usize) -> Inst {
+  let array = |array: &ArrayRef| ArrayRef { base: var(&array.base), id: array.id };
+  let mut inst = match inst {
+    Inst::Load { dst, var: v } => Inst::Load { dst: *dst + temps, var: var(v) },
+    Inst::Store { var: v, src } => Inst::Store { var: var(v), src: *src },
+    Inst::LoadElem { dst, array: a, index } => Inst::LoadElem { dst: *dst + temps, array: array(a), index: *index },
+    Inst::StoreElem { array: a, index, src } => Inst::StoreElem { array: array(a), index: *index, src: *src },
+    Inst::Call { dst, callee, depth: d, args } => {
+      Inst::Call { dst: dst.map(|t| t + temps), callee: *callee, depth: depth + d - 1, args: args.clone() }
+    },
+    Inst::Binary { dst, op, left, right } => Inst::Binary { dst: *dst + temps, op: *op, left: *left, right: *right },
+    Inst::Unary { dst, op, src } => Inst::Unary { dst: *dst + temps, op: *op, src: *src },
+    Inst::Copy { dst, src } => Inst::Copy { dst: *dst + temps, src: *src },
+    Inst::Read { dst } => Inst::Read { dst: *dst + temps },
+    Inst::Write { src, mode } => Inst::Write { src: *src, mode: *mode },
+  };
+  for operand in inst.operands_mut() {
+    *operand = shift(*operand, temps);
+  }
+  inst
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+use crate::ir::{Callee, ExportRef, Function, Inst, Unit};
+
+pub mod dataflow;
+mod propagation;
+mod dead;
+mod cfg;
+mod inline;
+mod frame;
+mod tail_calls;
+
+pub use inline::INLINE_THRESHOLD;
+
+// Optimizations work on one IR function at a time. Only inlining looks at
+// other functions, and data-flow passes never look across calls. Each pass
+// returns whether it changed the function, and `-O2` runs its passes again
+// as long as one of them does. Functions are optimized before those that
+// call them, so that a procedure is inlined as it is after its own
+// optimization.
+
+/// How much to optimize; `-O0` leaves the IR as lowered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+  #[default]
+  O0,
+  O1,
+  O2,
+}
+
+/// Rounds `-O2` runs at most, in case passes keep undoing each other.
+const MAX_ROUNDS: usize = 8;
+
+impl OptLevel {
+  /// Reads the digit of `-O<level>`.
+  pub fn from_name(name: &str) -> Option<OptLevel> {
+    match name {
+      "0" => Some(OptLevel::O0),
+      "1" => Some(OptLevel::O1),
+      "2" => Some(OptLevel::O2),
+      _ => None,
+    }
+  }
+
+  pub fn passes(self) -> &'static [Pass] {
+    match self {
+      OptLevel::O0 => &[],
+      OptLevel::O1 => &[Pass::ConstantPropagation, Pass::CopyPropagation, Pass::DeadCode],
+      OptLevel::O2 => &[
+        Pass::TailCalls, Pass::Inline, Pass::ConstantPropagation, Pass::CopyPropagation, Pass::DeadStores, Pass::DeadCode, Pass::SimplifyCfg,
+        Pass::PackFrame,
+      ],
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+  /// Turns a procedure's calls to itself right before returning into jumps.
+  TailCalls,
+  /// Replaces calls to small procedures that are not recursive with their
+  /// bodies.
+  Inline,
+  /// Folds operations on numbers, and replaces loads of variables whose
+  /// reaching definitions all store the same number.
+  ConstantPropagation,
+  /// Loads a variable instead of a copy of it, while both are unchanged.
+  CopyPropagation,
+  /// Removes stores to variables that are not live after them, and hands
+  /// a stored value straight to the only load reading it.
+  DeadStores,
+  /// Removes unused temporaries that cannot stop the program, and
+  /// unreachable blocks.
+  DeadCode,
+  /// Threads jumps through empty blocks and merges blocks that always run
+  /// one after the other.
+  SimplifyCfg,
+  /// Lets the variables of inlined procedures share words of the record
+  /// when they are never live at the same time, and drops unused ones.
+  PackFrame,
+}
+
+pub const PASSES: &[Pass] = &[
+  Pass::TailCalls, Pass::Inline, Pass::ConstantPropagation, Pass::CopyPropagation, Pass::DeadStores, Pass::DeadCode, Pass::SimplifyCfg,
+  Pass::PackFrame,
+];
+
+impl Pass {
+  pub fn id(self) -> &'static str {
+    match self {
+      Pass::TailCalls => "tail-calls",
+      Pass::Inline => "inline",
+      Pass::ConstantPropagation => "constant-propagation",
+      Pass::CopyPropagation => "copy-propagation",
+      Pass::DeadStores => "dead-stores",
+      Pass::DeadCode => "dead-code",
+      Pass::SimplifyCfg => "simplify-cfg",
+      Pass::PackFrame => "pack-frame",
+    }
+  }
+
+  pub fn from_id(id: &str) -> Option<Pass> {
+    PASSES.iter().copied().find(|pass| pass.id() == id)
+  }
+
+  /// Runs the pass on function `index` of `unit`, returning whether it
+  /// changed anything.
+  pub fn run(self, unit: &mut Unit, index: usize) -> bool {
+    let function = &mut unit.functions[index];
+    match self {
+      Pass::TailCalls => tail_calls::tail_calls(unit, index),
+      Pass::Inline => inline::inline(unit, index),
+      Pass::ConstantPropagation => propagation::constant_propagation(function),
+      Pass::CopyPropagation => propagation::copy_propagation(function),
+      Pass::DeadStores => dead::dead_stores(function),
+      Pass::DeadCode => dead::dead_code(function),
+      Pass::SimplifyCfg => cfg::simplify_cfg(function),
+      Pass::PackFrame => frame::pack_frame(unit, index),
+    }
+  }
+}
+
+/// One pass run on one function, with the function's size before and after.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassRun {
+  pub round: usize,
+  pub pass: Pass,
+  pub function: String,
+  pub before: usize,
+  pub after: usize,
+  pub changed: bool,
+}
+
+impl fmt::Display for PassRun {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "round {}: {} on {}: ", self.round, self.pass.id(), self.function)?;
+    match self.changed {
+      true => write!(f, "{} -> {} instructions", self.before, self.after),
+      false => write!(f, "unchanged"),
+    }
+  }
+}
+
+/// A function's instructions, counting each block's terminator.
+pub fn size(function: &Function) -> usize {
+  function.blocks.iter().map(|block| block.insts.len() + 1).sum()
+}
+
+/// Optimizes every function of `unit` at `level`, returning the passes run.
+pub fn optimize(unit: &mut Unit, level: OptLevel) -> Vec<PassRun> {
+  let rounds = if level == OptLevel::O2 { MAX_ROUNDS } else { 1 };
+  let mut runs: Vec<PassRun> = Vec::new();
+  for index in inline::callees_first(unit) {
+    for round in 1..=rounds {
+      let mut changed = false;
+      for &pass in level.passes() {
+        let before = size(&unit.functions[index]);
+        let pass_changed = pass.run(unit, index);
+        let function = &unit.functions[index];
+        runs.push(PassRun { round, pass, function: function.name.clone(), before, after: size(function), changed: pass_changed });
+        changed |= pass_changed;
+      }
+      if !changed {
+        break;
+      }
+    }
+  }
+  if level == OptLevel::O2 {
+    remove_unused_functions(unit);
+  }
+  runs
+}
+
+/// Drops the functions that are no longer called, such as those inlined
+/// everywhere, renumbering the rest.
+fn remove_unused_functions(unit: &mut Unit) {
+  let used = inline::used_functions(unit);
+  let mut renumbered: Vec<usize> = vec![0; used.len()];
+  let mut next = 0;
+  for (index, kept) in used.iter().enumerate() {
+    renumbered[index] = next;
+    next += *kept as usize;
+  }
+  let functions = std::mem::take(&mut unit.functions);
+  unit.functions = functions.into_iter().zip(&used).filter(|(_, kept)| **kept).map(|(mut function, _)| {
+    for inst in function.blocks.iter_mut().flat_map(|block| &mut block.insts) {
+      if let Inst::Call { callee: Callee::Local(index), .. } = inst {
+        *index = renumbered[*index];
+      }
+    }
+    function
+  }).collect();
+  for (_, export) in &mut unit.exports {
+    if let ExportRef::Function(index) = export {
+      *index = renumbered[*index];
+    }
+  }
+}
@@ -0,0 +1,230 @@
+use std::collections::{BTreeSet, HashMap};
+use crate::ir::*;
+use crate::machine::arithmetic;
+use crate::machine::machine_types::Word;
+use super::dataflow::{call_touches, key, Def, ReachingDefs, VarKey};
+
+/// The value of an instruction whose operands are all numbers, computed the
+/// way the VM would. Division by zero is left to stop the program.
+fn fold(inst: &Inst) -> Option<Word> {
+  match inst {
+    Inst::Copy { src: Operand::Const(value), .. } => Some(*value),
+    Inst::Unary { op: UnOp::Neg, src: Operand::Const(value), .. } => Some(value.wrapping_neg()),
+    Inst::Unary { op: UnOp::Odd, src: Operand::Const(value), .. } => arithmetic::modulo(*value, 2).ok(),
+    Inst::Binary { op, left: Operand::Const(left), right: Operand::Const(right), .. } => {
+      let (left, right) = (*left, *right);
+      match op {
+        BinOp::Add => Some(left.wrapping_add(right)),
+        BinOp::Sub => Some(left.wrapping_sub(right)),
+        BinOp::Mul => Some(left.wrapping_mul(right)),
+        BinOp::Div => arithmetic::divide(left, right).ok(),
+        BinOp::Mod => arithmetic::modulo(left, right).ok(),
+        BinOp::Eq => Some((left == right) as Word),
+        BinOp::Neq => Some((left != right) as Word),
+        BinOp::Lt => Some((left < right) as Word),
+        BinOp::Leq => Some((left <= right) as Word),
+        BinOp::Gt => Some((left > right) as Word),
+        BinOp::Geq => Some((left >= right) as Word),
+      }
+    },
+    _ => None,
+  }
+}
+
+/// Replaces every use of a temporary in `values` with its value.
+fn substitute(function: &mut Function, values: &HashMap<Temp, Operand>) -> bool {
+  let mut changed = false;
+  for block in &mut function.blocks {
+    let mut operands: Vec<&mut Operand> = block.insts.iter_mut().flat_map(|inst| inst.operands_mut()).collect();
+    if let Terminator::Branch { cond, .. } = &mut block.term {
+      operands.push(cond);
+    }
+    for operand in operands {
+      if let Operand::Temp(t) = operand {
+        if let Some(value) = values.get(t) {
+          *operand = *value;
+          changed = true;
+        }
+      }
+    }
+  }
+  changed
+}
+
+/// The number every definition in `defs` stores, if they all store the same.
+fn stored_constant(function: &Function, defs: &[Def]) -> Option<Word> {
+  let mut value: Option<Word> = None;
+  for def in defs {
+    let Def::Store { block, index } = def else {
+      return None;
+    };
+    let Inst::Store { src: Operand::Const(stored), .. } = function.blocks[*block].insts[*index] else {
+      return None;
+    };
+    if value.is_some_and(|v| v != stored) {
+      return None;
+    }
+    value = Some(stored);
+  }
+  value
+}
+
+pub fn constant_propagation(function: &mut Function) -> bool {
+  let mut changed = false;
+  loop {
+    let mut progress = false;
+
+    // Loads of variables that can only hold one number become that number.
+    let reaching = ReachingDefs::compute(function);
+    let mut loads: Vec<(BlockId, usize, Word)> = Vec::new();
+    for (id, block) in function.blocks.iter().enumerate() {
+      let mut state = reaching.block_in[id].clone();
+      for (index, inst) in block.insts.iter().enumerate() {
+        if let Inst::Load { var, .. } = inst {
+          if let Some(value) = stored_constant(function, &reaching.of(&state, key(var))) {
+            loads.push((id, index, value));
+          }
+        }
+        reaching.step(&mut state, id, index, inst);
+      }
+    }
+    for (id, index, value) in loads {
+      let dst = function.blocks[id].insts[index].dst().unwrap();
+      function.blocks[id].insts[index] = Inst::Copy { dst, src: Operand::Const(value) };
+      progress = true;
+    }
+
+    // Operations on numbers are done now, and their results used directly.
+    let mut values: HashMap<Temp, Operand> = HashMap::new();
+    for block in &mut function.blocks {
+      block.insts.retain(|inst| match (inst.dst(), fold(inst)) {
+        (Some(dst), Some(value)) => {
+          values.insert(dst, Operand::Const(value));
+          false
+        },
+        _ => true,
+      });
+    }
+    progress |= !values.is_empty();
+    substitute(function, &values);
+
+    for block in &mut function.blocks {
+      if let Terminator::Branch { cond: Operand::Const(value), then, otherwise } = block.term {
+        block.term = Terminator::Jump(if value != 0 { then } else { otherwise });
+        progress = true;
+      }
+    }
+
+    if !progress {
+      return changed;
+    }
+    changed = true;
+  }
+}
+
+pub fn copy_propagation(function: &mut Function) -> bool {
+  // Copies between temporaries are dropped for their source.
+  let mut values: HashMap<Temp, Operand> = HashMap::new();
+  for block in &mut function.blocks {
+    block.insts.retain(|inst| match inst {
+      Inst::Copy { dst, src } => {
+        values.insert(*dst, *src);
+        false
+      },
+      _ => true,
+    });
+  }
+  // A copy of a copy uses the original.
+  let sources: Vec<Temp> = values.keys().copied().collect();
+  for t in sources {
+    let mut value = values[&t];
+    while let Operand::Temp(next) = value {
+      match values.get(&next) {
+        Some(further) => value = *further,
+        None => break,
+      }
+    }
+    values.insert(t, value);
+  }
+  let mut changed = !values.is_empty();
+  substitute(function, &values);
+
+  // `y := x` leaves y holding x's value until either changes. The facts are
+  // pairs (y, x), and a load of y then loads x instead, unless x is further
+  // out and so takes longer to reach.
+  let vars: HashMap<VarKey, Var> = function.blocks.iter()
+    .flat_map(|block| &block.insts)
+    .filter_map(|inst| match inst {
+      Inst::Load { var, .. } | Inst::Store { var, .. } => Some((key(var), var.clone())),
+      _ => None,
+    })
+    .collect();
+  let preds = super::dataflow::predecessors(function);
+  let mut block_in: Vec<BTreeSet<(VarKey, VarKey)>> = vec![BTreeSet::new(); function.blocks.len()];
+  let mut block_out: Vec<Option<BTreeSet<(VarKey, VarKey)>>> = vec![None; function.blocks.len()];
+  let mut settled = false;
+  while !settled {
+    settled = true;
+    for id in 0..function.blocks.len() {
+      let mut outs = preds[id].iter().filter_map(|pred| block_out[*pred].as_ref());
+      let mut state = match (id, outs.next()) {
+        (0, _) | (_, None) => BTreeSet::new(),
+        (_, Some(first)) => outs.fold(first.clone(), |state, out| state.intersection(out).copied().collect()),
+      };
+      block_in[id] = state.clone();
+      copies(&mut function.blocks[id], &mut state, None);
+      if block_out[id].as_ref() != Some(&state) {
+        block_out[id] = Some(state);
+        settled = false;
+      }
+    }
+  }
+  for (id, block) in function.blocks.iter_mut().enumerate() {
+    let mut state = std::mem::take(&mut block_in[id]);
+    changed |= copies(block, &mut state, Some(&vars));
+  }
+  changed
+}
+
+/// Moves the copies held in `state` through `block`, rewriting the loads
+/// they apply to when given the function's variables. Returns whether it
+/// rewrote any.
+fn copies(block: &mut Block, state: &mut BTreeSet<(VarKey, VarKey)>, rewrite: Option<&HashMap<VarKey, Var>>) -> bool {
+  let mut changed = false;
+  // Temporaries loaded in this block from variables unchanged since.
+  let mut loaded: HashMap<Temp, VarKey> = HashMap::new();
+  for inst in &mut block.insts {
+    match inst {
+      Inst::Load { dst, var } => {
+        if let Some(&(_, source)) = state.iter().find(|(copy, source)| *copy == key(var) && source.0 <= var.depth) {
+          if let Some(vars) = rewrite {
+            *var = vars[&source].clone();
+            changed = true;
+          }
+          loaded.insert(*dst, source);
+        } else {
+          loaded.insert(*dst, key(var));
+        }
+      },
+      Inst::Store { var, src } => {
+        let target = key(var);
+        state.retain(|(copy, source)| *copy != target && *source != target);
+        loaded.retain(|_, source| *source != target);
+        if let Operand::Temp(t) = src {
+          match loaded.get(t) {
+            Some(source) if *source != target => {
+              state.insert((target, *source));
+            },
+            _ => {},
+          }
+        }
+      },
+      Inst::Call { .. } => {
+        state.retain(|(copy, source)| !call_touches(inst, *copy) && !call_touches(inst, *source));
+        loaded.retain(|_, source| !call_touches(inst, *source));
+      },
+      _ => {},
+    }
+  }
+  changed
+}
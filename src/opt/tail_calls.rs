@@ -0,0 +1,61 @@
+use crate::ast::ProcKind;
+use crate::ir::*;
+use crate::machine::machine_types::Address;
+use super::dataflow::key;
+
+// A procedure calling itself just before it returns can reuse its own
+// activation record: it stores the arguments in its parameters and jumps
+// back to its first block. A function has to be storing the call's result
+// as its own, and resets that result to the zero a new call starts with.
+// The recursion then no longer uses the stack, so it also no longer
+// overflows it.
+
+/// Whether `block` returns, either itself or by jumping through empty blocks.
+fn returns(function: &Function, mut block: BlockId) -> bool {
+  for _ in 0..function.blocks.len() {
+    match &function.blocks[block] {
+      Block { term: Terminator::Return, .. } => return true,
+      Block { insts, term: Terminator::Jump(next) } if insts.is_empty() => block = *next,
+      _ => return false,
+    }
+  }
+  false
+}
+
+pub fn tail_calls(unit: &mut Unit, index: usize) -> bool {
+  let function = &mut unit.functions[index];
+  let arity = function.params.len() as Address;
+  let result = Var { name: function.name.clone(), depth: 0, offset: -arity - 1 };
+  let is_function = function.kind == FunctionKind::Proc(ProcKind::Function);
+  let mut changed = false;
+  for id in 0..function.blocks.len() {
+    let tail = match function.blocks[id].term {
+      Terminator::Return => true,
+      Terminator::Jump(next) => returns(function, next),
+      _ => false,
+    };
+    let block = &mut function.blocks[id];
+    if !tail {
+      continue;
+    }
+    let call = match block.insts.as_slice() {
+      [.., Inst::Call { dst: Some(t), callee: Callee::Local(callee), .. }, Inst::Store { var, src: Operand::Temp(stored) }]
+        if is_function && *callee == index && key(var) == key(&result) && stored == t => block.insts.len() - 2,
+      [.., Inst::Call { dst: None, callee: Callee::Local(callee), .. }] if *callee == index => block.insts.len() - 1,
+      _ => continue,
+    };
+    let Inst::Call { args, .. } = block.insts.drain(call..).next().unwrap() else {
+      unreachable!();
+    };
+    for (i, arg) in args.into_iter().enumerate() {
+      let param = Var { name: function.params[i].clone(), depth: 0, offset: i as Address - arity };
+      block.insts.push(Inst::Store { var: param, src: arg });
+    }
+    if is_function {
+      block.insts.push(Inst::Store { var: result.clone(), src: Operand::Const(0) });
+    }
+    block.term = Terminator::Jump(0);
+    changed = true;
+  }
+  changed
+}
@@ -0,0 +1,474 @@
+use crate::ast::*;
+use crate::compile_error::CompileError;
+use crate::lexer::string_value;
+use crate::dialect::Dialect;
+use crate::token::{Token, TokenType};
+
+struct Parser<'a> {
+  tokens: &'a [Token],
+  pos: usize,
+  filename: String,
+  dialect: Dialect,
+}
+
+impl<'a> Parser<'a> {
+  fn initialize(tokens: &'a [Token], dialect: Dialect) -> Self {
+    let filename = tokens.first().map(|t| t.filename.clone()).unwrap_or_default();
+    Parser { tokens, pos: 0, filename, dialect }
+  }
+
+  fn current(&self) -> &Token {
+    &self.tokens[self.pos.min(self.tokens.len() - 1)]
+  }
+
+  fn peek_type(&self) -> TokenType {
+    self.current().typ
+  }
+
+  fn span(&self) -> Span {
+    Span { line: self.current().line, column: self.current().column }
+  }
+
+  fn advance(&mut self) -> Token {
+    let t = self.current().clone();
+    if self.pos < self.tokens.len() - 1 {
+      self.pos += 1;
+    }
+    t
+  }
+
+  fn error(&self, expected: &str) -> CompileError {
+    let t = self.current();
+    let found = if t.typ == TokenType::Eofsym { "end of file".to_string() } else { format!("'{}'", t.text) };
+    CompileError::new(&self.filename, self.span(), format!("Expected {}, but found {}.", expected, found))
+  }
+
+  fn expect(&mut self, typ: TokenType, expected: &str) -> Result<Token, CompileError> {
+    if self.peek_type() != typ {
+      return Err(self.error(expected));
+    }
+    Ok(self.advance())
+  }
+
+  fn accept(&mut self, typ: TokenType) -> bool {
+    if self.peek_type() == typ {
+      self.advance();
+      return true;
+    }
+    false
+  }
+
+  fn parse_ident(&mut self) -> Result<Ident, CompileError> {
+    let t = self.expect(TokenType::Identsym, "an identifier")?;
+    Ok(Ident { name: t.text, span: Span { line: t.line, column: t.column } })
+  }
+
+  fn parse_program(&mut self) -> Result<Program, CompileError> {
+    let mut module: Option<Ident> = None;
+    if self.dialect == Dialect::Extended && self.accept(TokenType::Modulesym) {
+      module = Some(self.parse_ident()?);
+      self.expect(TokenType::Semisym, "';'")?;
+    }
+
+    let mut imports: Vec<Ident> = Vec::new();
+    while self.accept(TokenType::Importsym) {
+      imports.push(self.parse_ident()?);
+      while self.accept(TokenType::Commasym) {
+        imports.push(self.parse_ident()?);
+      }
+      self.expect(TokenType::Semisym, "';' or ','")?;
+    }
+
+    let block = if module.is_some() { self.parse_module_block()? } else { self.parse_block()? };
+    self.expect(TokenType::Periodsym, "'.'")?;
+    self.expect(TokenType::Eofsym, "end of file after '.'")?;
+    Ok(Program { module, imports, block })
+  }
+
+  /// A module holds only constants and procedures, which it exports.
+  fn parse_module_block(&mut self) -> Result<Block, CompileError> {
+    let consts = self.parse_const_decls()?;
+    if self.peek_type() == TokenType::Varsym {
+      return Err(CompileError::new(&self.filename, self.span(), "Modules cannot declare variables; declare them in the procedures that use them.".to_string()));
+    }
+    let procs = self.parse_proc_decls()?;
+    let body = Stmt { kind: StmtKind::Skip, span: self.span() };
+    Ok(Block { consts, vars: Vec::new(), procs, body })
+  }
+
+  fn parse_block(&mut self) -> Result<Block, CompileError> {
+    let consts = self.parse_const_decls()?;
+
+    let mut vars: Vec<VarDecl> = Vec::new();
+    while self.accept(TokenType::Varsym) {
+      vars.push(self.parse_var_decl()?);
+      while self.accept(TokenType::Commasym) {
+        vars.push(self.parse_var_decl()?);
+      }
+      self.expect(TokenType::Semisym, "';' or ','")?;
+    }
+
+    let procs = self.parse_proc_decls()?;
+    let body = self.parse_stmt()?;
+    Ok(Block { consts, vars, procs, body })
+  }
+
+  fn parse_const_decls(&mut self) -> Result<Vec<ConstDef>, CompileError> {
+    let mut consts: Vec<ConstDef> = Vec::new();
+    while self.accept(TokenType::Constsym) {
+      loop {
+        let name = self.parse_ident()?;
+        self.expect(TokenType::Eqsym, "'='")?;
+        let value = self.parse_signed_number()?;
+        consts.push(ConstDef { name, value });
+        if !self.accept(TokenType::Commasym) {
+          break;
+        }
+      }
+      self.expect(TokenType::Semisym, "';' or ','")?;
+    }
+    Ok(consts)
+  }
+
+  fn parse_proc_decls(&mut self) -> Result<Vec<ProcDecl>, CompileError> {
+    let mut procs: Vec<ProcDecl> = Vec::new();
+    while self.peek_type() == TokenType::Procsym || self.peek_type() == TokenType::Funcsym {
+      procs.push(self.parse_proc_decl()?);
+    }
+    Ok(procs)
+  }
+
+  fn parse_var_decl(&mut self) -> Result<VarDecl, CompileError> {
+    let name = self.parse_ident()?;
+    let mut length: Option<i32> = None;
+    if self.accept(TokenType::Lbracketsym) {
+      let t = self.expect(TokenType::Numbersym, "the array length")?;
+      if t.value <= 0 {
+        return Err(CompileError::new(&self.filename, Span { line: t.line, column: t.column }, format!("Array '{}' must have a positive length.", name.name)));
+      }
+      length = Some(t.value);
+      self.expect(TokenType::Rbracketsym, "']'")?;
+    }
+    Ok(VarDecl { name, length })
+  }
+
+  /// Parses the optional `[ <expr> ]` after a variable name.
+  fn parse_index(&mut self) -> Result<Option<Expr>, CompileError> {
+    if !self.accept(TokenType::Lbracketsym) {
+      return Ok(None);
+    }
+    let index = self.parse_expr()?;
+    self.expect(TokenType::Rbracketsym, "']'")?;
+    Ok(Some(index))
+  }
+
+  fn parse_signed_number(&mut self) -> Result<i32, CompileError> {
+    let negative = match self.peek_type() {
+      TokenType::Minussym => { self.advance(); true },
+      TokenType::Plussym => { self.advance(); false },
+      _ => false,
+    };
+    let t = self.expect(TokenType::Numbersym, "a number")?;
+    Ok(if negative { t.value.wrapping_neg() } else { t.value })
+  }
+
+  fn parse_proc_decl(&mut self) -> Result<ProcDecl, CompileError> {
+    let kind = match self.advance().typ {
+      TokenType::Funcsym => ProcKind::Function,
+      _ => ProcKind::Procedure,
+    };
+    let name = self.parse_ident()?;
+
+    let mut params: Vec<Ident> = Vec::new();
+    if self.dialect == Dialect::Extended && self.accept(TokenType::Lparensym) {
+      if self.peek_type() != TokenType::Rparensym {
+        params.push(self.parse_ident()?);
+        while self.accept(TokenType::Commasym) {
+          params.push(self.parse_ident()?);
+        }
+      }
+      self.expect(TokenType::Rparensym, "')' or ','")?;
+    }
+
+    self.expect(TokenType::Semisym, "';'")?;
+    let block = self.parse_block()?;
+    self.expect(TokenType::Semisym, "';'")?;
+    Ok(ProcDecl { kind, name, params, block })
+  }
+
+  fn parse_args(&mut self) -> Result<Vec<Expr>, CompileError> {
+    let mut args: Vec<Expr> = Vec::new();
+    if self.dialect == Dialect::Extended && self.accept(TokenType::Lparensym) {
+      if self.peek_type() != TokenType::Rparensym {
+        args.push(self.parse_expr()?);
+        while self.accept(TokenType::Commasym) {
+          args.push(self.parse_expr()?);
+        }
+      }
+      self.expect(TokenType::Rparensym, "')' or ','")?;
+    }
+    Ok(args)
+  }
+
+  fn parse_stmt(&mut self) -> Result<Stmt, CompileError> {
+    let span = self.span();
+    let kind = match self.peek_type() {
+      TokenType::Identsym => {
+        let target = self.parse_ident()?;
+        let index = self.parse_index()?;
+        self.expect(TokenType::Becomessym, "':='")?;
+        let value = self.parse_expr()?;
+        StmtKind::Assign { target, index, value }
+      },
+      TokenType::Callsym => {
+        self.advance();
+        let name = self.parse_ident()?;
+        let args = self.parse_args()?;
+        StmtKind::Call { name, args }
+      },
+      TokenType::Beginsym => {
+        self.advance();
+        let mut stmts: Vec<Stmt> = vec![self.parse_stmt()?];
+        while self.accept(TokenType::Semisym) {
+          stmts.push(self.parse_stmt()?);
+        }
+        self.expect(TokenType::Endsym, "';' or 'end'")?;
+        StmtKind::Begin(stmts)
+      },
+      TokenType::Ifsym => {
+        self.advance();
+        let cond = self.parse_condition()?;
+        self.expect(TokenType::Thensym, "'then'")?;
+        let then_stmt = Box::new(self.parse_stmt()?);
+        // The extended dialect makes `else` optional; it binds to the nearest `if`.
+        let else_stmt = if self.dialect == Dialect::Extended && self.peek_type() != TokenType::Elsesym {
+          None
+        } else {
+          self.expect(TokenType::Elsesym, "'else'")?;
+          Some(Box::new(self.parse_stmt()?))
+        };
+        StmtKind::If { cond, then_stmt, else_stmt }
+      },
+      TokenType::Whilesym => {
+        self.advance();
+        let cond = self.parse_condition()?;
+        self.expect(TokenType::Dosym, "'do'")?;
+        let body = Box::new(self.parse_stmt()?);
+        StmtKind::While { cond, body }
+      },
+      TokenType::Repeatsym => {
+        self.advance();
+        let mut body: Vec<Stmt> = vec![self.parse_stmt()?];
+        while self.accept(TokenType::Semisym) {
+          body.push(self.parse_stmt()?);
+        }
+        self.expect(TokenType::Untilsym, "';' or 'until'")?;
+        let cond = self.parse_condition()?;
+        StmtKind::Repeat { body, cond }
+      },
+      TokenType::Forsym => {
+        self.advance();
+        let var = self.parse_ident()?;
+        self.expect(TokenType::Becomessym, "':='")?;
+        let from = self.parse_expr()?;
+        let downto = match self.peek_type() {
+          TokenType::Tosym => false,
+          TokenType::Downtosym => true,
+          _ => return Err(self.error("'to' or 'downto'")),
+        };
+        self.advance();
+        let to = self.parse_expr()?;
+        self.expect(TokenType::Dosym, "'do'")?;
+        let body = Box::new(self.parse_stmt()?);
+        StmtKind::For { var, from, to, downto, body }
+      },
+      TokenType::Breaksym => {
+        self.advance();
+        StmtKind::Break
+      },
+      TokenType::Continuesym => {
+        self.advance();
+        StmtKind::Continue
+      },
+      TokenType::Readsym => {
+        self.advance();
+        let target = self.parse_ident()?;
+        let index = self.parse_index()?;
+        StmtKind::Read { target, index }
+      },
+      TokenType::Writesym => {
+        self.advance();
+        StmtKind::Write(self.parse_write_arg()?)
+      },
+      TokenType::Writelnsym => {
+        self.advance();
+        let mut args: Vec<WriteArg> = Vec::new();
+        if self.peek_type() == TokenType::Stringsym || self.starts_expr() {
+          args.push(self.parse_write_arg()?);
+          while self.accept(TokenType::Commasym) {
+            args.push(self.parse_write_arg()?);
+          }
+        }
+        StmtKind::Writeln(args)
+      },
+      TokenType::Skipsym => {
+        self.advance();
+        StmtKind::Skip
+      },
+      _ => return Err(self.error("a statement")),
+    };
+    Ok(Stmt { kind, span })
+  }
+
+  /// One argument of `write` or `writeln`: a string or an expression.
+  fn parse_write_arg(&mut self) -> Result<WriteArg, CompileError> {
+    if self.peek_type() == TokenType::Stringsym {
+      return Ok(WriteArg::Str(string_value(&self.advance().text)));
+    }
+    Ok(WriteArg::Expr(self.parse_expr()?))
+  }
+
+  fn starts_expr(&self) -> bool {
+    matches!(self.peek_type(),
+      TokenType::Identsym | TokenType::Numbersym | TokenType::Lparensym | TokenType::Plussym | TokenType::Minussym)
+  }
+
+  /// In the extended dialect, `or` binds looser than `and`, which binds
+  /// looser than `not`.
+  fn parse_condition(&mut self) -> Result<Condition, CompileError> {
+    if self.dialect == Dialect::Standard {
+      return self.parse_simple_condition();
+    }
+    let mut left = self.parse_and_condition()?;
+    while self.accept(TokenType::Orsym) {
+      let right = self.parse_and_condition()?;
+      left = Condition::Or(Box::new(left), Box::new(right));
+    }
+    Ok(left)
+  }
+
+  fn parse_and_condition(&mut self) -> Result<Condition, CompileError> {
+    let mut left = self.parse_not_condition()?;
+    while self.accept(TokenType::Andsym) {
+      let right = self.parse_not_condition()?;
+      left = Condition::And(Box::new(left), Box::new(right));
+    }
+    Ok(left)
+  }
+
+  fn parse_not_condition(&mut self) -> Result<Condition, CompileError> {
+    if self.accept(TokenType::Notsym) {
+      return Ok(Condition::Not(Box::new(self.parse_not_condition()?)));
+    }
+    if self.peek_type() != TokenType::Lparensym {
+      return self.parse_simple_condition();
+    }
+    // A '(' starts either an expression, as in `(a + 1) < b`, or a nested
+    // condition, as in `(a < b) or c`. Try the comparison first and back up
+    // if it does not parse.
+    let start = self.pos;
+    if let Ok(cond) = self.parse_simple_condition() {
+      return Ok(cond);
+    }
+    self.pos = start;
+    self.advance();
+    let cond = self.parse_condition()?;
+    self.expect(TokenType::Rparensym, "')'")?;
+    Ok(cond)
+  }
+
+  fn parse_simple_condition(&mut self) -> Result<Condition, CompileError> {
+    if self.accept(TokenType::Oddsym) {
+      return Ok(Condition::Odd(self.parse_expr()?));
+    }
+
+    let left = self.parse_expr()?;
+    let op = match self.peek_type() {
+      TokenType::Eqsym => RelOp::Eq,
+      TokenType::Neqsym => RelOp::Neq,
+      TokenType::Lessym => RelOp::Lt,
+      TokenType::Leqsym => RelOp::Leq,
+      TokenType::Gtrsym => RelOp::Gt,
+      TokenType::Geqsym => RelOp::Geq,
+      _ => return Err(self.error("a relational operator")),
+    };
+    self.advance();
+    let right = self.parse_expr()?;
+    Ok(Condition::Rel { left, op, right })
+  }
+
+  fn parse_expr(&mut self) -> Result<Expr, CompileError> {
+    let mut left = self.parse_term()?;
+    loop {
+      let op = match self.peek_type() {
+        TokenType::Plussym => BinOp::Add,
+        TokenType::Minussym => BinOp::Sub,
+        _ => return Ok(left),
+      };
+      self.advance();
+      let right = self.parse_term()?;
+      let span = left.span;
+      left = Expr { kind: ExprKind::Binary { left: Box::new(left), op, right: Box::new(right) }, span };
+    }
+  }
+
+  fn parse_term(&mut self) -> Result<Expr, CompileError> {
+    let mut left = self.parse_factor()?;
+    loop {
+      let op = match self.peek_type() {
+        TokenType::Multsym => BinOp::Mul,
+        TokenType::Divsym => BinOp::Div,
+        TokenType::Modsym => BinOp::Mod,
+        _ => return Ok(left),
+      };
+      self.advance();
+      let right = self.parse_factor()?;
+      let span = left.span;
+      left = Expr { kind: ExprKind::Binary { left: Box::new(left), op, right: Box::new(right) }, span };
+    }
+  }
+
+  fn parse_factor(&mut self) -> Result<Expr, CompileError> {
+    let span = self.span();
+    let kind = match self.peek_type() {
+      TokenType::Identsym => {
+        let name = self.parse_ident()?;
+        if self.dialect == Dialect::Extended && self.peek_type() == TokenType::Lparensym {
+          let args = self.parse_args()?;
+          ExprKind::Call { name, args }
+        } else if let Some(index) = self.parse_index()? {
+          ExprKind::Index { name, index: Box::new(index) }
+        } else {
+          ExprKind::Name(name)
+        }
+      },
+      TokenType::Minussym if self.dialect == Dialect::Extended => {
+        self.advance();
+        ExprKind::Neg(Box::new(self.parse_factor()?))
+      },
+      TokenType::Plussym if self.dialect == Dialect::Extended => {
+        self.advance();
+        self.parse_factor()?.kind
+      },
+      TokenType::Numbersym | TokenType::Plussym | TokenType::Minussym => ExprKind::Number(self.parse_signed_number()?),
+      TokenType::Lparensym => {
+        self.advance();
+        let inner = self.parse_expr()?;
+        self.expect(TokenType::Rparensym, "')'")?;
+        return Ok(Expr { kind: inner.kind, span });
+      },
+      _ => return Err(self.error("an identifier, a number or '('")),
+    };
+    Ok(Expr { kind, span })
+  }
+}
+
+/// Parses a token stream (as produced by the lexer, ending in `Eofsym`) into
+/// a program.
+pub fn parse(tokens: &[Token], dialect: Dialect) -> Result<Program, CompileError> {
+  if tokens.is_empty() {
+    return Err(CompileError::new("", Span::default(), "Expected a program, but found end of file.".to_string()));
+  }
+  let mut parser = Parser::initialize(tokens, dialect);
+  parser.parse_program()
+}
@@ -0,0 +1,327 @@
+use std::io::{BufRead, Write};
+use crate::ast::{Program, StmtKind, VarDecl, WriteArg};
+use crate::ast_dump::{dump_with, walk_const, walk_expr, walk_proc, walk_stmt, walk_var, AstFormat, Value};
+use crate::compile_error::CompileError;
+use crate::compiler::{compile_program, CompileOptions};
+use crate::lexer::lex_with_options;
+use crate::machine::instruction::{print_formatted_instruction, print_instruction_heading};
+use crate::machine::machine::resume_code;
+use crate::machine::machine_types::{Address, Executable};
+use crate::machine::stack::Stack;
+use crate::opt::OptLevel;
+use crate::parser;
+use crate::token::TokenType;
+
+const FILENAME: &str = "<repl>";
+const PROMPT: &str = "pl0> ";
+const CONTINUATION: &str = "...> ";
+/// The main block's first variable comes after its activation record.
+const FIRST_VARIABLE: Address = 3;
+
+const HELP: &str = "Enter a declaration (`import`, `const`, `var`, `procedure`, `function`), a statement or an expression.
+Input that is not yet complete continues on the next line; an empty line gives up on it.
+  :tokens <input>  show the tokens of the input
+  :ast <input>     show the syntax tree of the input
+  :asm [<input>]   show the instructions generated for the input
+  :stack           show the variables and the current stack frame
+  :help            show this help
+  :quit            leave the REPL";
+
+/// What one input is, decided by its first tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+  Import,
+  Const,
+  Var,
+  Proc,
+  Statement,
+  /// Written out with `write`.
+  Expression,
+}
+
+const DECLARATIONS: [Kind; 4] = [Kind::Import, Kind::Const, Kind::Var, Kind::Proc];
+
+enum Outcome {
+  Done,
+  /// The input ended before the error, so more lines may complete it.
+  Incomplete(CompileError),
+}
+
+/// The declarations accepted so far, and the stack that keeps the values of
+/// the variables among them. Each input is compiled as a whole program after
+/// these declarations; variables are only ever added at the end, so they
+/// keep their stack offsets from one program to the next.
+struct Session {
+  options: CompileOptions,
+  declarations: Vec<(Kind, String)>,
+  variables: Vec<VarDecl>,
+  stack: Stack,
+}
+
+/// Reads inputs until `:quit` or the end of `input`, which also supplies
+/// what the programs `read`.
+pub fn run(options: CompileOptions, input: &mut dyn BufRead, output: &mut dyn Write) {
+  // Optimizing would drop stores to variables no later statement reads yet.
+  let options = CompileOptions { opt_level: OptLevel::O0, ..options };
+  let mut session = Session { options, declarations: Vec::new(), variables: Vec::new(), stack: Stack::initialize() };
+  let _ = writeln!(output, "rustpl0 REPL. Type :help for help.");
+  let mut pending = String::new();
+  let mut held: Option<CompileError> = None;
+  loop {
+    let _ = write!(output, "{}", if pending.is_empty() { PROMPT } else { CONTINUATION });
+    let _ = output.flush();
+    let mut line = String::new();
+    if !matches!(input.read_line(&mut line), Ok(n) if n > 0) {
+      let _ = writeln!(output);
+      break;
+    }
+    let line = line.trim_end_matches(['\r', '\n']);
+
+    if pending.is_empty() {
+      if line.trim().is_empty() {
+        continue;
+      }
+      if let Some(command) = line.trim().strip_prefix(':') {
+        if !session.command(command, output) {
+          break;
+        }
+        continue;
+      }
+    } else if line.trim().is_empty() {
+      if let Some(err) = held.take() {
+        let _ = write!(output, "{}", err.render(&pending));
+      }
+      pending.clear();
+      continue;
+    } else {
+      pending.push('\n');
+    }
+    pending += line;
+
+    match session.submit(&pending, input, output) {
+      Outcome::Done => pending.clear(),
+      Outcome::Incomplete(err) => held = Some(err),
+    }
+  }
+}
+
+fn kind(text: &str, options: &CompileOptions) -> Result<Kind, CompileError> {
+  let tokens = lex_with_options(FILENAME, text.as_bytes(), options.lexer.clone())?;
+  Ok(match tokens[0].typ {
+    TokenType::Importsym => Kind::Import,
+    TokenType::Constsym => Kind::Const,
+    TokenType::Varsym => Kind::Var,
+    TokenType::Procsym | TokenType::Funcsym => Kind::Proc,
+    TokenType::Identsym | TokenType::Numbersym | TokenType::Lparensym | TokenType::Minussym | TokenType::Plussym
+      if !tokens.iter().any(|t| t.typ == TokenType::Becomessym) => Kind::Expression,
+    _ => Kind::Statement,
+  })
+}
+
+/// Builds a program from `declarations` and one more input, returning it
+/// with the line the input starts on.
+fn assemble(declarations: &[(Kind, String)], kind: Kind, text: &str) -> (String, i32) {
+  let mut source = String::new();
+  let mut start = 0;
+  let mut insert = |source: &mut String, text: &str| {
+    start = source.matches('\n').count() as i32 + 1;
+    *source += text;
+    source.push('\n');
+  };
+  for section in DECLARATIONS {
+    for (_, declaration) in declarations.iter().filter(|(k, _)| *k == section) {
+      source += declaration;
+      source.push('\n');
+    }
+    if section == kind {
+      insert(&mut source, text);
+    }
+  }
+  source += "begin\n";
+  match kind {
+    Kind::Statement => insert(&mut source, text),
+    Kind::Expression => {
+      source += "write\n";
+      insert(&mut source, text);
+    },
+    _ => source += "skip\n",
+  }
+  source += "end.\n";
+  (source, start)
+}
+
+/// Declarations end with `;` and statements do not, whichever way they were
+/// typed. A missing `;` goes on a line of its own, so that a declaration cut
+/// short still fails after its last line.
+fn normalize(kind: Kind, text: &str) -> String {
+  let text = text.trim_end();
+  match kind {
+    Kind::Statement | Kind::Expression => text.trim_end_matches(';').trim_end().to_string(),
+    _ if text.ends_with(';') => text.to_string(),
+    _ => format!("{}\n;", text),
+  }
+}
+
+impl Session {
+  fn submit(&mut self, typed: &str, input: &mut dyn BufRead, output: &mut dyn Write) -> Outcome {
+    let (kind, text) = match kind(typed, &self.options) {
+      Ok(kind) => (kind, normalize(kind, typed)),
+      Err(err) => {
+        let _ = write!(output, "{}", err.render(typed));
+        return Outcome::Done;
+      },
+    };
+    let (code, program) = match self.compile(kind, &text) {
+      Ok(compiled) => compiled,
+      Err(err) if err.filename == FILENAME && err.line > typed.lines().count() as i32 => return Outcome::Incomplete(err),
+      Err(err) => {
+        let _ = write!(output, "{}", err.render(&text));
+        return Outcome::Done;
+      },
+    };
+
+    match kind {
+      Kind::Statement | Kind::Expression => {
+        if let Err(msg) = resume_code(code, &mut self.stack, input, output) {
+          let _ = writeln!(output, "{}", msg);
+        }
+      },
+      _ => {
+        // Slots past the old variables may have held temporaries.
+        let start: Address = FIRST_VARIABLE + self.variables.iter().map(|v| v.length.unwrap_or(1)).sum::<Address>();
+        let added: Address = program.block.vars[self.variables.len()..].iter().map(|v| v.length.unwrap_or(1)).sum();
+        for addr in start..start + added {
+          let _ = self.stack.assign(addr, 0);
+        }
+        self.variables = program.block.vars;
+        self.declarations.push((kind, text));
+      },
+    }
+    Outcome::Done
+  }
+
+  /// Compiles the accepted declarations with one more input, reporting errors
+  /// at their line in the input.
+  fn compile(&self, kind: Kind, text: &str) -> Result<(Executable, Program), CompileError> {
+    let (source, start) = assemble(&self.declarations, kind, text);
+    lex_with_options(FILENAME, source.as_bytes(), self.options.lexer.clone())
+      .map_err(CompileError::from)
+      .and_then(|tokens| Ok((compile_program(&tokens, &self.options)?, parser::parse(&tokens, self.options.lexer.dialect)?)))
+      .map_err(|mut err| {
+        if err.filename == FILENAME {
+          err.line -= start - 1;
+        }
+        err
+      })
+  }
+
+  /// Runs `:<command>`; returns whether to keep reading.
+  fn command(&mut self, command: &str, output: &mut dyn Write) -> bool {
+    let (name, text) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+    let text = text.trim();
+    match name {
+      "tokens" => self.show_tokens(text, output),
+      "ast" => self.show_ast(text, output),
+      "asm" => self.show_asm(text, output),
+      "stack" => self.show_stack(output),
+      "help" => {
+        let _ = writeln!(output, "{}", HELP);
+      },
+      "quit" | "q" => return false,
+      _ => {
+        let _ = writeln!(output, "Error: Unknown command `:{}`! Type :help for help.", name);
+      },
+    }
+    true
+  }
+
+  fn show_tokens(&self, text: &str, output: &mut dyn Write) {
+    match lex_with_options(FILENAME, text.as_bytes(), self.options.lexer.clone()) {
+      Ok(tokens) => {
+        for token in tokens.iter().filter(|t| t.typ != TokenType::Eofsym) {
+          let shown = if token.typ == TokenType::Numbersym { token.value.to_string() } else { token.text.clone() };
+          let _ = writeln!(output, "{:<12} {:>4}:{:<4} {}", token.ttyp2str(), token.line, token.column, shown);
+        }
+      },
+      Err(err) => {
+        let _ = write!(output, "{}", CompileError::from(err).render(text));
+      },
+    }
+  }
+
+  /// Shows the input's own syntax tree, parsed without the session's
+  /// declarations since names are not resolved.
+  fn show_ast(&self, text: &str, output: &mut dyn Write) {
+    let parsed = kind(text, &self.options).and_then(|kind| {
+      let text = normalize(kind, text);
+      let (source, start) = assemble(&[], kind, &text);
+      let mut tokens = lex_with_options(FILENAME, source.as_bytes(), self.options.lexer.clone())?;
+      for token in &mut tokens {
+        token.line -= start - 1;
+      }
+      parser::parse(&tokens, self.options.lexer.dialect)
+        .map(|program| (kind, program))
+    });
+    let (kind, program) = match parsed {
+      Ok(parsed) => parsed,
+      Err(err) => {
+        let _ = write!(output, "{}", err.render(text));
+        return;
+      },
+    };
+    let block = &program.block;
+    let statements = match &block.body.kind {
+      StmtKind::Begin(statements) => statements.as_slice(),
+      _ => &[],
+    };
+    let tree = dump_with(AstFormat::Text, |v| match kind {
+      Kind::Import => program.imports.iter().for_each(|import| v.leaf("Import", import.span, Value::Name(&import.name))),
+      Kind::Const => block.consts.iter().for_each(|c| walk_const(c, v)),
+      Kind::Var => block.vars.iter().for_each(|var| walk_var(var, v)),
+      Kind::Proc => block.procs.iter().for_each(|proc| walk_proc(proc, v)),
+      Kind::Statement => statements.iter().for_each(|stmt| walk_stmt(stmt, v)),
+      Kind::Expression => {
+        if let Some(StmtKind::Write(WriteArg::Expr(expr))) = statements.first().map(|s| &s.kind) {
+          walk_expr(expr, v);
+        }
+      },
+    });
+    let _ = write!(output, "{}", tree);
+  }
+
+  /// Shows the whole program compiled for the input, or for the session
+  /// alone when there is none.
+  fn show_asm(&self, text: &str, output: &mut dyn Write) {
+    let compiled = match text {
+      "" => self.compile(Kind::Statement, "skip"),
+      _ => kind(text, &self.options).and_then(|kind| self.compile(kind, &normalize(kind, text))),
+    };
+    match compiled {
+      Ok((code, _)) => {
+        print_instruction_heading(output);
+        for (addr, instruction) in code.code.iter().enumerate() {
+          print_formatted_instruction(output, addr as Address, instruction);
+        }
+      },
+      Err(err) => {
+        let _ = write!(output, "{}", err.render(text));
+      },
+    }
+  }
+
+  fn show_stack(&self, output: &mut dyn Write) {
+    let mut addr = FIRST_VARIABLE;
+    for variable in &self.variables {
+      let length = variable.length.unwrap_or(1);
+      let values: Vec<String> = (addr..addr + length).map(|a| self.stack.fetch(a).unwrap_or(0).to_string()).collect();
+      let _ = match variable.length {
+        Some(_) => writeln!(output, "{} = [{}]", variable.name.name, values.join(", ")),
+        None => writeln!(output, "{} = {}", variable.name.name, values[0]),
+      };
+      addr += length;
+    }
+    if !self.stack.is_empty() {
+      self.stack.print_stack(output);
+    }
+  }
+}
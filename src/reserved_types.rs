@@ -1,24 +1,60 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
 use crate::token::TokenType::{self, *};
+use crate::dialect::Dialect::{self, *};
 
-const RESERVED_WORDS: [&str; 15] = [
-  "const", "var", "procedure",
-  "call", "begin", "end",
-	"if", "then", "else", "while", "do",
-  "read", "write", "skip", "odd",
-];
+/// Every reserved word: its text, the token it lexes to, and the dialect it
+/// is reserved in. Words of the standard dialect are reserved in every
+/// dialect; adding a keyword only takes a new `TokenType` and a row here.
+pub const KEYWORDS: &[(&str, TokenType, Dialect)] = &[
+  ("const", Constsym, Standard),
+  ("var", Varsym, Standard),
+  ("procedure", Procsym, Standard),
+  ("call", Callsym, Standard),
+  ("begin", Beginsym, Standard),
+  ("end", Endsym, Standard),
+  ("if", Ifsym, Standard),
+  ("then", Thensym, Standard),
+  ("else", Elsesym, Standard),
+  ("while", Whilesym, Standard),
+  ("do", Dosym, Standard),
+  ("read", Readsym, Standard),
+  ("write", Writesym, Standard),
+  ("skip", Skipsym, Standard),
+  ("odd", Oddsym, Standard),
 
-const RESERVED_TYPES: [TokenType; 15] = [
-  Constsym, Varsym, Procsym,
-  Callsym, Beginsym, Endsym,
-  Ifsym, Thensym, Elsesym, Whilesym, Dosym,
-  Readsym, Writesym, Skipsym, Oddsym,
+  ("function", Funcsym, Extended),
+  ("repeat", Repeatsym, Extended),
+  ("until", Untilsym, Extended),
+  ("for", Forsym, Extended),
+  ("to", Tosym, Extended),
+  ("downto", Downtosym, Extended),
+  ("break", Breaksym, Extended),
+  ("continue", Continuesym, Extended),
+  ("and", Andsym, Extended),
+  ("or", Orsym, Extended),
+  ("not", Notsym, Extended),
+  ("mod", Modsym, Extended),
+  ("writeln", Writelnsym, Extended),
+  ("module", Modulesym, Extended),
+  ("import", Importsym, Extended),
 ];
 
-pub fn get_reserved_word(word: &str) -> TokenType {
-  for i in 0..RESERVED_WORDS.len() {
-    if word == RESERVED_WORDS[i] {
-      return RESERVED_TYPES[i];
-    }
+fn keyword_map() -> &'static HashMap<&'static str, (TokenType, Dialect)> {
+  static MAP: OnceLock<HashMap<&'static str, (TokenType, Dialect)>> = OnceLock::new();
+  MAP.get_or_init(|| KEYWORDS.iter().map(|&(text, typ, dialect)| (text, (typ, dialect))).collect())
+}
+
+/// The token type of `word`: its keyword if it is reserved in `dialect`, or
+/// `Identsym`. With `ignore_case`, keywords match in any letter case.
+pub fn get_reserved_word(word: &str, dialect: Dialect, ignore_case: bool) -> TokenType {
+  let found = if ignore_case {
+    keyword_map().get(word.to_ascii_lowercase().as_str())
+  } else {
+    keyword_map().get(word)
+  };
+  match found {
+    Some(&(typ, reserved_in)) if reserved_in == Standard || reserved_in == dialect => typ,
+    _ => Identsym,
   }
-  Identsym
-}
\ No newline at end of file
+}
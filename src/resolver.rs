@@ -0,0 +1,296 @@
+use crate::ast::*;
+use crate::compile_error::CompileError;
+use crate::object::{ExportValue, Object};
+use crate::scope::{Scopes, Symbol};
+
+/// Where a declared name comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+  Declared,
+  Parameter,
+  /// Exported by the named module; the declaration's span is the import.
+  Imported(String),
+}
+
+/// A name declared in a program, or imported into it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declaration {
+  pub name: Ident,
+  pub symbol: Symbol,
+  pub level: usize,
+  pub origin: Origin,
+  /// The parameter names of a procedure or function declared here.
+  pub params: Vec<String>,
+}
+
+/// A use of an identifier and the declaration it refers to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reference {
+  pub span: Span,
+  pub declaration: usize,
+}
+
+/// Every declaration in a program and every identifier use resolved to one.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymbolIndex {
+  pub declarations: Vec<Declaration>,
+  pub references: Vec<Reference>,
+}
+
+struct Resolver {
+  filename: String,
+  // Each name's symbol and its index in `index.declarations`.
+  scopes: Scopes<(Symbol, usize)>,
+  index: SymbolIndex,
+  // Functions whose bodies we are inside, with the level they were declared
+  // at; assigning to one of these sets its return value.
+  functions: Vec<(String, usize)>,
+  // How many loops enclose the statement being resolved.
+  loop_depth: usize,
+}
+
+impl Resolver {
+  fn error(&self, span: Span, msg: String) -> CompileError {
+    CompileError::new(&self.filename, span, msg)
+  }
+
+  fn declare(&mut self, name: &Ident, symbol: Symbol, origin: Origin, params: Vec<String>) -> bool {
+    let id = self.index.declarations.len();
+    if !self.scopes.declare(&name.name, (symbol, id)) {
+      return false;
+    }
+    let level = self.scopes.level();
+    self.index.declarations.push(Declaration { name: name.clone(), symbol, level, origin, params });
+    true
+  }
+
+  fn declare_here(&mut self, name: &Ident, symbol: Symbol, origin: Origin, params: Vec<String>) -> Result<(), CompileError> {
+    if !self.declare(name, symbol, origin, params) {
+      return Err(self.error(name.span, format!("'{}' is already declared in this scope.", name.name)));
+    }
+    Ok(())
+  }
+
+  fn lookup(&mut self, name: &Ident) -> Result<(Symbol, usize), CompileError> {
+    match self.scopes.lookup(&name.name) {
+      Some((&(symbol, declaration), level)) => {
+        self.index.references.push(Reference { span: name.span, declaration });
+        Ok((symbol, level))
+      },
+      None => Err(self.error(name.span, format!("Undeclared identifier '{}'.", name.name))),
+    }
+  }
+
+  fn resolve_program(&mut self, program: &Program, imports: &[&Object]) -> Result<(), CompileError> {
+    for (module, object) in program.imports.iter().zip(imports) {
+      for export in &object.exports {
+        let symbol = match export.value {
+          ExportValue::Const(value) => Symbol::Const(value),
+          ExportValue::Proc { kind, arity, .. } => Symbol::Proc { kind, arity, address: 0, import: None },
+        };
+        let name = Ident { name: export.name.clone(), span: module.span };
+        if !self.declare(&name, symbol, Origin::Imported(module.name.clone()), Vec::new()) {
+          return Err(self.error(module.span, format!("'{}' imported from module '{}' is already declared.", export.name, module.name)));
+        }
+      }
+    }
+    self.resolve_block(&program.block)
+  }
+
+  fn resolve_block(&mut self, block: &Block) -> Result<(), CompileError> {
+    for c in &block.consts {
+      self.declare_here(&c.name, Symbol::Const(c.value), Origin::Declared, Vec::new())?;
+    }
+    for v in &block.vars {
+      let symbol = match v.length {
+        Some(length) => Symbol::Array { offset: 0, length, id: 0 },
+        None => Symbol::Var { offset: 0 },
+      };
+      self.declare_here(&v.name, symbol, Origin::Declared, Vec::new())?;
+    }
+    for p in &block.procs {
+      let params = p.params.iter().map(|param| param.name.clone()).collect();
+      self.declare_here(&p.name, Symbol::Proc { kind: p.kind, arity: p.params.len(), address: 0, import: None }, Origin::Declared, params)?;
+      self.scopes.push();
+      for param in &p.params {
+        self.declare_here(param, Symbol::Var { offset: 0 }, Origin::Parameter, Vec::new())?;
+      }
+      if p.kind == ProcKind::Function {
+        self.functions.push((p.name.name.clone(), self.scopes.level() - 1));
+      }
+      self.resolve_block(&p.block)?;
+      if p.kind == ProcKind::Function {
+        self.functions.pop();
+      }
+      self.scopes.pop();
+    }
+    self.resolve_stmt(&block.body)
+  }
+
+  fn check_call(&self, name: &Ident, args: &[Expr], symbol: Symbol) -> Result<(), CompileError> {
+    match symbol {
+      Symbol::Proc { kind, arity, .. } => {
+        if args.len() != arity {
+          let what = if kind == ProcKind::Function { "Function" } else { "Procedure" };
+          return Err(self.error(name.span, format!("{} '{}' expects {} argument(s), but was given {}.", what, name.name, arity, args.len())));
+        }
+        Ok(())
+      },
+      _ => Err(self.error(name.span, format!("'{}' is not a procedure or function.", name.name))),
+    }
+  }
+
+  /// Checks that `name` is indexed exactly when it names an array.
+  fn check_index(&mut self, name: &Ident, symbol: Symbol, index: Option<&Expr>) -> Result<(), CompileError> {
+    match (symbol, index) {
+      (Symbol::Array { .. }, Some(index)) => self.resolve_expr(index),
+      (Symbol::Array { .. }, None) => Err(self.error(name.span, format!("Array '{}' must be indexed.", name.name))),
+      (_, Some(_)) => Err(self.error(name.span, format!("'{}' is not an array.", name.name))),
+      (_, None) => Ok(()),
+    }
+  }
+
+  fn resolve_stmt(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+    match &stmt.kind {
+      StmtKind::Assign { target, index, value } => {
+        let (symbol, level) = self.lookup(target)?;
+        self.check_index(target, symbol, index.as_ref())?;
+        match symbol {
+          Symbol::Var { .. } | Symbol::Array { .. } => {},
+          Symbol::Const(_) => return Err(self.error(target.span, format!("Cannot assign to constant '{}'.", target.name))),
+          Symbol::Proc { kind: ProcKind::Function, .. } => {
+            if !self.functions.iter().any(|(name, l)| *name == target.name && *l == level) {
+              return Err(self.error(target.span, format!("Cannot assign to function '{}' outside its body.", target.name)));
+            }
+          },
+          Symbol::Proc { .. } => return Err(self.error(target.span, format!("Cannot assign to procedure '{}'.", target.name))),
+        }
+        self.resolve_expr(value)
+      },
+      StmtKind::Call { name, args } => {
+        let (symbol, _) = self.lookup(name)?;
+        self.check_call(name, args, symbol)?;
+        args.iter().try_for_each(|arg| self.resolve_expr(arg))
+      },
+      StmtKind::Begin(stmts) => stmts.iter().try_for_each(|s| self.resolve_stmt(s)),
+      StmtKind::If { cond, then_stmt, else_stmt } => {
+        self.resolve_condition(cond)?;
+        self.resolve_stmt(then_stmt)?;
+        else_stmt.iter().try_for_each(|s| self.resolve_stmt(s))
+      },
+      StmtKind::While { cond, body } => {
+        self.resolve_condition(cond)?;
+        self.resolve_loop_body(std::slice::from_ref(body.as_ref()))
+      },
+      StmtKind::Repeat { body, cond } => {
+        self.resolve_loop_body(body)?;
+        self.resolve_condition(cond)
+      },
+      StmtKind::For { var, from, to, body, .. } => {
+        match self.lookup(var)? {
+          (Symbol::Var { .. }, _) => {},
+          _ => return Err(self.error(var.span, format!("The for loop counter '{}' must be a variable.", var.name))),
+        }
+        self.resolve_expr(from)?;
+        self.resolve_expr(to)?;
+        self.resolve_loop_body(std::slice::from_ref(body.as_ref()))
+      },
+      StmtKind::Break | StmtKind::Continue => {
+        if self.loop_depth == 0 {
+          let word = if stmt.kind == StmtKind::Break { "break" } else { "continue" };
+          return Err(self.error(stmt.span, format!("'{}' is not inside a loop.", word)));
+        }
+        Ok(())
+      },
+      StmtKind::Read { target, index } => {
+        let (symbol, _) = self.lookup(target)?;
+        self.check_index(target, symbol, index.as_ref())?;
+        match symbol {
+          Symbol::Var { .. } | Symbol::Array { .. } => Ok(()),
+          _ => Err(self.error(target.span, format!("Cannot read into '{}', which is not a variable.", target.name))),
+        }
+      },
+      StmtKind::Write(arg) => self.resolve_write_arg(arg),
+      StmtKind::Writeln(args) => args.iter().try_for_each(|arg| self.resolve_write_arg(arg)),
+      StmtKind::Skip => Ok(()),
+    }
+  }
+
+  fn resolve_write_arg(&mut self, arg: &WriteArg) -> Result<(), CompileError> {
+    match arg {
+      WriteArg::Expr(expr) => self.resolve_expr(expr),
+      WriteArg::Str(_) => Ok(()),
+    }
+  }
+
+  fn resolve_loop_body(&mut self, body: &[Stmt]) -> Result<(), CompileError> {
+    self.loop_depth += 1;
+    let result = body.iter().try_for_each(|s| self.resolve_stmt(s));
+    self.loop_depth -= 1;
+    result
+  }
+
+  fn resolve_condition(&mut self, cond: &Condition) -> Result<(), CompileError> {
+    match cond {
+      Condition::Odd(expr) => self.resolve_expr(expr),
+      Condition::Rel { left, right, .. } => {
+        self.resolve_expr(left)?;
+        self.resolve_expr(right)
+      },
+      Condition::Not(cond) => self.resolve_condition(cond),
+      Condition::And(left, right) | Condition::Or(left, right) => {
+        self.resolve_condition(left)?;
+        self.resolve_condition(right)
+      },
+    }
+  }
+
+  fn resolve_expr(&mut self, expr: &Expr) -> Result<(), CompileError> {
+    match &expr.kind {
+      ExprKind::Number(_) => Ok(()),
+      ExprKind::Name(name) => {
+        match self.lookup(name)? {
+          (Symbol::Proc { kind: ProcKind::Procedure, .. }, _) => {
+            Err(self.error(name.span, format!("Procedure '{}' does not return a value.", name.name)))
+          },
+          (symbol @ Symbol::Proc { .. }, _) => self.check_call(name, &[], symbol),
+          (symbol, _) => self.check_index(name, symbol, None),
+        }
+      },
+      ExprKind::Index { name, index } => {
+        let (symbol, _) = self.lookup(name)?;
+        self.check_index(name, symbol, Some(index))
+      },
+      ExprKind::Call { name, args } => {
+        let (symbol, _) = self.lookup(name)?;
+        if let Symbol::Proc { kind: ProcKind::Procedure, .. } = symbol {
+          return Err(self.error(name.span, format!("Procedure '{}' does not return a value.", name.name)));
+        }
+        self.check_call(name, args, symbol)?;
+        args.iter().try_for_each(|arg| self.resolve_expr(arg))
+      },
+      ExprKind::Neg(inner) => self.resolve_expr(inner),
+      ExprKind::Binary { left, right, .. } => {
+        self.resolve_expr(left)?;
+        self.resolve_expr(right)
+      },
+    }
+  }
+}
+
+/// Checks that every identifier is declared before use and used as what it
+/// was declared as, and that calls pass the right number of arguments.
+/// `imports` holds the compiled modules named by `program.imports`, in order;
+/// their exports are declared alongside the program's own globals.
+pub fn resolve(program: &Program, filename: &str, imports: &[&Object]) -> Result<(), CompileError> {
+  index(program, filename, imports).1
+}
+
+/// Resolves the program like `resolve`, and also returns its declarations and
+/// what each identifier use refers to, as far as resolving got before an error.
+pub fn index(program: &Program, filename: &str, imports: &[&Object]) -> (SymbolIndex, Result<(), CompileError>) {
+  let mut resolver = Resolver { filename: filename.to_string(), scopes: Scopes::new(), index: SymbolIndex::default(), functions: Vec::new(), loop_depth: 0 };
+  resolver.scopes.push();
+  let result = resolver.resolve_program(program, imports);
+  (resolver.index, result)
+}
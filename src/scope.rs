@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use crate::ast::ProcKind;
+use crate::machine::machine_types::{Address, Word};
+
+/// What an identifier has been declared as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Symbol {
+  Const(Word),
+  /// A variable or parameter, at `offset` from the base of its activation record.
+  Var { offset: Address },
+  /// An array of `length` words starting at `offset`; `id` is its entry in
+  /// the executable's array table, which the bounds check refers to.
+  Array { offset: Address, length: Word, id: usize },
+  /// A procedure or function; `address` is its first instruction once
+  /// generated. One declared in another module has no address yet; `import`
+  /// is then its index in the object's imports.
+  Proc { kind: ProcKind, arity: usize, address: Address, import: Option<usize> },
+}
+
+/// Nested declaration scopes, innermost last. The main block is level 0 and
+/// each procedure body is one level deeper than the block declaring it.
+pub struct Scopes<T> {
+  frames: Vec<HashMap<String, T>>,
+}
+
+impl<T> Default for Scopes<T> {
+  fn default() -> Self {
+    Scopes::new()
+  }
+}
+
+impl<T> Scopes<T> {
+  pub fn new() -> Self {
+    Scopes { frames: Vec::new() }
+  }
+
+  pub fn push(&mut self) {
+    self.frames.push(HashMap::new());
+  }
+
+  pub fn pop(&mut self) {
+    self.frames.pop();
+  }
+
+  /// The level of the innermost scope.
+  pub fn level(&self) -> usize {
+    self.frames.len() - 1
+  }
+
+  /// Declares `name` in the innermost scope. Returns false, leaving the
+  /// existing declaration in place, if the name is already declared there.
+  pub fn declare(&mut self, name: &str, value: T) -> bool {
+    let frame = self.frames.last_mut().expect("no open scope");
+    if frame.contains_key(name) {
+      return false;
+    }
+    frame.insert(name.to_string(), value);
+    true
+  }
+
+  /// Finds the innermost declaration of `name` and the level it was declared at.
+  pub fn lookup(&self, name: &str) -> Option<(&T, usize)> {
+    self.frames.iter().enumerate().rev()
+      .find_map(|(level, frame)| frame.get(name).map(|value| (value, level)))
+  }
+}
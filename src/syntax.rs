@@ -0,0 +1,330 @@
+use crate::token::{Token, TokenType, Trivia};
+
+/// What a syntax node holds. Conditions, expressions and simple statements
+/// keep their tokens flat, in source order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxKind {
+  Program,
+  /// `module m;`
+  ModuleHeader,
+  /// `import a, b;`
+  Import,
+  /// `const a = 1, b = 2;`, holding a `ConstDef` for each definition.
+  ConstDecl,
+  ConstDef,
+  /// `var a, b[3];`
+  VarDecl,
+  /// The heading, then a `Block`, then the closing `;`.
+  ProcDecl,
+  Block,
+  BeginStmt,
+  IfStmt,
+  WhileStmt,
+  /// `for` up to `do`, then the body.
+  ForStmt,
+  RepeatStmt,
+  /// Assignment, call, `read`, `write`, `writeln`, `break`, `continue` or
+  /// `skip`.
+  SimpleStmt,
+  Condition,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+  pub typ: TokenType,
+  pub text: String,
+  pub line: i32,
+  pub column: i32,
+  /// Whitespace and comments on the lines before this token, or earlier on
+  /// its line.
+  pub leading: Vec<Trivia>,
+  /// Whitespace and comments after this token on the same line, including
+  /// the line break.
+  pub trailing: Vec<Trivia>,
+}
+
+impl SyntaxToken {
+  /// The token's source text with its trivia.
+  pub fn full_text(&self) -> String {
+    let mut text: String = self.leading.iter().map(|t| t.text.as_str()).collect();
+    text += &self.text;
+    text.extend(self.trailing.iter().map(|t| t.text.as_str()));
+    text
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+  Node(SyntaxNode),
+  Token(SyntaxToken),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+  pub kind: SyntaxKind,
+  pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+  fn new(kind: SyntaxKind) -> Self {
+    SyntaxNode { kind, children: Vec::new() }
+  }
+
+  /// The node's child nodes.
+  pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+    self.children.iter().filter_map(|child| match child {
+      SyntaxElement::Node(node) => Some(node),
+      SyntaxElement::Token(_) => None,
+    })
+  }
+
+  /// Every token under the node, in source order.
+  pub fn tokens(&self) -> Vec<&SyntaxToken> {
+    let mut tokens: Vec<&SyntaxToken> = Vec::new();
+    for child in &self.children {
+      match child {
+        SyntaxElement::Node(node) => tokens.extend(node.tokens()),
+        SyntaxElement::Token(token) => tokens.push(token),
+      }
+    }
+    tokens
+  }
+
+  fn tokens_mut(&mut self) -> Vec<&mut SyntaxToken> {
+    let mut tokens: Vec<&mut SyntaxToken> = Vec::new();
+    for child in &mut self.children {
+      match child {
+        SyntaxElement::Node(node) => tokens.extend(node.tokens_mut()),
+        SyntaxElement::Token(token) => tokens.push(token),
+      }
+    }
+    tokens
+  }
+
+  /// The text the node was parsed from, trivia included. Each character
+  /// stands for one byte, as in the lexer.
+  pub fn text(&self) -> String {
+    self.tokens().into_iter().map(SyntaxToken::full_text).collect()
+  }
+
+  /// The bytes the node was parsed from. For a tree built from a whole file
+  /// lexed with `keep_trivia`, this is the file exactly.
+  pub fn to_source(&self) -> Vec<u8> {
+    self.text().chars().map(|c| c as u8).collect()
+  }
+
+  /// Replaces the text of the tokens that start at the given `(line, column)`
+  /// positions with `text`, keeping their trivia. Returns how many tokens
+  /// were replaced.
+  pub fn replace_tokens(&mut self, positions: &[(i32, i32)], text: &str) -> usize {
+    let mut replaced = 0;
+    for token in self.tokens_mut() {
+      if positions.contains(&(token.line, token.column)) {
+        token.text = text.to_string();
+        replaced += 1;
+      }
+    }
+    replaced
+  }
+}
+
+/// Builds the syntax tree of a token stream ending in `Eofsym`. The tokens
+/// should already have been checked by `parser::parse`; the tree follows
+/// their structure but does not report errors. If they were lexed with
+/// `keep_trivia`, the tree holds every byte of the source.
+pub fn parse(tokens: &[Token]) -> SyntaxNode {
+  let mut builder = Builder { tokens: attach_trivia(tokens), pos: 0 };
+  builder.program()
+}
+
+/// Splits each token's trivia between it and the token before: up to and
+/// including the first line break, trivia on the previous token's line
+/// trails that token, and the rest leads the next.
+fn attach_trivia(tokens: &[Token]) -> Vec<SyntaxToken> {
+  let mut attached: Vec<SyntaxToken> = Vec::new();
+  for t in tokens {
+    let mut trivia = t.trivia.iter();
+    if let Some(prev) = attached.last_mut() {
+      for piece in trivia.by_ref() {
+        prev.trailing.push(piece.clone());
+        if piece.text.ends_with('\n') {
+          break;
+        }
+      }
+    }
+    // The end of file has no text of its own.
+    let text = if t.typ == TokenType::Eofsym { String::new() } else { t.text.clone() };
+    attached.push(SyntaxToken { typ: t.typ, text, line: t.line, column: t.column, leading: trivia.cloned().collect(), trailing: Vec::new() });
+  }
+  attached
+}
+
+struct Builder {
+  tokens: Vec<SyntaxToken>,
+  pos: usize,
+}
+
+impl Builder {
+  fn peek(&self) -> TokenType {
+    self.tokens.get(self.pos).map_or(TokenType::Eofsym, |t| t.typ)
+  }
+
+  fn at_end(&self) -> bool {
+    self.pos >= self.tokens.len()
+  }
+
+  /// Moves the current token into `node`.
+  fn bump(&mut self, node: &mut SyntaxNode) {
+    if let Some(t) = self.tokens.get(self.pos) {
+      node.children.push(SyntaxElement::Token(t.clone()));
+      self.pos += 1;
+    }
+  }
+
+  fn bump_if(&mut self, node: &mut SyntaxNode, typ: TokenType) -> bool {
+    if self.peek() == typ {
+      self.bump(node);
+      return true;
+    }
+    false
+  }
+
+  /// Moves tokens into `node` up to (not including) one of `stops` outside
+  /// any parentheses or brackets.
+  fn bump_until(&mut self, node: &mut SyntaxNode, stops: &[TokenType]) {
+    let mut depth = 0;
+    while !self.at_end() && self.peek() != TokenType::Eofsym {
+      match self.peek() {
+        TokenType::Lparensym | TokenType::Lbracketsym => depth += 1,
+        TokenType::Rparensym | TokenType::Rbracketsym => depth -= 1,
+        typ if depth <= 0 && stops.contains(&typ) => return,
+        _ => {},
+      }
+      self.bump(node);
+    }
+  }
+
+  fn program(&mut self) -> SyntaxNode {
+    let mut program = SyntaxNode::new(SyntaxKind::Program);
+    let is_module = self.peek() == TokenType::Modulesym;
+    if is_module {
+      let mut header = SyntaxNode::new(SyntaxKind::ModuleHeader);
+      self.bump_until(&mut header, &[TokenType::Semisym]);
+      self.bump(&mut header);
+      program.children.push(SyntaxElement::Node(header));
+    }
+    while self.peek() == TokenType::Importsym {
+      let mut import = SyntaxNode::new(SyntaxKind::Import);
+      self.bump_until(&mut import, &[TokenType::Semisym]);
+      self.bump(&mut import);
+      program.children.push(SyntaxElement::Node(import));
+    }
+    let block = self.block(!is_module);
+    program.children.push(SyntaxElement::Node(block));
+    // The final '.', the end of file and anything left over.
+    while !self.at_end() {
+      self.bump(&mut program);
+    }
+    program
+  }
+
+  fn block(&mut self, has_body: bool) -> SyntaxNode {
+    let mut block = SyntaxNode::new(SyntaxKind::Block);
+    while self.peek() == TokenType::Constsym {
+      let mut decl = SyntaxNode::new(SyntaxKind::ConstDecl);
+      self.bump(&mut decl);
+      loop {
+        let mut def = SyntaxNode::new(SyntaxKind::ConstDef);
+        self.bump_until(&mut def, &[TokenType::Commasym, TokenType::Semisym]);
+        decl.children.push(SyntaxElement::Node(def));
+        if !self.bump_if(&mut decl, TokenType::Commasym) {
+          break;
+        }
+      }
+      self.bump_if(&mut decl, TokenType::Semisym);
+      block.children.push(SyntaxElement::Node(decl));
+    }
+    while self.peek() == TokenType::Varsym {
+      let mut decl = SyntaxNode::new(SyntaxKind::VarDecl);
+      self.bump_until(&mut decl, &[TokenType::Semisym]);
+      self.bump(&mut decl);
+      block.children.push(SyntaxElement::Node(decl));
+    }
+    while matches!(self.peek(), TokenType::Procsym | TokenType::Funcsym) {
+      let mut decl = SyntaxNode::new(SyntaxKind::ProcDecl);
+      self.bump_until(&mut decl, &[TokenType::Semisym]);
+      self.bump(&mut decl);
+      decl.children.push(SyntaxElement::Node(self.block(true)));
+      self.bump_if(&mut decl, TokenType::Semisym);
+      block.children.push(SyntaxElement::Node(decl));
+    }
+    if has_body {
+      block.children.push(SyntaxElement::Node(self.stmt()));
+    }
+    block
+  }
+
+  /// Statements separated by `;`, up to `end` or `until`.
+  fn stmt_list(&mut self, node: &mut SyntaxNode) {
+    loop {
+      node.children.push(SyntaxElement::Node(self.stmt()));
+      if !self.bump_if(node, TokenType::Semisym) {
+        return;
+      }
+    }
+  }
+
+  fn stmt(&mut self) -> SyntaxNode {
+    const STMT_END: &[TokenType] = &[TokenType::Semisym, TokenType::Endsym, TokenType::Elsesym, TokenType::Untilsym, TokenType::Periodsym];
+    let kind = match self.peek() {
+      TokenType::Beginsym => SyntaxKind::BeginStmt,
+      TokenType::Ifsym => SyntaxKind::IfStmt,
+      TokenType::Whilesym => SyntaxKind::WhileStmt,
+      TokenType::Forsym => SyntaxKind::ForStmt,
+      TokenType::Repeatsym => SyntaxKind::RepeatStmt,
+      _ => SyntaxKind::SimpleStmt,
+    };
+    let mut stmt = SyntaxNode::new(kind);
+    match kind {
+      SyntaxKind::BeginStmt => {
+        self.bump(&mut stmt);
+        self.stmt_list(&mut stmt);
+        self.bump_if(&mut stmt, TokenType::Endsym);
+      },
+      SyntaxKind::IfStmt => {
+        self.bump(&mut stmt);
+        stmt.children.push(SyntaxElement::Node(self.condition(&[TokenType::Thensym])));
+        self.bump(&mut stmt);
+        stmt.children.push(SyntaxElement::Node(self.stmt()));
+        if self.bump_if(&mut stmt, TokenType::Elsesym) {
+          stmt.children.push(SyntaxElement::Node(self.stmt()));
+        }
+      },
+      SyntaxKind::WhileStmt => {
+        self.bump(&mut stmt);
+        stmt.children.push(SyntaxElement::Node(self.condition(&[TokenType::Dosym])));
+        self.bump(&mut stmt);
+        stmt.children.push(SyntaxElement::Node(self.stmt()));
+      },
+      SyntaxKind::ForStmt => {
+        self.bump_until(&mut stmt, &[TokenType::Dosym]);
+        self.bump(&mut stmt);
+        stmt.children.push(SyntaxElement::Node(self.stmt()));
+      },
+      SyntaxKind::RepeatStmt => {
+        self.bump(&mut stmt);
+        self.stmt_list(&mut stmt);
+        self.bump_if(&mut stmt, TokenType::Untilsym);
+        stmt.children.push(SyntaxElement::Node(self.condition(STMT_END)));
+      },
+      _ => self.bump_until(&mut stmt, STMT_END),
+    }
+    stmt
+  }
+
+  fn condition(&mut self, stops: &[TokenType]) -> SyntaxNode {
+    let mut cond = SyntaxNode::new(SyntaxKind::Condition);
+    self.bump_until(&mut cond, stops);
+    cond
+  }
+}
@@ -1,28 +1,40 @@
-const NUM_TOKENS: usize = 34;
-
-const TYPE_TO_STR: [&str; NUM_TOKENS] = [
-  "periodsym", "constsym", "semisym", "commasym",
-  "varsym", "procsym", "becomessym", "callsym", "beginsym", "endsym",
-  "ifsym", "thensym", "elsesym", "whilesym", "dosym",
-  "readsym", "writesym", "skipsym",
-  "oddsym", "lparensym", "rparensym",
-  "identsym", "numbersym",
-  "eqsym", "neqsym", "lessym", "leqsym", "gtrsym", "geqsym",
-  "plussym", "minussym", "multsym", "divsym",
-  "eofsym"
-];
-
-#[derive(Debug, PartialEq)] 
-pub enum TokenType {
-  Periodsym, Constsym, Semisym, Commasym,
-  Varsym, Procsym, Becomessym, Callsym, Beginsym, Endsym,
-  Ifsym, Thensym, Elsesym, Whilesym, Dosym, 
-  Readsym, Writesym, Skipsym,
-  Oddsym, Lparensym, Rparensym,
-  Identsym, Numbersym, 
-  Eqsym, Neqsym, Lessym, Leqsym, Gtrsym, Geqsym, 
-  Plussym, Minussym, Multsym, Divsym, 
-  Eofsym
+// Declares `TokenType` together with the name each variant is displayed
+// with, so the two cannot drift apart.
+macro_rules! token_types {
+  ($($variant:ident => $name:literal),* $(,)?) => {
+    #[derive(Debug, PartialEq, Eq, Hash)]
+    pub enum TokenType {
+      $($variant),*
+    }
+
+    impl TokenType {
+      pub const ALL: &'static [TokenType] = &[$(TokenType::$variant),*];
+
+      /// The name used for this token type in logs and test output.
+      pub fn name(self) -> &'static str {
+        match self {
+          $(TokenType::$variant => $name),*
+        }
+      }
+    }
+  };
+}
+
+token_types! {
+  Periodsym => "periodsym", Constsym => "constsym", Semisym => "semisym", Commasym => "commasym",
+  Varsym => "varsym", Procsym => "procsym", Funcsym => "funcsym", Becomessym => "becomessym",
+  Callsym => "callsym", Beginsym => "beginsym", Endsym => "endsym",
+  Ifsym => "ifsym", Thensym => "thensym", Elsesym => "elsesym", Whilesym => "whilesym", Dosym => "dosym",
+  Readsym => "readsym", Writesym => "writesym", Writelnsym => "writelnsym", Skipsym => "skipsym",
+  Repeatsym => "repeatsym", Untilsym => "untilsym", Forsym => "forsym", Tosym => "tosym",
+  Downtosym => "downtosym", Breaksym => "breaksym", Continuesym => "continuesym",
+  Modulesym => "modulesym", Importsym => "importsym",
+  Oddsym => "oddsym", Andsym => "andsym", Orsym => "orsym", Notsym => "notsym",
+  Lparensym => "lparensym", Rparensym => "rparensym", Lbracketsym => "lbracketsym", Rbracketsym => "rbracketsym",
+  Identsym => "identsym", Numbersym => "numbersym", Stringsym => "stringsym",
+  Eqsym => "eqsym", Neqsym => "neqsym", Lessym => "lessym", Leqsym => "leqsym", Gtrsym => "gtrsym", Geqsym => "geqsym",
+  Plussym => "plussym", Minussym => "minussym", Multsym => "multsym", Divsym => "divsym", Modsym => "modsym",
+  Eofsym => "eofsym",
 }
 
 impl Copy for TokenType { }
@@ -31,13 +43,46 @@ impl Clone for TokenType {
   fn clone(&self) -> Self { *self }
 }
 
+/// Text between tokens that the parser ignores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+  /// Spaces and tabs, ending at and including a newline if there is one.
+  Whitespace,
+  Comment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+  pub kind: TriviaKind,
+  pub text: String,
+  pub line: i32,
+  pub column: i32,
+}
+
+impl Trivia {
+  /// The line the trivia ends on; block comments may span lines.
+  pub fn end_line(&self) -> i32 {
+    self.line + self.text.matches('\n').count() as i32
+  }
+
+  /// Whether this is a comment that runs to the end of its line, so nothing
+  /// may follow it there.
+  pub fn is_line_comment(&self) -> bool {
+    self.kind == TriviaKind::Comment && (self.text.starts_with('#') || self.text.starts_with("//"))
+  }
+}
+
+#[derive(Debug)]
 pub struct Token {
   pub typ: TokenType,
   pub filename: String,
   pub line: i32,
   pub column: i32,
   pub text: String,
-  pub value: i32
+  pub value: i32,
+  /// Everything between the previous token and this one, when the lexer
+  /// keeps trivia; otherwise empty.
+  pub trivia: Vec<Trivia>,
 }
 
 impl Clone for Token {
@@ -48,11 +93,18 @@ impl Clone for Token {
       line: self.line,
       column: self.column,
       text: self.text.clone(),
-      value: self.value
+      value: self.value,
+      trivia: self.trivia.clone(),
     }
   }
 }
 
+impl Default for Token {
+  fn default() -> Self {
+    Token::new()
+  }
+}
+
 impl Token {
   pub fn new() -> Self {
     Token {
@@ -61,7 +113,8 @@ impl Token {
       line: 0,
       column: 0,
       text: String::new(),
-      value: 0
+      value: 0,
+      trivia: Vec::new(),
     }
   }
 
@@ -72,15 +125,26 @@ impl Token {
       line: 0,
       column: 0,
       text: String::new(),
-      value: 0
+      value: 0,
+      trivia: Vec::new(),
     }
   }
   
   pub fn ttyp2str(&self) -> String {
-    TYPE_TO_STR[self.typ as usize].to_string()
+    self.typ.name().to_string()
   }
 }
 
 pub fn type_to_string(ttyp: TokenType) -> String {
-  TYPE_TO_STR[ttyp as usize].to_string()
-}
\ No newline at end of file
+  ttyp.name().to_string()
+}
+
+/// Renders a token stream back into source text, separating tokens with a
+/// single space. Lexing the result gives back the same tokens.
+pub fn tokens_to_source(tokens: &[Token]) -> String {
+  tokens.iter()
+    .filter(|t| t.typ != TokenType::Eofsym)
+    .map(|t| t.text.as_str())
+    .collect::<Vec<&str>>()
+    .join(" ")
+}
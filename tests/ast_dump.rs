@@ -0,0 +1,37 @@
+mod common;
+
+use rustpl0::ast_dump::{dump, AstFormat};
+use rustpl0::dialect::Dialect;
+use rustpl0::lexer::{lex_with_options, LexerOptions};
+use rustpl0::json::Json;
+use rustpl0::parser::parse;
+
+fn parse_extended(filename: &str, source: &str) -> rustpl0::ast::Program {
+  let options = LexerOptions { dialect: Dialect::Extended, ..LexerOptions::default() };
+  parse(&lex_with_options(filename, source.as_bytes(), options).unwrap(), Dialect::Extended).unwrap()
+}
+
+// Each `tests/ast/<name>.pl0` is parsed in the extended dialect and dumped
+// in every format, compared with `<name>.ast`, `<name>.json` and
+// `<name>.sexp`.
+#[test]
+fn fixtures_dump_as_expected() {
+  for (format, extension) in [(AstFormat::Text, "ast"), (AstFormat::Json, "json"), (AstFormat::Sexp, "sexp")] {
+    common::check_fixture_dir("tests/ast", "pl0", extension, |path, source| {
+      dump(&parse_extended(&path.display().to_string(), source), format)
+    });
+  }
+}
+
+#[test]
+fn dumps_show_how_operators_group() {
+  let program = parse_extended("group.pl0", "var x; x := 1 - 2 * -x + 3.");
+  assert_eq!(dump(&program, AstFormat::Sexp), "(program (block (var x) (assign x (binary + (binary - 1 (binary * 2 (neg x))) 3))))\n");
+
+  let json = Json::parse(&dump(&program, AstFormat::Json)).unwrap();
+  let value = json.get("block").get("body").get("value");
+  assert_eq!((value.get("kind").as_str(), value.get("op").as_str()), (Some("Binary"), Some("+")));
+  assert_eq!(value.get("right").get("value").as_i64(), Some(3));
+  assert_eq!(value.get("span").get("column").as_i64(), Some(13));
+  assert_eq!(json.get("block").get("procs").as_array(), []);
+}
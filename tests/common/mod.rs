@@ -0,0 +1,127 @@
+// Shared by several test crates; each uses only some of these helpers.
+//
+// Golden files hold the expected output of fixtures. After an intended
+// change, regenerate them all with `BLESS=1 cargo test` and review the diff.
+#![allow(dead_code)]
+
+use std::{env, fs, path::{Path, PathBuf}};
+
+/// Returns every file in `dir` with the given extension, sorted by name so
+/// failures are reported in a stable order.
+pub fn fixtures(dir: &str, extension: &str) -> Vec<PathBuf> {
+  let root = Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+  let mut files: Vec<PathBuf> = fs::read_dir(&root)
+    .unwrap_or_else(|err| panic!("Could not read fixture directory `{}`: {}", root.display(), err))
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+    .collect();
+  files.sort();
+  files
+}
+
+/// Reads the file next to `path` with its extension swapped, if it exists.
+pub fn sibling(path: &Path, extension: &str) -> Option<String> {
+  fs::read_to_string(path.with_extension(extension)).ok()
+}
+
+/// Splits machine output into steps. Step 0 is everything printed before the
+/// first traced instruction; every later step starts at a `--> addr:` line.
+pub fn split_steps(output: &str) -> Vec<Vec<&str>> {
+  let mut steps: Vec<Vec<&str>> = vec![Vec::new()];
+  for line in output.lines() {
+    if line.starts_with("--> addr:") {
+      steps.push(Vec::new());
+    }
+    steps.last_mut().unwrap().push(line);
+  }
+  steps
+}
+
+/// Compares two outputs step by step and describes the first step that
+/// differs, with the differing lines marked `-` (expected) and `+` (actual).
+pub fn first_divergence(expected: &str, actual: &str) -> Option<String> {
+  let expected_steps = split_steps(expected);
+  let actual_steps = split_steps(actual);
+  let step_count = expected_steps.len().max(actual_steps.len());
+
+  for step in 0..step_count {
+    let exp: &[&str] = expected_steps.get(step).map_or(&[], |s| s.as_slice());
+    let act: &[&str] = actual_steps.get(step).map_or(&[], |s| s.as_slice());
+    if exp == act {
+      continue;
+    }
+
+    let mut report = format!("first divergence at step {}:\n", step);
+    for i in 0..exp.len().max(act.len()) {
+      match (exp.get(i), act.get(i)) {
+        (Some(e), Some(a)) if e == a => report += &format!("  {}\n", e),
+        (e, a) => {
+          if let Some(e) = e {
+            report += &format!("- {}\n", e);
+          }
+          if let Some(a) = a {
+            report += &format!("+ {}\n", a);
+          }
+        },
+      }
+    }
+    return Some(report);
+  }
+
+  None
+}
+
+/// Compares two outputs line by line and describes the first line that differs.
+pub fn first_line_divergence(expected: &str, actual: &str) -> Option<String> {
+  let expected_lines: Vec<&str> = expected.lines().collect();
+  let actual_lines: Vec<&str> = actual.lines().collect();
+
+  for i in 0..expected_lines.len().max(actual_lines.len()) {
+    let (e, a) = (expected_lines.get(i), actual_lines.get(i));
+    if e == a {
+      continue;
+    }
+
+    let mut report = format!("first divergence at line {}:\n", i + 1);
+    if let Some(e) = e {
+      report += &format!("- {}\n", e);
+    }
+    if let Some(a) = a {
+      report += &format!("+ {}\n", a);
+    }
+    return Some(report);
+  }
+
+  None
+}
+
+/// Checks `actual` against the golden file at `expected_path` using `compare`.
+/// When the `BLESS` environment variable is set the golden file is rewritten
+/// with `actual` instead.
+pub fn check_golden(expected_path: &Path, actual: &str, compare: fn(&str, &str) -> Option<String>) -> Option<String> {
+  if env::var_os("BLESS").is_some() {
+    fs::write(expected_path, actual)
+      .unwrap_or_else(|err| panic!("Could not bless `{}`: {}", expected_path.display(), err));
+    return None;
+  }
+
+  match fs::read_to_string(expected_path) {
+    Ok(expected) => compare(&expected, actual).map(|report| format!("{}: {}", expected_path.display(), report)),
+    Err(_) => Some(format!("{}: missing expected file (run with BLESS=1 to create it)", expected_path.display())),
+  }
+}
+
+/// Renders each `<name>.<extension>` fixture in `dir` from its path and
+/// contents, and compares the result line by line with the golden file
+/// `<name>.<golden_extension>`. Fails listing every fixture that differs.
+pub fn check_fixture_dir(dir: &str, extension: &str, golden_extension: &str, mut render: impl FnMut(&Path, &str) -> String) {
+  let mut failures: Vec<String> = Vec::new();
+  for path in fixtures(dir, extension) {
+    let source = fs::read_to_string(&path).unwrap();
+    let actual = render(&path, &source);
+    if let Some(report) = check_golden(&path.with_extension(golden_extension), &actual, first_line_divergence) {
+      failures.push(report);
+    }
+  }
+  assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use std::path::PathBuf;
+use rustpl0::compiler::{compile_program, CompileOptions};
+use rustpl0::dialect::Dialect;
+use rustpl0::lexer::{lex_with_options, LexerOptions};
+use rustpl0::machine::machine::run_code;
+use rustpl0::opt::OptLevel;
+
+// Each `tests/programs/<dialect>/<name>.pl0` program is compiled and run, and
+// everything it prints (or the compile or runtime error) is compared with
+// `<name>.expected`. An optional `<name>.in` supplies the program's input.
+fn check_programs(dir: &str, options: CompileOptions) {
+  common::check_fixture_dir(dir, "pl0", "expected", |source_path, source| {
+    let input = common::sibling(source_path, "in").unwrap_or_default();
+    let file_name = source_path.file_name().unwrap().to_string_lossy();
+
+    let compiled = lex_with_options(&file_name, source.as_bytes(), options.lexer.clone())
+      .map_err(|err| err.into())
+      .and_then(|tokens| compile_program(&tokens, &options));
+    match compiled {
+      Ok(code) => {
+        let mut output: Vec<u8> = Vec::new();
+        let result = run_code(code, false, false, Some(100_000), &mut input.as_bytes(), &mut output);
+        let mut actual = String::from_utf8(output).unwrap();
+        if let Err(msg) = result {
+          actual += &format!("\n{}\n", msg);
+        }
+        actual
+      },
+      // Errors in an imported module are shown with that module's source.
+      Err(err) if err.filename != file_name => err.render(&std::fs::read_to_string(&err.filename).unwrap_or_default()),
+      Err(err) => err.render(source),
+    }
+  });
+}
+
+fn dialect_options(dialect: Dialect) -> CompileOptions {
+  CompileOptions { lexer: LexerOptions { dialect, ..LexerOptions::default() }, ..CompileOptions::default() }
+}
+
+#[test]
+fn standard_programs_match_expected() {
+  check_programs("tests/programs/standard", dialect_options(Dialect::Standard));
+}
+
+#[test]
+fn extended_programs_match_expected() {
+  check_programs("tests/programs/extended", dialect_options(Dialect::Extended));
+}
+
+// Programs in `tests/programs/modules/` import modules kept in its `lib/`
+// directory, found through the module path.
+#[test]
+fn module_programs_match_expected() {
+  let options = CompileOptions { module_path: vec![PathBuf::from("tests/programs/modules/lib")], ..dialect_options(Dialect::Extended) };
+  check_programs("tests/programs/modules", options);
+}
+
+// Optimizing must never change what a program prints, nor the error that
+// stops it.
+#[test]
+fn optimized_programs_match_expected() {
+  for opt_level in [OptLevel::O1, OptLevel::O2] {
+    check_programs("tests/programs/standard", CompileOptions { opt_level, ..dialect_options(Dialect::Standard) });
+    check_programs("tests/programs/extended", CompileOptions { opt_level, ..dialect_options(Dialect::Extended) });
+    let modules = vec![PathBuf::from("tests/programs/modules/lib")];
+    check_programs("tests/programs/modules", CompileOptions { opt_level, module_path: modules, ..dialect_options(Dialect::Extended) });
+  }
+}
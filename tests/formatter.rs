@@ -0,0 +1,90 @@
+mod common;
+
+use rustpl0::compile_error::CompileError;
+use rustpl0::compiler::{compile_program, compile_source, CompileOptions};
+use rustpl0::dialect::Dialect;
+use rustpl0::formatter::{format_source, FormatOptions, KeywordCase};
+use rustpl0::lexer::{lex_with_options, LexerOptions};
+use rustpl0::machine::machine_types::Executable;
+
+fn extended() -> LexerOptions {
+  LexerOptions { dialect: Dialect::Extended, ..LexerOptions::default() }
+}
+
+fn format(source: &str, options: &FormatOptions) -> String {
+  format_source("format.pl0", source.as_bytes(), &extended(), options).unwrap()
+}
+
+fn compile(source: &str, options: &CompileOptions) -> Result<Executable, CompileError> {
+  let tokens = lex_with_options("program.pl0", source.as_bytes(), options.lexer.clone())?;
+  compile_program(&tokens, options)
+}
+
+// Each `tests/format/<name>.pl0` is formatted with the default options and
+// compared with `<name>.expected`, which must itself already be formatted.
+#[test]
+fn sources_format_as_expected() {
+  common::check_fixture_dir("tests/format", "pl0", "expected", |path, source| {
+    let actual = format(source, &FormatOptions::default());
+    assert_eq!(format(&actual, &FormatOptions::default()), actual, "{}: formatting the output again changes it", path.display());
+    actual
+  });
+}
+
+#[test]
+fn formatting_keeps_what_programs_compile_to() {
+  for (dir, dialect) in [("tests/programs/standard", Dialect::Standard), ("tests/programs/extended", Dialect::Extended)] {
+    let lexer = LexerOptions { dialect, ..LexerOptions::default() };
+    for path in common::fixtures(dir, "pl0") {
+      let source = std::fs::read_to_string(&path).unwrap();
+      let Ok(code) = compile_source("program.pl0", &source, dialect) else {
+        continue;
+      };
+      let formatted = format_source("program.pl0", source.as_bytes(), &lexer, &FormatOptions::default()).unwrap();
+      let again = format_source("program.pl0", formatted.as_bytes(), &lexer, &FormatOptions::default()).unwrap();
+      assert_eq!(again, formatted, "{}", path.display());
+      assert_eq!(compile_source("program.pl0", &formatted, dialect).unwrap().code, code.code, "{}", path.display());
+    }
+  }
+}
+
+#[test]
+fn indent_width_and_keyword_case_are_configurable() {
+  let options = FormatOptions { indent_width: 4, keyword_case: KeywordCase::Upper };
+  let lexer = LexerOptions { ignore_keyword_case: true, ..extended() };
+  let formatted = format_source("format.pl0", b"procedure p; begin x := 1 end; call p.", &lexer, &options).unwrap();
+  assert_eq!(formatted, "PROCEDURE p;\n    BEGIN\n        x := 1\n    END;\nCALL p.\n");
+  assert_eq!(format_source("format.pl0", formatted.as_bytes(), &lexer, &options).unwrap(), formatted);
+  let lowered = format_source("format.pl0", formatted.as_bytes(), &lexer, &FormatOptions::default()).unwrap();
+  assert_eq!(lowered, "procedure p;\n  begin\n    x := 1\n  end;\ncall p.\n");
+}
+
+#[test]
+fn upper_case_keywords_need_keyword_case_ignored() {
+  let options = FormatOptions { keyword_case: KeywordCase::Upper, ..FormatOptions::default() };
+  let err = format_source("format.pl0", b"begin end.", &extended(), &options).unwrap_err();
+  assert!(err.message.contains("keyword case is ignored"), "{}", err.message);
+}
+
+#[test]
+fn upper_case_output_compiles_with_the_same_options() {
+  for (dir, dialect) in [("tests/programs/standard", Dialect::Standard), ("tests/programs/extended", Dialect::Extended)] {
+    let options = CompileOptions { lexer: LexerOptions { dialect, ignore_keyword_case: true, ..LexerOptions::default() }, ..CompileOptions::default() };
+    let format_options = FormatOptions { keyword_case: KeywordCase::Upper, ..FormatOptions::default() };
+    for path in common::fixtures(dir, "pl0") {
+      let source = std::fs::read_to_string(&path).unwrap();
+      let Ok(code) = compile(&source, &options) else {
+        continue;
+      };
+      let formatted = format_source("program.pl0", source.as_bytes(), &options.lexer, &format_options).unwrap();
+      assert_eq!(compile(&formatted, &options).unwrap().code, code.code, "{}", path.display());
+    }
+  }
+}
+
+#[test]
+fn refuses_sources_that_do_not_parse() {
+  let err = format_source("bad.pl0", b"begin x := end.", &extended(), &FormatOptions::default()).unwrap_err();
+  assert_eq!(err.line, 1);
+  assert!(err.message.starts_with("Expected"), "{}", err.message);
+}
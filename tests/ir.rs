@@ -0,0 +1,55 @@
+mod common;
+
+use rustpl0::compiler::{compile_ir, compile_program, CompileOptions};
+use rustpl0::dialect::Dialect;
+use rustpl0::lexer::{lex_with_options, LexerOptions};
+use rustpl0::machine::instruction::INC;
+use rustpl0::machine::machine::run_code;
+use rustpl0::token::Token;
+
+fn options() -> CompileOptions {
+  CompileOptions { lexer: LexerOptions { dialect: Dialect::Extended, ..LexerOptions::default() }, ..CompileOptions::default() }
+}
+
+fn lex(filename: &str, source: &str) -> Vec<Token> {
+  lex_with_options(filename, source.as_bytes(), options().lexer).unwrap()
+}
+
+fn run(source: &str) -> String {
+  let code = compile_program(&lex("run.pl0", source), &options()).unwrap();
+  let mut output: Vec<u8> = Vec::new();
+  run_code(code, false, false, Some(100_000), &mut "".as_bytes(), &mut output).unwrap();
+  String::from_utf8(output).unwrap()
+}
+
+// Each `tests/ir/<name>.pl0` is lowered in the extended dialect and its IR
+// compared with `<name>.ir`.
+#[test]
+fn fixtures_lower_as_expected() {
+  common::check_fixture_dir("tests/ir", "pl0", "ir", |path, source| {
+    compile_ir(&lex(&path.display().to_string(), source), &options()).unwrap().to_string()
+  });
+}
+
+#[test]
+fn temporaries_used_once_stay_on_the_stack() {
+  let code = compile_program(&lex("expr.pl0", "var a, b, c; begin c := a + b * -c; write c end."), &options()).unwrap();
+  assert_eq!((code.code[0].op, code.code[0].m), (INC, 6));
+}
+
+#[test]
+fn operands_are_evaluated_left_to_right() {
+  let source = "var x;
+function bump;
+  begin
+    x := x + 10;
+    bump := 1
+  end;
+begin
+  x := 1;
+  write x + bump();
+  write bump() + x;
+  write x
+end.";
+  assert!(run(source).ends_with("\nOUTPUT: 2\nOUTPUT: 22\nOUTPUT: 21\n"));
+}
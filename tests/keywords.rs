@@ -0,0 +1,46 @@
+use std::collections::HashSet;
+use rustpl0::dialect::Dialect;
+use rustpl0::lexer::{lex_with_options, LexerOptions};
+use rustpl0::reserved_types::KEYWORDS;
+use rustpl0::token::TokenType;
+
+fn first_token(word: &str, dialect: Dialect, ignore_keyword_case: bool) -> TokenType {
+  let options = LexerOptions { dialect, ignore_keyword_case, ..LexerOptions::default() };
+  lex_with_options("keywords.pl0", word.as_bytes(), options).unwrap()[0].typ
+}
+
+#[test]
+fn every_keyword_lexes_to_its_token_in_its_dialect() {
+  for &(text, typ, dialect) in KEYWORDS {
+    assert_eq!(first_token(text, dialect, false), typ, "{}", text);
+    assert_eq!(first_token(text, Dialect::Extended, false), typ, "{}", text);
+    if dialect == Dialect::Extended {
+      assert_eq!(first_token(text, Dialect::Standard, false), TokenType::Identsym, "{}", text);
+    }
+  }
+}
+
+#[test]
+fn keywords_and_token_names_are_unique() {
+  let words: HashSet<&str> = KEYWORDS.iter().map(|&(text, _, _)| text).collect();
+  assert_eq!(words.len(), KEYWORDS.len());
+  let names: HashSet<&str> = TokenType::ALL.iter().map(|t| t.name()).collect();
+  assert_eq!(names.len(), TokenType::ALL.len());
+}
+
+#[test]
+fn keyword_case_is_ignored_only_when_asked() {
+  assert_eq!(first_token("BEGIN", Dialect::Standard, false), TokenType::Identsym);
+  assert_eq!(first_token("BEGIN", Dialect::Standard, true), TokenType::Beginsym);
+  assert_eq!(first_token("End", Dialect::Standard, true), TokenType::Endsym);
+  assert_eq!(first_token("Function", Dialect::Standard, true), TokenType::Identsym);
+  assert_eq!(first_token("Function", Dialect::Extended, true), TokenType::Funcsym);
+}
+
+#[test]
+fn identifiers_keep_their_case() {
+  let options = LexerOptions { ignore_keyword_case: true, ..LexerOptions::default() };
+  let tokens = lex_with_options("keywords.pl0", b"Total total", options).unwrap();
+  assert_eq!(tokens[0].text, "Total");
+  assert_eq!(tokens[1].text, "total");
+}
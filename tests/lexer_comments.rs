@@ -0,0 +1,58 @@
+use rustpl0::lexer::{lex_with_options, CommentSyntax, LexerOptions};
+use rustpl0::token::{TokenType, TriviaKind};
+
+fn token_types(source: &str, comments: CommentSyntax) -> Result<Vec<TokenType>, String> {
+  let options = LexerOptions { comments, ..LexerOptions::default() };
+  match lex_with_options("comments.pl0", source.as_bytes(), options) {
+    Ok(tokens) => Ok(tokens.iter().map(|t| t.typ).collect()),
+    Err(err) => Err(err.message),
+  }
+}
+
+#[test]
+fn disabled_line_comments_are_lexed_as_tokens() {
+  let comments = CommentSyntax { double_slash: false, ..CommentSyntax::default() };
+  assert_eq!(token_types("x // y", comments),
+    Ok(vec![TokenType::Identsym, TokenType::Divsym, TokenType::Divsym, TokenType::Identsym, TokenType::Eofsym]));
+  assert_eq!(token_types("x // y", CommentSyntax::default()), Ok(vec![TokenType::Identsym, TokenType::Eofsym]));
+}
+
+#[test]
+fn disabled_block_comments_are_lexed_as_tokens() {
+  let comments = CommentSyntax { paren_star: false, braces: false, ..CommentSyntax::default() };
+  assert_eq!(token_types("(*x*)", comments),
+    Ok(vec![TokenType::Lparensym, TokenType::Multsym, TokenType::Identsym, TokenType::Multsym, TokenType::Rparensym, TokenType::Eofsym]));
+  assert!(token_types("{ x }", comments).unwrap_err().contains("Illegal character"));
+}
+
+#[test]
+fn block_comment_styles_nest_inside_each_other() {
+  let source = "{ a (* b } c *) d } x";
+  assert_eq!(token_types(source, CommentSyntax::default()), Ok(vec![TokenType::Identsym, TokenType::Eofsym]));
+}
+
+#[test]
+fn kept_trivia_holds_the_whitespace_and_comments_before_each_token() {
+  let options = LexerOptions { keep_trivia: true, ..LexerOptions::default() };
+  let tokens = lex_with_options("comments.pl0", b"x := # one\n{ two\n } -1 // three", options).unwrap();
+  let trivia = |i: usize| -> Vec<(TriviaKind, &str, i32, i32)> {
+    tokens[i].trivia.iter().map(|t| (t.kind, t.text.as_str(), t.line, t.column)).collect()
+  };
+  assert_eq!(trivia(0), []);
+  assert_eq!(trivia(1), [(TriviaKind::Whitespace, " ", 1, 2)]);
+  // Whitespace is split after each line break.
+  assert_eq!(trivia(2), [
+    (TriviaKind::Whitespace, " ", 1, 5),
+    (TriviaKind::Comment, "# one", 1, 6),
+    (TriviaKind::Whitespace, "\n", 1, 11),
+    (TriviaKind::Comment, "{ two\n }", 2, 1),
+    (TriviaKind::Whitespace, " ", 3, 3),
+  ]);
+  // Comments before a sign do not stop it from belonging to the number.
+  assert_eq!((tokens[2].typ, tokens[2].text.as_str()), (TokenType::Numbersym, "-1"));
+  assert_eq!(tokens[3].typ, TokenType::Eofsym);
+  assert_eq!(trivia(3), [(TriviaKind::Whitespace, " ", 3, 6), (TriviaKind::Comment, "// three", 3, 7)]);
+
+  let tokens = lex_with_options("comments.pl0", b"x := # one\n-1", LexerOptions::default()).unwrap();
+  assert!(tokens.iter().all(|t| t.trivia.is_empty()));
+}
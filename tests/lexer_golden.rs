@@ -0,0 +1,30 @@
+mod common;
+
+use rustpl0::dialect::Dialect;
+use rustpl0::lexer::{lex_with_options, LexerOptions};
+
+// Each `<dir>/<name>.pl0` fixture is lexed and compared with
+// `<name>.expected`: one line per token (type, line:column, text, value), or
+// the reported error when lexing fails.
+fn check_fixtures(dir: &str, dialect: Dialect) {
+  let options = LexerOptions { dialect, ..LexerOptions::default() };
+  common::check_fixture_dir(dir, "pl0", "expected", |source_path, source| {
+    let file_name = source_path.file_name().unwrap().to_string_lossy();
+    match lex_with_options(&file_name, source.as_bytes(), options.clone()) {
+      Ok(tokens) => tokens.iter()
+        .map(|t| format!("{} {}:{} {:?} {}\n", t.ttyp2str(), t.line, t.column, t.text, t.value))
+        .collect::<String>(),
+      Err(err) => err.to_string(),
+    }
+  });
+}
+
+#[test]
+fn lexer_fixtures_match_expected() {
+  check_fixtures("tests/lexer", Dialect::Standard);
+}
+
+#[test]
+fn extended_lexer_fixtures_match_expected() {
+  check_fixtures("tests/lexer/extended", Dialect::Extended);
+}
@@ -0,0 +1,101 @@
+use rustpl0::lexer::{lex_source, lex_with_options, LexerOptions};
+use rustpl0::token::TokenType;
+
+fn lex_word(word: &str, options: LexerOptions) -> Result<String, String> {
+  let source = format!("{};", word);
+  match lex_with_options("limits.pl0", source.as_bytes(), options) {
+    Ok(tokens) => Ok(tokens[0].text.clone()),
+    Err(err) => Err(err.message),
+  }
+}
+
+#[test]
+fn identifier_at_default_limit_is_accepted() {
+  let ident = "x".repeat(255);
+  let tokens = lex_source("limits.pl0", &format!("{};", ident)).unwrap();
+  assert_eq!(tokens[0].typ, TokenType::Identsym);
+  assert_eq!(tokens[0].text, ident);
+}
+
+#[test]
+fn identifier_over_default_limit_is_rejected() {
+  let err = lex_source("limits.pl0", &format!("{};", "x".repeat(256))).unwrap_err();
+  assert!(err.message.contains("too long"), "{}", err.message);
+  assert_eq!(err.column, 256);
+}
+
+#[test]
+fn identifier_far_over_default_limit_does_not_panic() {
+  assert!(lex_source("limits.pl0", &"x".repeat(1000)).is_err());
+}
+
+#[test]
+fn identifier_limit_is_configurable() {
+  let options = LexerOptions { max_identifier_length: 4, ..LexerOptions::default() };
+  assert_eq!(lex_word("abcd", options.clone()), Ok("abcd".to_string()));
+  assert!(lex_word("abcde", options).is_err());
+}
+
+#[test]
+fn numbers_at_the_word_limits_are_accepted() {
+  let tokens = lex_source("limits.pl0", "2147483647, -2147483648;").unwrap();
+  assert_eq!(tokens[0].value, i32::MAX);
+  assert_eq!(tokens[2].typ, TokenType::Numbersym);
+  assert_eq!(tokens[2].text, "-2147483648");
+  assert_eq!(tokens[2].value, i32::MIN);
+}
+
+#[test]
+fn numbers_past_the_word_limits_are_rejected_with_their_span() {
+  let err = lex_source("limits.pl0", "x := 2147483648;").unwrap_err();
+  assert!(err.message.contains("out of range"), "{}", err.message);
+  assert_eq!((err.column, err.length), (6, 10));
+
+  let err = lex_source("limits.pl0", "x := -2147483649;").unwrap_err();
+  assert_eq!((err.column, err.length), (6, 11));
+}
+
+#[test]
+fn very_long_numbers_are_rejected_without_panicking() {
+  let err = lex_source("limits.pl0", &"9".repeat(100)).unwrap_err();
+  assert_eq!(err.length, 100);
+}
+
+#[test]
+fn number_range_is_configurable() {
+  let options = LexerOptions { min_number: 0, max_number: 99999, ..LexerOptions::default() };
+  assert_eq!(lex_word("99999", options.clone()), Ok("99999".to_string()));
+  assert_eq!(lex_word("100000", options.clone()), Err("Number '100000' is out of range! Numbers must be between 0 and 99999.".to_string()));
+  assert!(lex_word("-1", options).is_err());
+}
+
+#[test]
+fn sign_after_an_operand_is_an_operator() {
+  let tokens = lex_source("limits.pl0", "x -1 * (y) -2 + -3").unwrap();
+  let types: Vec<TokenType> = tokens.iter().map(|t| t.typ).collect();
+  assert_eq!(types, vec![
+    TokenType::Identsym, TokenType::Minussym, TokenType::Numbersym, TokenType::Multsym,
+    TokenType::Lparensym, TokenType::Identsym, TokenType::Rparensym, TokenType::Minussym,
+    TokenType::Numbersym, TokenType::Plussym, TokenType::Numbersym, TokenType::Eofsym,
+  ]);
+  assert_eq!(tokens[10].value, -3);
+}
+
+#[cfg(feature = "radix-literals")]
+#[test]
+fn hex_and_binary_literals() {
+  let tokens = lex_source("limits.pl0", "0x1F, 0b101, -0x80000000").unwrap();
+  assert_eq!((tokens[0].text.as_str(), tokens[0].value), ("0x1F", 31));
+  assert_eq!((tokens[2].text.as_str(), tokens[2].value), ("0b101", 5));
+  assert_eq!(tokens[4].value, i32::MIN);
+  assert!(lex_source("limits.pl0", "0x").is_err());
+  assert!(lex_source("limits.pl0", "0x100000000").is_err());
+}
+
+#[cfg(not(feature = "radix-literals"))]
+#[test]
+fn hex_literals_need_the_feature() {
+  let tokens = lex_source("limits.pl0", "0x1F").unwrap();
+  assert_eq!((tokens[0].typ, tokens[0].value), (TokenType::Numbersym, 0));
+  assert_eq!((tokens[1].typ, tokens[1].text.as_str()), (TokenType::Identsym, "x1F"));
+}
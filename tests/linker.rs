@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+use rustpl0::compiler::{compile_object, compile_program, CompileOptions};
+use rustpl0::dialect::Dialect;
+use rustpl0::lexer::{lex_with_options, LexerOptions};
+use rustpl0::linker::link;
+use rustpl0::machine::machine::{format_program, load_program, run_code};
+use rustpl0::machine::machine_types::Executable;
+use rustpl0::ast::ProcKind;
+use rustpl0::object::{kind_name, ExportValue, Object};
+use rustpl0::token::Token;
+
+const MAIN: &str = "tests/programs/modules/imports.pl0";
+const MATH: &str = "tests/programs/modules/lib/math.pl0";
+const REPORT: &str = "tests/programs/modules/lib/report.pl0";
+
+fn options() -> CompileOptions {
+  CompileOptions {
+    lexer: LexerOptions { dialect: Dialect::Extended, ..LexerOptions::default() },
+    module_path: vec![PathBuf::from("tests/programs/modules/lib")],
+    ..CompileOptions::default()
+  }
+}
+
+fn tokens(path: &str) -> Vec<Token> {
+  let source = std::fs::read(path).unwrap();
+  lex_with_options(path, &source, options().lexer).unwrap()
+}
+
+/// Compiles the file on its own and sends the object through its text form,
+/// as `rustpl0 -c` and `rustpl0 link` would.
+fn object(path: &str) -> Object {
+  let object = compile_object(&tokens(path), &options()).unwrap();
+  let parsed = Object::parse(&object.to_text()).unwrap();
+  assert_eq!(parsed, object, "{}", path);
+  parsed
+}
+
+fn run(code: Executable) -> String {
+  let mut output: Vec<u8> = Vec::new();
+  run_code(code, false, false, Some(100_000), &mut "".as_bytes(), &mut output).unwrap();
+  String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn linked_objects_run_like_the_whole_program() {
+  let expected = run(compile_program(&tokens(MAIN), &options()).unwrap());
+  let (main, math, report) = (object(MAIN), object(MATH), object(REPORT));
+
+  for objects in [
+    vec![main.clone(), math.clone(), report.clone()],
+    vec![report.clone(), main.clone(), math.clone()],
+    vec![math.clone(), report.clone(), main.clone()],
+  ] {
+    assert_eq!(run(link(&objects).unwrap()), expected);
+  }
+}
+
+#[test]
+fn linked_programs_survive_the_vm_file_format() {
+  let program = link(&[object(MAIN), object(MATH), object(REPORT)]).unwrap();
+  let reloaded = load_program(&format_program(&program)).unwrap();
+  assert_eq!(reloaded.code, program.code);
+  assert_eq!(reloaded.arrays, program.arrays);
+  assert_eq!(run(reloaded), run(program));
+}
+
+#[test]
+fn link_errors() {
+  let (main, math, report) = (object(MAIN), object(MATH), object(REPORT));
+
+  let missing = link(&[main.clone(), report.clone()]).unwrap_err();
+  assert!(missing.starts_with("Error: Unresolved symbol 'math."), "{}", missing);
+
+  let duplicate = link(&[main.clone(), math.clone(), math.clone(), report.clone()]).unwrap_err();
+  assert_eq!(duplicate, "Error: Duplicate module 'math'; it is linked more than once!");
+
+  let no_main = link(&[math.clone(), report.clone()]).unwrap_err();
+  assert_eq!(no_main, "Error: No main program to link; every object is a module!");
+
+  let two_mains = link(&[main.clone(), main.clone(), math.clone(), report.clone()]).unwrap_err();
+  assert_eq!(two_mains, "Error: More than one main program to link!");
+
+  let mut twice = math.clone();
+  let first = twice.exports.iter().find(|e| matches!(e.value, ExportValue::Proc { .. })).unwrap().clone();
+  twice.exports.push(first.clone());
+  let duplicate_symbol = link(&[main.clone(), twice, report.clone()]).unwrap_err();
+  assert_eq!(duplicate_symbol, format!("Error: Duplicate symbol 'math.{}'!", first.name));
+}
+
+// An object compiled against an older version of a module must not link
+// against a procedure whose kind or arity has changed since.
+#[test]
+fn imports_must_match_the_exported_kind_and_arity() {
+  let (main, math, report) = (object(MAIN), object(MATH), object(REPORT));
+  let import = main.imports.iter().find(|i| i.module == "math").unwrap().clone();
+
+  let mut changed = math.clone();
+  let export = changed.exports.iter_mut().find(|e| e.name == import.name).unwrap();
+  let ExportValue::Proc { arity, .. } = &mut export.value else {
+    panic!("`{}` is not a procedure", import.name);
+  };
+  *arity += 1;
+  let error = link(&[main.clone(), changed, report.clone()]).unwrap_err();
+  assert_eq!(error, format!(
+    "Error: 'math.{}' is imported as a {} of arity {} but exported as a {} of arity {}!",
+    import.name, kind_name(import.kind), import.arity, kind_name(import.kind), import.arity + 1,
+  ));
+
+  let mut stale = main.clone();
+  stale.imports.iter_mut().find(|i| i.name == import.name).unwrap().kind = match import.kind {
+    ProcKind::Function => ProcKind::Procedure,
+    ProcKind::Procedure => ProcKind::Function,
+  };
+  assert!(link(&[stale, math, report]).unwrap_err().contains("is imported as a"));
+}
+
+#[test]
+fn malformed_objects_are_rejected() {
+  assert!(Object::parse("1 0\n").unwrap_err().starts_with("Error: Not an object file!"));
+  assert!(Object::parse("pl0-object 2\nprogram\n").unwrap_err().contains("no `code` section"));
+  assert!(Object::parse("pl0-object 2\nprogram\nrelocate code 3\ncode\n2 0\n").unwrap_err().contains("outside the code"));
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use rustpl0::compile_error::{CompileError, Severity};
+use rustpl0::compiler::{analyze, CompileOptions};
+use rustpl0::dialect::Dialect;
+use rustpl0::lexer::LexerOptions;
+use rustpl0::linter::{lint, Level, Lint, LintConfig, CONFIG_FILE};
+
+fn extended() -> CompileOptions {
+  CompileOptions { lexer: LexerOptions { dialect: Dialect::Extended, ..LexerOptions::default() }, ..CompileOptions::default() }
+}
+
+fn lint_source(source: &str, config: &LintConfig) -> Result<Vec<CompileError>, CompileError> {
+  let options = extended();
+  let analysis = analyze("lint.pl0", source.as_bytes(), &options);
+  lint("lint.pl0", source.as_bytes(), &analysis, &options.lexer, config)
+}
+
+// Each `tests/lint/<name>.pl0` is linted in the extended dialect with every
+// lint warning, and the rendered warnings are compared with
+// `<name>.expected`.
+#[test]
+fn fixtures_report_expected_warnings() {
+  common::check_fixture_dir("tests/lint", "pl0", "expected", |path, source| {
+    let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+    let options = extended();
+    let analysis = analyze(&file_name, source.as_bytes(), &options);
+    match lint(&file_name, source.as_bytes(), &analysis, &options.lexer, &LintConfig::default()) {
+      Ok(warnings) => warnings.iter().map(|warning| warning.render(source)).collect(),
+      Err(err) => err.render(source),
+    }
+  });
+}
+
+#[test]
+fn configured_levels_allow_or_deny_lints() {
+  let source = "var x, unused; begin x := 1; write x end.";
+  let found = lint_source(source, &LintConfig::default()).unwrap();
+  assert_eq!(found.len(), 1);
+  assert_eq!((found[0].lint, found[0].severity), (Some(Lint::UnusedVariable), Severity::Warning));
+  assert_eq!(found[0].to_string(), "lint.pl0: At line: 1, column: 8\nWarning: Variable 'unused' is never used. [unused-variable]\n");
+
+  let config = LintConfig::parse("# Stricter than the default.\nunused-variable = deny\n\nshadowed-name=allow\n").unwrap();
+  assert_eq!(config.level(Lint::ShadowedName), Level::Allow);
+  assert_eq!(config.level(Lint::EmptyProcedure), Level::Warn);
+  assert_eq!(lint_source(source, &config).unwrap()[0].severity, Severity::Error);
+
+  let config = LintConfig::parse("unused-variable = allow").unwrap();
+  assert_eq!(lint_source(source, &config).unwrap(), []);
+
+  assert_eq!(LintConfig::parse("unused-variables = allow"), Err("Error: Unknown lint `unused-variables` on line 1!".to_string()));
+  assert_eq!(LintConfig::parse("\nunused-variable = never"), Err("Error: Unknown lint level `never` on line 2!".to_string()));
+  assert!(LintConfig::parse("unused-variable").is_err());
+}
+
+#[test]
+fn configuration_comes_from_the_nearest_file() {
+  let root = env!("CARGO_MANIFEST_DIR");
+  let config = LintConfig::for_file(&std::path::Path::new(root).join("tests/lint/configured/program.pl0")).unwrap();
+  assert_eq!(config.level(Lint::UnusedConstant), Level::Allow);
+  assert_eq!(config.level(Lint::UnusedVariable), Level::Deny);
+}
+
+// A configuration above the repository a file is in does not apply to it, so
+// what a test finds does not depend on where the checkout lives.
+#[test]
+fn configuration_is_not_looked_for_above_the_repository() {
+  let outer = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("lint-config");
+  let repository = outer.join("repository");
+  let file = repository.join("src").join("program.pl0");
+  std::fs::create_dir_all(repository.join(".git")).unwrap();
+  std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+  std::fs::write(outer.join(CONFIG_FILE), "unused-variable = deny\n").unwrap();
+  let _ = std::fs::remove_file(repository.join(CONFIG_FILE));
+  assert_eq!(LintConfig::for_file(&file).unwrap(), LintConfig::default());
+
+  std::fs::write(repository.join(CONFIG_FILE), "unused-variable = allow\n").unwrap();
+  assert_eq!(LintConfig::for_file(&file).unwrap().level(Lint::UnusedVariable), Level::Allow);
+}
+
+#[test]
+fn files_with_errors_or_unknown_allowed_lints_are_not_linted() {
+  let err = lint_source("var x; begin y := 1 end.", &LintConfig::default()).unwrap_err();
+  assert_eq!(err.message, "Undeclared identifier 'y'.");
+
+  let err = lint_source("var x; # rustpl0: allow(unused-thing)\nbegin x := 1 end.", &LintConfig::default()).unwrap_err();
+  assert_eq!((err.line, err.column, err.message.as_str()), (1, 8, "Unknown lint 'unused-thing' in allow comment."));
+}
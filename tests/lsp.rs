@@ -0,0 +1,265 @@
+use rustpl0::compiler::CompileOptions;
+use rustpl0::json::Json;
+use rustpl0::lsp::server::{read_message, run, Server};
+
+const URI: &str = "file:///work/squares.pl0";
+
+const SOURCE: &str = "\
+const limit = 3;
+var total;
+procedure add(n);
+  var i;
+  for i := 1 to n do total := total + i * limit;
+begin
+  total := 0;
+  call add(limit);
+  write total
+end.
+";
+
+fn message(text: &str) -> Json {
+  Json::parse(text).unwrap()
+}
+
+fn request(id: i64, method: &str, params: Json) -> Json {
+  Json::object([("jsonrpc", "2.0".into()), ("id", id.into()), ("method", method.into()), ("params", params)])
+}
+
+fn at(line: i64, character: i64) -> Json {
+  Json::object([
+    ("textDocument", Json::object([("uri", URI.into())])),
+    ("position", Json::object([("line", line.into()), ("character", character.into())])),
+  ])
+}
+
+/// A server that has opened `source` as `URI`, and the diagnostics it sent.
+fn open(source: &str) -> (Server, Json) {
+  let mut server = Server::new(CompileOptions::default());
+  server.handle(&message(r#"{"jsonrpc":"2.0","id":0,"method":"initialize","params":{"initializationOptions":{"dialect":"extended"}}}"#));
+  let document = Json::object([("uri", URI.into()), ("text", source.into())]);
+  let params = Json::object([("textDocument", document)]);
+  let mut sent = server.handle(&Json::object([("jsonrpc", "2.0".into()), ("method", "textDocument/didOpen".into()), ("params", params)]));
+  assert_eq!(sent.len(), 1);
+  (server, sent.remove(0))
+}
+
+fn result(server: &mut Server, request: Json) -> Json {
+  server.handle(&request).remove(0).get("result").clone()
+}
+
+fn start(location: &Json) -> (i64, i64) {
+  let start = location.get("range").get("start");
+  (start.get("line").as_i64().unwrap(), start.get("character").as_i64().unwrap())
+}
+
+#[test]
+fn reports_the_first_error_as_a_diagnostic() {
+  let (_, sent) = open(SOURCE);
+  assert_eq!(sent.get("method").as_str(), Some("textDocument/publishDiagnostics"));
+  assert_eq!(sent.get("params").get("diagnostics").as_array(), &[]);
+
+  let (_, sent) = open(&SOURCE.replace("write total", "write totl"));
+  let diagnostics = sent.get("params").get("diagnostics").as_array();
+  assert_eq!(diagnostics.len(), 1);
+  assert_eq!(diagnostics[0].get("message").as_str(), Some("Undeclared identifier 'totl'."));
+  assert_eq!(start(&diagnostics[0]), (8, 8));
+  assert_eq!(diagnostics[0].get("range").get("end").get("character").as_i64(), Some(12));
+
+  let (_, sent) = open("var x; x := .");
+  assert_eq!(sent.get("params").get("diagnostics").as_array().len(), 1);
+}
+
+#[test]
+fn finds_definitions_and_references() {
+  let (mut server, _) = open(SOURCE);
+
+  // `limit` in `call add(limit)` is the constant on the first line.
+  let definition = result(&mut server, request(1, "textDocument/definition", at(7, 12)));
+  assert_eq!(definition.get("uri").as_str(), Some(URI));
+  assert_eq!(start(&definition), (0, 6));
+
+  let references = result(&mut server, request(2, "textDocument/references", at(1, 4)));
+  let found: Vec<(i64, i64)> = references.as_array().iter().map(start).collect();
+  assert_eq!(found, [(1, 4), (4, 21), (4, 30), (6, 2), (8, 8)]);
+
+  // The parameter `n` is only visible inside `add`.
+  let references = result(&mut server, request(3, "textDocument/references", at(2, 14)));
+  assert_eq!(references.as_array().len(), 2);
+
+  assert_eq!(result(&mut server, request(4, "textDocument/definition", at(5, 0))), Json::Null);
+}
+
+#[test]
+fn hover_shows_values_and_levels() {
+  let (mut server, _) = open(SOURCE);
+  let hover = |server: &mut Server, line, character| {
+    result(server, request(1, "textDocument/hover", at(line, character))).get("contents").get("value").as_str().unwrap().to_string()
+  };
+  assert_eq!(hover(&mut server, 7, 12), "```pl0\nconst limit = 3\n```\nDeclared at level 0 (global).");
+  assert_eq!(hover(&mut server, 4, 6), "```pl0\nvar i\n```\nDeclared at level 1.");
+  assert_eq!(hover(&mut server, 7, 7), "```pl0\nprocedure add(n)\n```\nDeclared at level 0 (global).");
+}
+
+#[test]
+fn completes_keywords_of_the_dialect() {
+  let (mut server, _) = open(SOURCE);
+  let completion = result(&mut server, request(1, "textDocument/completion", at(0, 0)));
+  let labels: Vec<&str> = completion.as_array().iter().filter_map(|item| item.get("label").as_str()).collect();
+  assert!(labels.contains(&"begin"));
+  assert!(labels.contains(&"repeat"));
+
+  let mut server = Server::new(CompileOptions::default());
+  let completion = result(&mut server, request(1, "textDocument/completion", at(0, 0)));
+  let labels: Vec<&str> = completion.as_array().iter().filter_map(|item| item.get("label").as_str()).collect();
+  assert!(labels.contains(&"begin"));
+  assert!(!labels.contains(&"repeat"));
+}
+
+#[test]
+fn lists_procedures_as_document_symbols() {
+  let source = "procedure outer; procedure inner; skip; skip; function twice(x); twice := 2 * x; call outer.";
+  let (mut server, _) = open(source);
+  let params = Json::object([("textDocument", Json::object([("uri", URI.into())]))]);
+  let symbols = result(&mut server, request(1, "textDocument/documentSymbol", params));
+  let symbols = symbols.as_array();
+  assert_eq!(symbols.len(), 2);
+  assert_eq!(symbols[0].get("name").as_str(), Some("outer"));
+  assert_eq!(symbols[0].get("children").as_array()[0].get("name").as_str(), Some("inner"));
+  assert_eq!(symbols[1].get("detail").as_str(), Some("function(x)"));
+}
+
+#[test]
+fn resolves_names_imported_from_modules() {
+  let path = format!("{}/tests/programs/modules/imports.pl0", env!("CARGO_MANIFEST_DIR"));
+  let uri = format!("file://{}", path);
+  let mut server = Server::new(CompileOptions::default());
+  let init = format!(r#"{{"jsonrpc":"2.0","id":0,"method":"initialize","params":{{"initializationOptions":{{"dialect":"extended","modulePath":["{}/tests/programs/modules/lib"]}}}}}}"#, env!("CARGO_MANIFEST_DIR"));
+  server.handle(&message(&init));
+  let document = Json::object([("uri", uri.as_str().into()), ("text", std::fs::read_to_string(&path).unwrap().into())]);
+  let sent = server.handle(&Json::object([("method", "textDocument/didOpen".into()), ("params", Json::object([("textDocument", document)]))]));
+  assert_eq!(sent[0].get("params").get("diagnostics").as_array(), &[]);
+
+  // `square` in `values[i] := square(i + 1)` comes from `import math`.
+  let position = Json::object([("textDocument", Json::object([("uri", uri.as_str().into())])), ("position", Json::object([("line", 6.into()), ("character", 40.into())]))]);
+  let hover = result(&mut server, request(1, "textDocument/hover", position.clone()));
+  assert_eq!(hover.get("contents").get("value").as_str(), Some("```pl0\nfunction square (1 parameter(s))\n```\nImported from module `math`."));
+  assert_eq!(start(&result(&mut server, request(2, "textDocument/definition", position))), (0, 7));
+}
+
+#[test]
+fn serves_framed_messages_until_exit() {
+  let messages = [
+    r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+    r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+    r#"{"jsonrpc":"2.0","id":2,"method":"workspace/symbol","params":{}}"#,
+    r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+    r#"{"jsonrpc":"2.0","method":"exit"}"#,
+  ];
+  let input: String = messages.iter().map(|m| format!("Content-Length: {}\r\n\r\n{}", m.len(), m)).collect();
+  let mut output: Vec<u8> = Vec::new();
+  assert_eq!(run(CompileOptions::default(), &mut input.as_bytes(), &mut output), 0);
+
+  let mut output = output.as_slice();
+  let initialized = message(&read_message(&mut output).unwrap().unwrap());
+  assert_eq!(initialized.get("result").get("capabilities").get("hoverProvider"), &Json::Bool(true));
+  let unknown = message(&read_message(&mut output).unwrap().unwrap());
+  assert_eq!(unknown.get("error").get("code").as_i64(), Some(-32601));
+  let shutdown = message(&read_message(&mut output).unwrap().unwrap());
+  assert_eq!(shutdown.get("id").as_i64(), Some(3));
+  assert_eq!(read_message(&mut output).unwrap(), None);
+}
+
+#[test]
+fn reports_errors_in_imported_modules_at_the_import() {
+  let root = env!("CARGO_MANIFEST_DIR");
+  let uri = format!("file://{}/tests/programs/modules/main.pl0", root);
+  let mut server = Server::new(CompileOptions::default());
+  let init = format!(r#"{{"jsonrpc":"2.0","id":0,"method":"initialize","params":{{"initializationOptions":{{"dialect":"extended","modulePath":["{}/tests/programs/modules/lib"]}}}}}}"#, root);
+  server.handle(&message(&init));
+  let document = Json::object([("uri", uri.as_str().into()), ("text", "import math, broken;\ncall p.".into())]);
+  let sent = server.handle(&Json::object([("method", "textDocument/didOpen".into()), ("params", Json::object([("textDocument", document)]))]));
+  let diagnostics = sent[0].get("params").get("diagnostics").as_array();
+  assert_eq!(start(&diagnostics[0]), (0, 13));
+  let message = diagnostics[0].get("message").as_str().unwrap();
+  assert!(message.starts_with("Module 'broken' does not compile: "), "{}", message);
+  assert!(message.ends_with("broken.pl0:3:9: Undeclared identifier 'missing'."), "{}", message);
+}
+
+/// The text of a single edit that replaces the whole of `URI`.
+fn replacement(edits: &Json) -> &str {
+  let edits = edits.as_array();
+  assert_eq!(edits.len(), 1);
+  assert_eq!(start(&edits[0]), (0, 0));
+  edits[0].get("newText").as_str().unwrap()
+}
+
+#[test]
+fn renames_a_declaration_and_its_uses_keeping_comments() {
+  let source = SOURCE.replace("var total;", "var total; { the sum }").replace("write total", "write total # done");
+  let (mut server, _) = open(&source);
+  let mut params = at(6, 3);
+  if let Json::Object(members) = &mut params {
+    members.push(("newName".to_string(), "sum".into()));
+  }
+  let edit = result(&mut server, request(1, "textDocument/rename", params.clone()));
+  let renamed = replacement(edit.get("changes").get(URI));
+  assert_eq!(renamed, source.replace("total", "sum"));
+
+  // Keywords, names already in use and non-identifiers are refused.
+  for name in ["begin", "limit", "2x", "a b"] {
+    if let Json::Object(members) = &mut params {
+      members.last_mut().unwrap().1 = name.into();
+    }
+    let reply = server.handle(&request(2, "textDocument/rename", params.clone())).remove(0);
+    assert_eq!(reply.get("error").get("code").as_i64(), Some(-32803), "{}", name);
+  }
+}
+
+#[test]
+fn formats_documents_with_the_clients_tab_size() {
+  let (mut server, _) = open("var x;begin x:=1;\n  write x end.");
+  let options = Json::object([("tabSize", 4.into()), ("insertSpaces", true.into())]);
+  let params = Json::object([("textDocument", Json::object([("uri", URI.into())])), ("options", options)]);
+  let edits = result(&mut server, request(1, "textDocument/formatting", params.clone()));
+  assert_eq!(replacement(&edits), "var x;\nbegin\n    x := 1;\n    write x\nend.\n");
+
+  let (mut server, _) = open("var x;\nbegin\n    x := 1;\n    write x\nend.\n");
+  assert_eq!(result(&mut server, request(1, "textDocument/formatting", params.clone())).as_array(), &[]);
+  let (mut server, _) = open("var x; x := .");
+  assert_eq!(result(&mut server, request(1, "textDocument/formatting", params)), Json::Null);
+}
+
+#[test]
+fn publishes_lint_warnings_with_their_ids() {
+  let (_, sent) = open(&SOURCE.replace("var total;", "var total, spare;"));
+  let diagnostics = sent.get("params").get("diagnostics").as_array();
+  assert_eq!(diagnostics.len(), 1);
+  assert_eq!(diagnostics[0].get("severity").as_i64(), Some(2));
+  assert_eq!(diagnostics[0].get("code").as_str(), Some("unused-variable"));
+  assert_eq!(start(&diagnostics[0]), (1, 11));
+
+  // Errors stop the file from being linted.
+  let (_, sent) = open(&SOURCE.replace("var total;", "var total, spare;").replace("write total", "write totl"));
+  let diagnostics = sent.get("params").get("diagnostics").as_array();
+  assert_eq!(diagnostics.len(), 1);
+  assert_eq!(diagnostics[0].get("severity").as_i64(), Some(1));
+  assert_eq!(diagnostics[0].get("code"), &Json::Null);
+}
+
+#[test]
+fn positions_count_utf16_code_units() {
+  // The comment is 16 bytes but 11 UTF-16 code units long, so `total`
+  // starts at character 13.
+  let source = SOURCE.replace("  total := 0;", "  { Σ → 😀 } total := 0;");
+  let (mut server, _) = open(&source);
+  let definition = result(&mut server, request(1, "textDocument/definition", at(6, 15)));
+  assert_eq!(start(&definition), (1, 4));
+  let references = result(&mut server, request(2, "textDocument/references", at(1, 4)));
+  let found: Vec<(i64, i64)> = references.as_array().iter().map(start).collect();
+  assert_eq!(found, [(1, 4), (4, 21), (4, 30), (6, 13), (8, 8)]);
+
+  let (_, sent) = open(&source.replace("} total", "} totl"));
+  let diagnostics = sent.get("params").get("diagnostics").as_array();
+  assert_eq!(start(&diagnostics[0]), (6, 13));
+  assert_eq!(diagnostics[0].get("range").get("end").get("character").as_i64(), Some(17));
+}
@@ -0,0 +1,125 @@
+#![cfg(all(target_arch = "x86_64", target_os = "linux"))]
+
+mod common;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use rustpl0::compiler::{compile_program, CompileOptions};
+use rustpl0::dialect::Dialect;
+use rustpl0::lexer::{lex_with_options, LexerOptions};
+use rustpl0::machine::machine::run_code;
+use rustpl0::machine::machine_types::Executable;
+use rustpl0::native;
+use rustpl0::opt::OptLevel;
+
+// Every program that compiles in `tests/programs/` and `tests/native/` is
+// built into a native executable, which must print exactly what the VM
+// prints after its banner, and stop with the same error and exit status.
+// Where a `<name>.stdout` file exists, the output must also match it.
+// Skipped when the system has no `as` or `ld` to build with.
+
+fn have_toolchain() -> bool {
+  let found = ["as", "ld"].iter().all(|tool| Command::new(tool).arg("--version").stdout(Stdio::null()).status().is_ok());
+  if !found {
+    eprintln!("Skipping native builds: `as` or `ld` was not found.");
+  }
+  found
+}
+
+/// What a program printed, what it printed on standard error and its exit
+/// status.
+type Run = (String, String, i32);
+
+fn run_vm(code: Executable, input: &str) -> Run {
+  let mut output: Vec<u8> = Vec::new();
+  let result = run_code(code, false, false, Some(100_000), &mut input.as_bytes(), &mut output);
+  let output = String::from_utf8_lossy(&output);
+  let (_, printed) = output.split_once("\n\n\n").expect("the VM's banner");
+  match result {
+    Ok(()) => (printed.to_string(), String::new(), 0),
+    Err(msg) => (printed.to_string(), format!("{}\n", msg), 101),
+  }
+}
+
+fn run_native(path: &Path, input: &str) -> Run {
+  let mut child = Command::new(path).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+  // A program may stop before reading all its input.
+  let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+  let output = child.wait_with_output().unwrap();
+  let printed = String::from_utf8_lossy(&output.stdout).to_string();
+  (printed, String::from_utf8_lossy(&output.stderr).to_string(), output.status.code().unwrap_or(-1))
+}
+
+fn check_programs(dir: &str, options: CompileOptions) {
+  let build_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("native").join(dir).join(format!("{:?}", options.opt_level));
+  std::fs::create_dir_all(&build_dir).unwrap();
+  let mut failures: Vec<String> = Vec::new();
+
+  for source_path in common::fixtures(dir, "pl0") {
+    let source = std::fs::read_to_string(&source_path).unwrap();
+    let input = common::sibling(&source_path, "in").unwrap_or_default();
+    let file_name = source_path.file_name().unwrap().to_string_lossy();
+    let compiled = lex_with_options(&file_name, source.as_bytes(), options.lexer.clone())
+      .map_err(|err| err.into())
+      .and_then(|tokens| compile_program(&tokens, &options));
+    let Ok(code) = compiled else {
+      continue;
+    };
+
+    let executable = build_dir.join(source_path.file_stem().unwrap());
+    if let Err(msg) = native::build(&code, &executable) {
+      failures.push(format!("{}: {}", source_path.display(), msg));
+      continue;
+    }
+    let expected = run_vm(code, &input);
+    let actual = run_native(&executable, &input);
+    if actual != expected {
+      failures.push(format!("{} at {:?}:\n  VM:     {:?}\n  native: {:?}", source_path.display(), options.opt_level, expected, actual));
+    }
+    if let Some(stdout) = common::sibling(&source_path, "stdout") {
+      if actual.0 != stdout {
+        failures.push(format!("{} at {:?}:\n  expected: {:?}\n  native:   {:?}", source_path.display(), options.opt_level, stdout, actual.0));
+      }
+    }
+  }
+
+  assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+fn options(dialect: Dialect, opt_level: OptLevel) -> CompileOptions {
+  CompileOptions { lexer: LexerOptions { dialect, ..LexerOptions::default() }, opt_level, ..CompileOptions::default() }
+}
+
+#[test]
+fn native_programs_print_what_the_vm_prints() {
+  if !have_toolchain() {
+    return;
+  }
+  for opt_level in [OptLevel::O0, OptLevel::O2] {
+    check_programs("tests/programs/standard", options(Dialect::Standard, opt_level));
+    check_programs("tests/programs/extended", options(Dialect::Extended, opt_level));
+    let modules = vec![PathBuf::from("tests/programs/modules/lib")];
+    check_programs("tests/programs/modules", CompileOptions { module_path: modules, ..options(Dialect::Extended, opt_level) });
+    check_programs("tests/native", options(Dialect::Extended, opt_level));
+  }
+}
+
+#[test]
+fn default_output_never_overwrites_the_source() {
+  assert_eq!(native::default_output(Path::new("dir/prog.pl0"), false), Path::new("dir/prog"));
+  assert_eq!(native::default_output(Path::new("dir/prog.pl0"), true), Path::new("dir/prog.s"));
+  assert_eq!(native::default_output(Path::new("dir/prog"), false), Path::new("dir/prog.out"));
+  assert_eq!(native::default_output(Path::new("dir/prog.s"), true), Path::new("dir/prog.s.out"));
+}
+
+#[test]
+fn assembly_labels_every_instruction() {
+  let tokens = lex_with_options("t.pl0", b"var x; begin x := 2; write x * 3 end.", LexerOptions::default()).unwrap();
+  let code = compile_program(&tokens, &CompileOptions::default()).unwrap();
+  let assembly = native::assemble(&code);
+  for address in 0..=code.code.len() {
+    assert!(assembly.contains(&format!(".Lpc{}:", address)), "{}", assembly);
+  }
+  assert!(assembly.contains("_start:"));
+}
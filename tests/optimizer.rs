@@ -0,0 +1,135 @@
+mod common;
+
+use rustpl0::compiler::{compile_ir, compile_program, CompileOptions};
+use rustpl0::dialect::Dialect;
+use rustpl0::lexer::{lex_with_options, LexerOptions};
+use rustpl0::machine::machine::run_code;
+use rustpl0::opt::{OptLevel, Pass};
+use rustpl0::token::Token;
+
+const LEVELS: [OptLevel; 3] = [OptLevel::O0, OptLevel::O1, OptLevel::O2];
+
+fn options(dialect: Dialect, opt_level: OptLevel) -> CompileOptions {
+  CompileOptions { lexer: LexerOptions { dialect, ..LexerOptions::default() }, opt_level, ..CompileOptions::default() }
+}
+
+fn lex(filename: &str, source: &str, dialect: Dialect) -> Vec<Token> {
+  lex_with_options(filename, source.as_bytes(), options(dialect, OptLevel::O0).lexer).unwrap()
+}
+
+/// Compiles `source`, returning its instruction count and what running it
+/// printed, or `None` if it does not compile.
+fn compile_and_run(source: &str, dialect: Dialect, opt_level: OptLevel) -> Option<(usize, String)> {
+  let code = compile_program(&lex("test.pl0", source, dialect), &options(dialect, opt_level)).ok()?;
+  let length = code.code.len();
+  let mut output: Vec<u8> = Vec::new();
+  let result = run_code(code, false, false, Some(100_000), &mut "7\n".as_bytes(), &mut output);
+  let mut printed = String::from_utf8(output).unwrap();
+  if let Err(msg) = result {
+    printed += &msg;
+  }
+  Some((length, printed))
+}
+
+// Each `tests/opt/<name>.pl0` is lowered in the extended dialect at -O2 and
+// its IR compared with `<name>.ir`.
+#[test]
+fn fixtures_optimize_as_expected() {
+  common::check_fixture_dir("tests/opt", "pl0", "ir", |path, source| {
+    let tokens = lex(&path.display().to_string(), source, Dialect::Extended);
+    compile_ir(&tokens, &options(Dialect::Extended, OptLevel::O2)).unwrap().to_string()
+  });
+}
+
+// The number of VM instructions each sample program compiles to at -O0, -O1
+// and -O2 is kept in `tests/opt/instruction_counts`. Every level must print
+// the same as -O0, in no more instructions than the level below it.
+#[test]
+fn instruction_counts_shrink_with_each_level() {
+  let mut programs: Vec<(String, Dialect)> = Vec::new();
+  for (dir, dialect) in [("tests/programs/standard", Dialect::Standard), ("tests/programs/extended", Dialect::Extended), ("tests/opt", Dialect::Extended)] {
+    programs.extend(common::fixtures(dir, "pl0").into_iter().map(|path| (path.display().to_string(), dialect)));
+  }
+
+  let mut table = format!("{:<48} {:>5} {:>5} {:>5}\n", "program", "-O0", "-O1", "-O2");
+  for (path, dialect) in programs {
+    let source = std::fs::read_to_string(&path).unwrap();
+    let Some(compiled) = LEVELS.iter().map(|level| compile_and_run(&source, dialect, *level)).collect::<Option<Vec<_>>>() else {
+      continue;
+    };
+    for (level, (_, printed)) in LEVELS.iter().zip(&compiled).skip(1) {
+      assert_eq!(*printed, compiled[0].1, "{} prints something else at {:?}", path, level);
+    }
+    assert!(compiled.windows(2).all(|pair| pair[1].0 <= pair[0].0), "{} grows when optimized", path);
+    let name = path.rsplit_once("tests/").map_or(path.as_str(), |(_, name)| name);
+    table += &format!("{:<48} {:>5} {:>5} {:>5}\n", name, compiled[0].0, compiled[1].0, compiled[2].0);
+  }
+  let expected = std::path::Path::new("tests/opt/instruction_counts");
+  if let Some(report) = common::check_golden(expected, &table, common::first_line_divergence) {
+    panic!("\n{}", report);
+  }
+}
+
+#[test]
+fn calls_and_traps_are_kept() {
+  let source = std::fs::read_to_string("tests/opt/traps.pl0").unwrap();
+  let (_, printed) = compile_and_run(&source, Dialect::Extended, OptLevel::O2).unwrap();
+  assert!(printed.ends_with("OUTPUT: 6\nError: Division by zero!"), "{}", printed);
+}
+
+#[test]
+fn passes_are_listed_in_order() {
+  assert_eq!(OptLevel::O0.passes(), []);
+  let ids: Vec<&str> = OptLevel::O2.passes().iter().map(|pass| pass.id()).collect();
+  assert_eq!(ids, ["tail-calls", "inline", "constant-propagation", "copy-propagation", "dead-stores", "dead-code", "simplify-cfg", "pack-frame"]);
+  assert!(OptLevel::O1.passes().iter().all(|pass| OptLevel::O2.passes().contains(pass)));
+  assert_eq!(Pass::from_id("dead-stores"), Some(Pass::DeadStores));
+}
+
+#[test]
+fn tail_recursion_runs_in_one_record() {
+  let source = "var total;
+procedure count(n);
+  begin
+    if n > 0 then begin
+      total := total + 1;
+      call count(n - 1)
+    end
+  end;
+begin
+  total := 0;
+  call count(3000);
+  write total
+end.";
+  let (_, printed) = compile_and_run(source, Dialect::Extended, OptLevel::O0).unwrap();
+  assert!(printed.contains("Error: Stack overflow!"), "{}", printed);
+  let (_, printed) = compile_and_run(source, Dialect::Extended, OptLevel::O2).unwrap();
+  assert!(printed.ends_with("OUTPUT: 3000\n"), "{}", printed);
+}
+
+// Inlining a procedure at many call sites must not give the caller a word
+// per site: at -O2 a function's record holds its own variables and at most
+// one copy of each other procedure's parameters, result and variables.
+#[test]
+fn inlining_does_not_grow_frames_with_each_call_site() {
+  for (dir, dialect) in [("tests/programs/standard", Dialect::Standard), ("tests/programs/extended", Dialect::Extended), ("tests/opt", Dialect::Extended)] {
+    for path in common::fixtures(dir, "pl0") {
+      let source = std::fs::read_to_string(&path).unwrap();
+      let tokens = lex("test.pl0", &source, dialect);
+      let (Ok(lowered), Ok(optimized)) = (compile_ir(&tokens, &options(dialect, OptLevel::O0)), compile_ir(&tokens, &options(dialect, OptLevel::O2))) else {
+        continue;
+      };
+      for function in &optimized.functions {
+        let others: i32 = lowered.functions.iter().filter(|other| other.name != function.name)
+          .map(|other| other.params.len() as i32 + 1 + other.locals)
+          .sum();
+        assert!(function.locals <= function.declared + others, "{}: {} has {} locals", path.display(), function.name, function.locals);
+      }
+    }
+  }
+  let source = std::fs::read_to_string("tests/opt/frames.pl0").unwrap();
+  let unit = compile_ir(&lex("frames.pl0", &source, Dialect::Extended), &options(Dialect::Extended, OptLevel::O2)).unwrap();
+  assert_eq!(unit.functions.len(), 1);
+  // `max` needs its parameters, result and `m`, and `swap` reuses them.
+  assert!(unit.functions[0].locals <= 3 + 4, "{}", unit);
+}
@@ -0,0 +1,43 @@
+use std::io::Cursor;
+use rustpl0::compiler::CompileOptions;
+use rustpl0::dialect::Dialect;
+use rustpl0::lexer::LexerOptions;
+use rustpl0::repl;
+
+fn session(input: &str) -> String {
+  let options = CompileOptions { lexer: LexerOptions { dialect: Dialect::Extended, ..LexerOptions::default() }, ..CompileOptions::default() };
+  let mut output: Vec<u8> = Vec::new();
+  repl::run(options, &mut Cursor::new(input.as_bytes().to_vec()), &mut output);
+  String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn variables_keep_their_values_between_inputs() {
+  let output = session("var x;\nx := 4;\nvar a[2]\nprocedure double; x := x * 2;\ncall double\na[1] := x + 1\na[1] - x\nread x\n7\nx\n:stack\n");
+  assert_eq!(output, "rustpl0 REPL. Type :help for help.\n\
+    pl0> pl0> pl0> pl0> pl0> pl0> pl0> OUTPUT: 1\n\
+    pl0> INPUT > pl0> OUTPUT: 7\n\
+    pl0> x = 7\na = [0, 9]\nS[0]: 0 S[1]: 0 S[2]: 0 S[3]: 7 S[4]: 0 S[5]: 9 \n\
+    pl0> \n");
+}
+
+#[test]
+fn unfinished_input_continues_on_the_next_line() {
+  let output = session("var n;\nfor n := 1 to 3 do\nbegin\n  write n\nend\nwhile n < 5 do\n  n := n +\n\nn := m\n");
+  assert_eq!(output, "rustpl0 REPL. Type :help for help.\n\
+    pl0> pl0> ...> ...> ...> OUTPUT: 1\nOUTPUT: 2\nOUTPUT: 3\n\
+    pl0> ...> ...> <repl>: At line: 3, column: 1\nError: Expected an identifier, a number or '(', but found 'end'.\n\
+    pl0> <repl>: At line: 1, column: 6\nError: Undeclared identifier 'm'.\n\tn := m\n\t     ^\n\
+    pl0> \n");
+}
+
+#[test]
+fn commands_show_each_stage() {
+  let output = session(":tokens x := 10 { c }\n:asm\n:ast 1\n:frobnicate\n:quit\nx\n");
+  assert_eq!(output, "rustpl0 REPL. Type :help for help.\n\
+    pl0> identsym        1:1    x\nbecomessym      1:3    :=\nnumbersym       1:6    10\n\
+    pl0>  ADDR    OP     M\n    0   INC     3\n    1   HLT     0\n\
+    pl0> Number 1 [1:1]\n\
+    pl0> Error: Unknown command `:frobnicate`! Type :help for help.\n\
+    pl0> ");
+}
@@ -0,0 +1,120 @@
+use proptest::prelude::*;
+use rustpl0::compiler::compile_source;
+use rustpl0::dialect::Dialect;
+use rustpl0::lexer::{lex_bytes, lex_source};
+use rustpl0::machine::machine::{run_code, run_program};
+use rustpl0::reserved_types::KEYWORDS;
+use rustpl0::token::{tokens_to_source, Token};
+
+// Identifiers avoid the words reserved in any dialect.
+fn ident() -> impl Strategy<Value = String> {
+  "[a-z][a-z0-9]{0,7}".prop_filter("reserved word", |s| !KEYWORDS.iter().any(|(word, _, _)| word == s))
+}
+
+fn number() -> impl Strategy<Value = String> {
+  (0..=99999i32).prop_map(|n| n.to_string())
+}
+
+/// The names a generated program declares: its variables, one constant and
+/// one procedure. Statements use nothing else, so every program compiles.
+#[derive(Debug, Clone)]
+struct Names {
+  vars: Vec<String>,
+  constant: String,
+  procedure: String,
+}
+
+fn names() -> impl Strategy<Value = Names> {
+  prop::collection::btree_set(ident(), 3..6).prop_map(|names| names.into_iter().collect::<Vec<String>>()).prop_shuffle().prop_map(|mut names| {
+    let procedure = names.pop().unwrap();
+    let constant = names.pop().unwrap();
+    Names { vars: names, constant, procedure }
+  })
+}
+
+fn expr(names: &Names) -> impl Strategy<Value = String> {
+  let readable: Vec<String> = names.vars.iter().cloned().chain(std::iter::once(names.constant.clone())).collect();
+  let factor = prop_oneof![
+    prop::sample::select(readable),
+    (prop_oneof![Just(""), Just("+"), Just("-")], number()).prop_map(|(sign, n)| format!("{}{}", sign, n)),
+  ];
+  factor.prop_recursive(3, 16, 2, |inner| prop_oneof![
+    (inner.clone(), prop_oneof![Just("+"), Just("-"), Just("*"), Just("/")], inner.clone())
+      .prop_map(|(l, op, r)| format!("{} {} {}", l, op, r)),
+    inner.prop_map(|e| format!("({})", e)),
+  ])
+}
+
+fn condition(names: &Names) -> impl Strategy<Value = String> {
+  prop_oneof![
+    expr(names).prop_map(|e| format!("odd {}", e)),
+    (expr(names), prop_oneof![Just("="), Just("<>"), Just("<"), Just("<="), Just(">"), Just(">=")], expr(names))
+      .prop_map(|(l, op, r)| format!("{} {} {}", l, op, r)),
+  ]
+}
+
+fn stmt(names: &Names) -> impl Strategy<Value = String> {
+  let var = prop::sample::select(names.vars.clone());
+  let simple = prop_oneof![
+    (var.clone(), expr(names)).prop_map(|(i, e)| format!("{} := {}", i, e)),
+    Just(format!("call {}", names.procedure)),
+    var.prop_map(|i| format!("read {}", i)),
+    expr(names).prop_map(|e| format!("write {}", e)),
+    Just("skip".to_string()),
+  ];
+  let names = names.clone();
+  simple.prop_recursive(3, 24, 4, move |inner| prop_oneof![
+    prop::collection::vec(inner.clone(), 1..4).prop_map(|s| format!("begin\n{}\nend", s.join(";\n"))),
+    (condition(&names), inner.clone(), inner.clone()).prop_map(|(c, t, e)| format!("if {} then {} else {}", c, t, e)),
+    (condition(&names), inner).prop_map(|(c, s)| format!("while {} do {}", c, s)),
+  ])
+}
+
+fn program() -> impl Strategy<Value = String> {
+  names().prop_flat_map(|names| (number(), stmt(&names), stmt(&names), Just(names))).prop_map(|(value, body, main, names)| {
+    format!(
+      "# generated\nconst {} = {};\nvar {};\nprocedure {};\n{};\n{}.\n",
+      names.constant, value, names.vars.join(", "), names.procedure, body, main,
+    )
+  })
+}
+
+fn summary(tokens: &[Token]) -> Vec<(String, String, i32)> {
+  tokens.iter().map(|t| (t.ttyp2str(), t.text.clone(), t.value)).collect()
+}
+
+fn vm_instruction() -> impl Strategy<Value = String> {
+  (-2..33i32, prop_oneof![-8..8i32, any::<i32>()]).prop_map(|(op, m)| format!("{} {}", op, m))
+}
+
+proptest! {
+  #[test]
+  fn lexer_never_panics_on_arbitrary_bytes(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+    let _ = lex_bytes("arbitrary.pl0", &bytes);
+  }
+
+  #[test]
+  fn valid_programs_lex_and_round_trip(source in program()) {
+    let tokens = lex_source("generated.pl0", &source).map_err(|e| TestCaseError::fail(e.to_string()))?;
+    let printed = tokens_to_source(&tokens);
+    let relexed = lex_source("printed.pl0", &printed).map_err(|e| TestCaseError::fail(e.to_string()))?;
+    prop_assert_eq!(summary(&tokens), summary(&relexed));
+  }
+
+  #[test]
+  fn generated_programs_compile_and_run(source in program()) {
+    let code = compile_source("generated.pl0", &source, Dialect::Standard).map_err(|e| TestCaseError::fail(e.to_string()))?;
+    let _ = run_code(code, false, false, Some(10_000), &mut &b"7\n"[..], &mut std::io::sink());
+  }
+
+  #[test]
+  fn vm_never_panics_on_arbitrary_programs(lines in prop::collection::vec(vm_instruction(), 0..64)) {
+    let program = lines.join("\n");
+    let _ = run_program(&program, false, false, Some(10_000), &mut &b"7\n"[..], &mut std::io::sink());
+  }
+
+  #[test]
+  fn vm_loader_never_panics_on_arbitrary_text(text in "[0-9a-z \\-\\n]{0,128}") {
+    let _ = run_program(&text, false, true, Some(1_000), &mut &b""[..], &mut std::io::sink());
+  }
+}
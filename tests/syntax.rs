@@ -0,0 +1,52 @@
+mod common;
+
+use rustpl0::dialect::Dialect;
+use rustpl0::lexer::{lex_with_options, LexerOptions};
+use rustpl0::syntax::{self, SyntaxKind};
+
+fn tree(source: &[u8]) -> syntax::SyntaxNode {
+  let options = LexerOptions { dialect: Dialect::Extended, keep_trivia: true, ..LexerOptions::default() };
+  syntax::parse(&lex_with_options("syntax.pl0", source, options).unwrap())
+}
+
+// Every fixture that lexes, whether or not it parses, comes back byte for byte.
+#[test]
+fn trees_reproduce_their_source_exactly() {
+  let dirs = ["tests/lexer", "tests/lexer/extended", "tests/programs/standard", "tests/programs/extended", "tests/programs/modules", "tests/format"];
+  let mut checked = 0;
+  for dir in dirs {
+    for path in common::fixtures(dir, "pl0") {
+      let source = std::fs::read(&path).unwrap();
+      let options = LexerOptions { dialect: Dialect::Extended, keep_trivia: true, ..LexerOptions::default() };
+      let Ok(tokens) = lex_with_options(&path.display().to_string(), &source, options) else {
+        continue;
+      };
+      assert!(syntax::parse(&tokens).to_source() == source, "{} does not round-trip", path.display());
+      checked += 1;
+    }
+  }
+  assert!(checked > 20);
+
+  for source in ["", "  \r\n\t", "x{ a\r\n b }y\n\n// end", "begin write \"caf\u{e9}\" end.\n"] {
+    assert_eq!(tree(source.as_bytes()).to_source(), source.as_bytes());
+  }
+}
+
+#[test]
+fn trivia_on_the_same_line_trails_the_token_before() {
+  let program = tree(b"var x; { lead }\nx := 1 # one\n.");
+  let tokens = program.tokens();
+  assert_eq!(tokens[2].text, ";");
+  assert_eq!(tokens[2].full_text(), "; { lead }\n");
+  assert_eq!(tokens[3].full_text(), "x ");
+  assert_eq!(tokens[5].full_text(), "1 # one\n");
+  assert!(tokens[6].leading.is_empty());
+}
+
+#[test]
+fn renamed_tokens_keep_their_trivia() {
+  let mut program = tree(b"var x; (* the counter *)\nbegin\n  x := x + 1 { step }\nend.");
+  assert_eq!(program.nodes().next().map(|node| node.kind), Some(SyntaxKind::Block));
+  assert_eq!(program.replace_tokens(&[(1, 5), (3, 3), (3, 8)], "count"), 3);
+  assert_eq!(program.text(), "var count; (* the counter *)\nbegin\n  count := count + 1 { step }\nend.");
+}
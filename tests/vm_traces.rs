@@ -0,0 +1,32 @@
+mod common;
+
+use rustpl0::machine::machine::run_program;
+
+// Each `tests/vm/<name>.vm` program is run with tracing on and compared with
+// `<name>.trace`. An optional `<name>.in` supplies the input read by CHI.
+//
+// The traces are snapshots of this VM's own output, blessed after checking
+// them by hand, not traces from the reference C implementation. They catch
+// changes to the VM's behaviour, not disagreements with the original.
+#[test]
+fn vm_traces_match_snapshots() {
+  let mut failures: Vec<String> = Vec::new();
+
+  for program_path in common::fixtures("tests/vm", "vm") {
+    let program = std::fs::read_to_string(&program_path).unwrap();
+    let input = common::sibling(&program_path, "in").unwrap_or_default();
+    let mut output: Vec<u8> = Vec::new();
+    if let Err(msg) = run_program(&program, false, true, None, &mut input.as_bytes(), &mut output) {
+      failures.push(format!("{}: {}", program_path.display(), msg));
+      continue;
+    }
+    let actual = String::from_utf8(output).unwrap();
+
+    let trace_path = program_path.with_extension("trace");
+    if let Some(report) = common::check_golden(&trace_path, &actual, common::first_divergence) {
+      failures.push(report);
+    }
+  }
+
+  assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}